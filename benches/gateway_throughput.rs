@@ -0,0 +1,201 @@
+//! Concurrent GET /movies/{id} throughput through the axum gateway.
+//!
+//! A stub gRPC server answers `GetMovie` after a fixed delay, standing in for
+//! the network and storage round trip. With shared state behind a lock the
+//! requests would queue behind each other; without it, throughput should
+//! scale with the concurrency level until the runtime saturates.
+//!
+//! Run with `cargo bench --bench gateway_throughput`.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{body::Body, http::Request as HttpRequest, Router};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prometheus_client::registry::Registry;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::transport::{Channel, Server};
+use tonic::{Request, Response, Status};
+use tonic_health::pb::health_client::HealthClient;
+use tower::ServiceExt;
+
+use movie_tonic::api_keys::{ApiKeys, MemoryApiKeyStore};
+use movie_tonic::gateway::{self, AppState, Metrics, MovieService};
+use movie_tonic::movie::movie_service_client::MovieServiceClient;
+use movie_tonic::movie::movie_service_server::{self, MovieServiceServer};
+use movie_tonic::movie::{
+    BatchCreateMoviesRequest, BatchCreateMoviesResponse, BatchDeleteMoviesRequest,
+    BatchDeleteMoviesResponse, BatchGetMoviesRequest, BatchGetMoviesResponse, CreateMovieRequest,
+    CreateMovieResponse, DeleteMovieRequest, DeleteMovieResponse, Movie, MovieEvent,
+    ReadMovieRequest, ReadMovieResponse, ReadMoviesRequest, ReadMoviesResponse,
+    SearchMoviesRequest, SearchMoviesResponse, UpdateMovieRequest, UpdateMovieResponse,
+    WatchMoviesRequest,
+};
+use movie_tonic::rate_limit::{RateLimitConfig, RateLimiter};
+use movie_tonic::upstream::Upstream;
+
+const BACKEND_LATENCY: Duration = Duration::from_millis(1);
+const CONCURRENCY: [usize; 3] = [1, 8, 64];
+
+struct StubMovieService;
+
+#[tonic::async_trait]
+impl movie_service_server::MovieService for StubMovieService {
+    type WatchMoviesStream = ReceiverStream<Result<MovieEvent, Status>>;
+
+    async fn create_movie(
+        &self,
+        _request: Request<CreateMovieRequest>,
+    ) -> Result<Response<CreateMovieResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn get_movie(
+        &self,
+        request: Request<ReadMovieRequest>,
+    ) -> Result<Response<ReadMovieResponse>, Status> {
+        tokio::time::sleep(BACKEND_LATENCY).await;
+
+        let id = request.into_inner().id;
+        Ok(Response::new(ReadMovieResponse {
+            movie: Some(Movie {
+                id,
+                title: "Bench".to_string(),
+                genre: "Drama".to_string(),
+                genres: vec!["Drama".to_string()],
+                version: 1,
+                ..Default::default()
+            }),
+        }))
+    }
+
+    async fn get_movies(
+        &self,
+        _request: Request<ReadMoviesRequest>,
+    ) -> Result<Response<ReadMoviesResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn update_movie(
+        &self,
+        _request: Request<UpdateMovieRequest>,
+    ) -> Result<Response<UpdateMovieResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn delete_movie(
+        &self,
+        _request: Request<DeleteMovieRequest>,
+    ) -> Result<Response<DeleteMovieResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn search_movies(
+        &self,
+        _request: Request<SearchMoviesRequest>,
+    ) -> Result<Response<SearchMoviesResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn watch_movies(
+        &self,
+        _request: Request<WatchMoviesRequest>,
+    ) -> Result<Response<Self::WatchMoviesStream>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn batch_get_movies(
+        &self,
+        _request: Request<BatchGetMoviesRequest>,
+    ) -> Result<Response<BatchGetMoviesResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn batch_create_movies(
+        &self,
+        _request: Request<BatchCreateMoviesRequest>,
+    ) -> Result<Response<BatchCreateMoviesResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+
+    async fn batch_delete_movies(
+        &self,
+        _request: Request<BatchDeleteMoviesRequest>,
+    ) -> Result<Response<BatchDeleteMoviesResponse>, Status> {
+        Err(Status::unimplemented("not used by this benchmark"))
+    }
+}
+
+async fn start_gateway() -> Router {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(MovieServiceServer::new(StubMovieService))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let upstream = Upstream::from(channel);
+
+    let mut registry = Registry::default();
+    let metrics = Metrics::new();
+    metrics.register(&mut registry);
+
+    gateway::router(AppState {
+        registry: Arc::new(registry),
+        movie_service: MovieService::new(MovieServiceClient::new(upstream.clone()), metrics),
+        upstream_health: HealthClient::new(upstream),
+        auth: None,
+        api_keys: ApiKeys::new(Arc::new(MemoryApiKeyStore::default())),
+        service_token: None,
+        rate_limiter: RateLimiter::new(RateLimitConfig::default()),
+        ready: Arc::new(AtomicBool::new(true)),
+    })
+}
+
+async fn get_movies_concurrently(router: &Router, concurrency: usize) {
+    let requests = (0..concurrency).map(|i| {
+        let router = router.clone();
+        tokio::spawn(async move {
+            let request = HttpRequest::get(format!("/movies/bench-{}", i))
+                .body(Body::empty())
+                .unwrap();
+            let response = router.oneshot(request).await.unwrap();
+            assert!(response.status().is_success());
+        })
+    });
+
+    for handle in requests.collect::<Vec<_>>() {
+        handle.await.unwrap();
+    }
+}
+
+fn gateway_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let router = runtime.block_on(start_gateway());
+
+    let mut group = c.benchmark_group("gateway_get_movie");
+    for concurrency in CONCURRENCY {
+        group.throughput(Throughput::Elements(concurrency as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&runtime)
+                    .iter(|| get_movies_concurrently(&router, concurrency));
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, gateway_throughput);
+criterion_main!(benches);
@@ -0,0 +1,132 @@
+//! Mixed read/write workloads against `MovieServiceImpl` and the in-memory
+//! store, bypassing the network.
+//!
+//! Each iteration spawns `TASKS` concurrent tasks that issue `OPS_PER_TASK`
+//! requests drawn from the workload's mix of `GetMovie`, `GetMovies` and
+//! masked `UpdateMovie` calls.
+//!
+//! Run with `cargo bench --bench service_mixed`.
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prost_types::FieldMask;
+use tokio::runtime::Runtime;
+use tonic::Request;
+
+use movie_tonic::auth::Identity;
+use movie_tonic::movie::movie_service_server::MovieService;
+use movie_tonic::movie::{
+    CreateMovieRequest, Movie, ReadMovieRequest, ReadMoviesRequest, UpdateMovieRequest,
+};
+use movie_tonic::service::MovieServiceImpl;
+use movie_tonic::storage::MovieStore;
+use movie_tonic::watch::{self, ChangeFeed};
+
+const MOVIES: usize = 1_000;
+const TASKS: usize = 8;
+const OPS_PER_TASK: usize = 64;
+const PAGE_SIZE: i32 = 20;
+
+/// Number of operations out of every ten that are writes. Of the reads, every
+/// tenth operation fetches a `GetMovies` page and the rest are `GetMovie`.
+const WORKLOADS: [(&str, usize); 3] = [("read_only", 0), ("read_heavy", 1), ("balanced", 5)];
+
+fn movie_id(n: usize) -> String {
+    format!("movie-{}", n % MOVIES)
+}
+
+/// Marks a request as coming from an authenticated admin, as the server's
+/// auth interceptor would, since the benchmark calls the service directly.
+fn as_admin<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request.extensions_mut().insert(Identity::anonymous());
+    request
+}
+
+async fn start_service() -> Arc<MovieServiceImpl> {
+    let service = MovieServiceImpl::new(
+        Arc::new(MovieStore::default()),
+        Arc::new(ChangeFeed::new(watch::DEFAULT_HISTORY)),
+    )
+    .await
+    .unwrap();
+
+    for n in 0..MOVIES {
+        service
+            .create_movie(as_admin(CreateMovieRequest {
+                movie: Some(Movie {
+                    id: movie_id(n),
+                    title: format!("Movie {}", n),
+                    genres: vec!["Drama".to_string()],
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap();
+    }
+
+    Arc::new(service)
+}
+
+async fn run_task(service: Arc<MovieServiceImpl>, task: usize, writes: usize) {
+    for op in 0..OPS_PER_TASK {
+        let n = task * OPS_PER_TASK + op;
+        if op % 10 < writes {
+            service
+                .update_movie(as_admin(UpdateMovieRequest {
+                    movie: Some(Movie {
+                        id: movie_id(n),
+                        title: format!("Movie {} (op {})", n, op),
+                        ..Default::default()
+                    }),
+                    expected_version: None,
+                    update_mask: Some(FieldMask {
+                        paths: vec!["title".to_string()],
+                    }),
+                }))
+                .await
+                .unwrap();
+        } else if op % 10 == 9 {
+            service
+                .get_movies(Request::new(ReadMoviesRequest {
+                    page_size: PAGE_SIZE,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+        } else {
+            service
+                .get_movie(Request::new(ReadMovieRequest { id: movie_id(n) }))
+                .await
+                .unwrap();
+        }
+    }
+}
+
+async fn run_workload(service: &Arc<MovieServiceImpl>, writes: usize) {
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|task| tokio::spawn(run_task(service.clone(), task, writes)))
+        .collect();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+fn service_mixed(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let service = runtime.block_on(start_service());
+
+    let mut group = c.benchmark_group("movie_service_mixed");
+    group.throughput(Throughput::Elements((TASKS * OPS_PER_TASK) as u64));
+    for (name, writes) in WORKLOADS {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &writes, |b, &writes| {
+            b.to_async(&runtime).iter(|| run_workload(&service, writes));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, service_mixed);
+criterion_main!(benches);
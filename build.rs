@@ -1,5 +1,10 @@
+use std::{env, path::PathBuf};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/movie.proto")?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("movie_descriptor.bin"))
+        .compile_protos(&["proto/movie.proto"], &["proto"])?;
     Ok(())
 }
-
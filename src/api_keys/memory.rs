@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use super::{ApiKey, ApiKeyStore};
+use crate::storage::StorageError;
+
+/// Keeps keys for the lifetime of the process only; every key is lost on
+/// restart.
+#[derive(Debug, Default)]
+pub struct MemoryApiKeyStore {
+    keys: RwLock<HashMap<String, ApiKey>>,
+}
+
+fn lock_error<T>(_: T) -> StorageError {
+    StorageError::Backend("Lock error".to_string())
+}
+
+#[async_trait]
+impl ApiKeyStore for MemoryApiKeyStore {
+    async fn insert(&self, key: ApiKey) -> Result<(), StorageError> {
+        let mut keys = self.keys.write().map_err(lock_error)?;
+        if keys.contains_key(&key.id) {
+            return Err(StorageError::Backend(format!(
+                "duplicate API key id: {}",
+                key.id
+            )));
+        }
+        keys.insert(key.id.clone(), key);
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ApiKey>, StorageError> {
+        Ok(self.keys.read().map_err(lock_error)?.get(id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, StorageError> {
+        let mut keys: Vec<ApiKey> = self
+            .keys
+            .read()
+            .map_err(lock_error)?
+            .values()
+            .cloned()
+            .collect();
+        keys.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(keys)
+    }
+
+    async fn revoke(&self, id: &str, at: SystemTime) -> Result<bool, StorageError> {
+        match self.keys.write().map_err(lock_error)?.get_mut(id) {
+            Some(key) => {
+                key.revoked_at.get_or_insert(at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
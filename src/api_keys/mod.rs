@@ -0,0 +1,246 @@
+//! API keys for machine clients of the HTTP gateway.
+//!
+//! Keys look like `mk_<id>_<secret>`. The id is not secret and locates the
+//! stored record; only a SHA-256 hash of the whole key is persisted, so the
+//! plaintext is returned exactly once, when the key is created. Each key
+//! carries the scopes it grants, an optional expiry, and can be revoked.
+
+use std::{fmt, sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use prost_types::Timestamp;
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::StorageError;
+
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryApiKeyStore;
+pub use sqlite::SqliteApiKeyStore;
+
+const KEY_PREFIX: &str = "mk_";
+const ID_BYTES: usize = 8;
+const SECRET_BYTES: usize = 32;
+
+/// What a key is allowed to do beyond reading movies, which is open to
+/// every caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "movies:write")]
+    MoviesWrite,
+    #[serde(rename = "movies:delete")]
+    MoviesDelete,
+    #[serde(rename = "keys:admin")]
+    KeysAdmin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::MoviesWrite => "movies:write",
+            Scope::MoviesDelete => "movies:delete",
+            Scope::KeysAdmin => "keys:admin",
+        }
+    }
+}
+
+/// A stored key. `hash` is the hex SHA-256 of the full key string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub hash: String,
+    pub created_at: SystemTime,
+    pub expires_at: Option<SystemTime>,
+    pub revoked_at: Option<SystemTime>,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiKeyError {
+    /// The value is not shaped like a key issued by this gateway.
+    Malformed,
+    /// No key with this id exists, or the secret does not match.
+    Invalid,
+    Expired,
+    Revoked,
+    Storage(StorageError),
+}
+
+impl fmt::Display for ApiKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeyError::Malformed => write!(f, "malformed API key"),
+            ApiKeyError::Invalid => write!(f, "invalid API key"),
+            ApiKeyError::Expired => write!(f, "API key has expired"),
+            ApiKeyError::Revoked => write!(f, "API key has been revoked"),
+            ApiKeyError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ApiKeyError {}
+
+impl From<StorageError> for ApiKeyError {
+    fn from(err: StorageError) -> Self {
+        ApiKeyError::Storage(err)
+    }
+}
+
+/// Persistence boundary for API keys. Records are never deleted, only
+/// marked revoked, so they stay visible in listings.
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync + fmt::Debug {
+    async fn insert(&self, key: ApiKey) -> Result<(), StorageError>;
+
+    async fn get(&self, id: &str) -> Result<Option<ApiKey>, StorageError>;
+
+    /// Returns every key, oldest first.
+    async fn list(&self) -> Result<Vec<ApiKey>, StorageError>;
+
+    /// Marks a key revoked at `at`, returning whether it exists. Revoking
+    /// an already revoked key keeps the original time.
+    async fn revoke(&self, id: &str, at: SystemTime) -> Result<bool, StorageError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiKeyStoreConfig {
+    Memory,
+    Sqlite { path: String },
+}
+
+impl ApiKeyStoreConfig {
+    pub async fn open(&self) -> Result<Arc<dyn ApiKeyStore>, StorageError> {
+        match self {
+            ApiKeyStoreConfig::Memory => Ok(Arc::new(MemoryApiKeyStore::default())),
+            ApiKeyStoreConfig::Sqlite { path } => {
+                Ok(Arc::new(SqliteApiKeyStore::open(path).await?))
+            }
+        }
+    }
+}
+
+/// Issues and checks keys against an [`ApiKeyStore`].
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    store: Arc<dyn ApiKeyStore>,
+    rng: SystemRandom,
+}
+
+impl ApiKeys {
+    pub fn new(store: Arc<dyn ApiKeyStore>) -> Self {
+        Self {
+            store,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Creates a key and returns its record together with the plaintext,
+    /// which cannot be recovered later.
+    pub async fn create(
+        &self,
+        name: String,
+        scopes: Vec<Scope>,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(ApiKey, String), StorageError> {
+        let mut id = [0u8; ID_BYTES];
+        let mut secret = [0u8; SECRET_BYTES];
+        self.rng
+            .fill(&mut id)
+            .and_then(|_| self.rng.fill(&mut secret))
+            .map_err(|_| StorageError::Backend("cannot generate API key".to_string()))?;
+
+        let id = hex(&id);
+        let plaintext = format!("{}{}_{}", KEY_PREFIX, id, URL_SAFE_NO_PAD.encode(secret));
+
+        let mut scopes = scopes;
+        scopes.sort_by_key(|scope| scope.as_str());
+        scopes.dedup();
+
+        let key = ApiKey {
+            id,
+            name,
+            scopes,
+            hash: hash(&plaintext),
+            created_at: SystemTime::now(),
+            expires_at,
+            revoked_at: None,
+        };
+        self.store.insert(key.clone()).await?;
+        Ok((key, plaintext))
+    }
+
+    /// Looks up the key presented by a caller and checks that it is still
+    /// usable. Expiry and revocation are only reported once the secret has
+    /// matched.
+    pub async fn authenticate(&self, presented: &str) -> Result<ApiKey, ApiKeyError> {
+        let (id, secret) = presented
+            .strip_prefix(KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+            .ok_or(ApiKeyError::Malformed)?;
+        if id.len() != ID_BYTES * 2 || secret.is_empty() {
+            return Err(ApiKeyError::Malformed);
+        }
+
+        let key = self.store.get(id).await?.ok_or(ApiKeyError::Invalid)?;
+        if !constant_time_eq(key.hash.as_bytes(), hash(presented).as_bytes()) {
+            return Err(ApiKeyError::Invalid);
+        }
+        if key.revoked_at.is_some() {
+            return Err(ApiKeyError::Revoked);
+        }
+        if key.is_expired(SystemTime::now()) {
+            return Err(ApiKeyError::Expired);
+        }
+        Ok(key)
+    }
+
+    pub async fn list(&self) -> Result<Vec<ApiKey>, StorageError> {
+        self.store.list().await
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<bool, StorageError> {
+        self.store.revoke(id, SystemTime::now()).await
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hash(key: &str) -> String {
+    hex(digest::digest(&digest::SHA256, key.as_bytes()).as_ref())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Renders a time as RFC 3339, the format used in storage and in responses.
+pub fn rfc3339(time: SystemTime) -> String {
+    Timestamp::from(time).to_string()
+}
+
+/// Parses an RFC 3339 time.
+pub fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    value
+        .parse::<Timestamp>()
+        .ok()
+        .and_then(|timestamp| SystemTime::try_from(timestamp).ok())
+}
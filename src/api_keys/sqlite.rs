@@ -0,0 +1,183 @@
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row};
+
+use super::{parse_rfc3339, rfc3339, ApiKey, ApiKeyStore, Scope};
+use crate::storage::StorageError;
+
+/// Schema migrations, applied in order and tracked in `PRAGMA user_version`
+/// like the movie database; entries must only ever be appended.
+const MIGRATIONS: &[&str] = &["CREATE TABLE api_keys (
+        id         TEXT PRIMARY KEY NOT NULL,
+        name       TEXT NOT NULL,
+        scopes     TEXT NOT NULL,
+        hash       TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        expires_at TEXT,
+        revoked_at TEXT
+    );"];
+
+const COLUMNS: &str = "id, name, scopes, hash, created_at, expires_at, revoked_at";
+
+/// Keys in their own SQLite database file. Scopes are stored as a JSON
+/// array and times as RFC 3339 text.
+#[derive(Debug, Clone)]
+pub struct SqliteApiKeyStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteApiKeyStore {
+    pub async fn open(path: &str) -> Result<Self, StorageError> {
+        let path = path.to_string();
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection, StorageError> {
+            let mut conn = Connection::open(path)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            migrate(&mut conn)?;
+            Ok(conn)
+        })
+        .await
+        .map_err(|e| StorageError::Backend(e.to_string()))??;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| StorageError::Backend("Lock error".to_string()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| StorageError::Backend(e.to_string()))?
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let current: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (version, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", (version + 1) as u32)?;
+        tx.commit()?;
+        tracing::info!("Applied API key sqlite migration {}", version + 1);
+    }
+
+    Ok(())
+}
+
+fn time_column(row: &Row<'_>, index: usize) -> rusqlite::Result<Option<SystemTime>> {
+    let text: Option<String> = row.get(index)?;
+    text.map(|text| {
+        parse_rfc3339(&text).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                index,
+                Type::Text,
+                format!("invalid timestamp: {}", text).into(),
+            )
+        })
+    })
+    .transpose()
+}
+
+fn key_from_row(row: &Row<'_>) -> rusqlite::Result<ApiKey> {
+    let scopes: String = row.get(2)?;
+    let scopes: Vec<Scope> = serde_json::from_str(&scopes)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, Type::Text, Box::new(e)))?;
+    let created_at = time_column(row, 4)?.ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(4, Type::Null, "missing created_at".into())
+    })?;
+
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        scopes,
+        hash: row.get(3)?,
+        created_at,
+        expires_at: time_column(row, 5)?,
+        revoked_at: time_column(row, 6)?,
+    })
+}
+
+#[async_trait]
+impl ApiKeyStore for SqliteApiKeyStore {
+    async fn insert(&self, key: ApiKey) -> Result<(), StorageError> {
+        let scopes =
+            serde_json::to_string(&key.scopes).map_err(|e| StorageError::Backend(e.to_string()))?;
+        self.with_conn(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO api_keys ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    COLUMNS
+                ),
+                params![
+                    key.id,
+                    key.name,
+                    scopes,
+                    key.hash,
+                    rfc3339(key.created_at),
+                    key.expires_at.map(rfc3339),
+                    key.revoked_at.map(rfc3339),
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ApiKey>, StorageError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            Ok(conn
+                .query_row(
+                    &format!("SELECT {} FROM api_keys WHERE id = ?1", COLUMNS),
+                    params![id],
+                    key_from_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, StorageError> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(&format!("SELECT {} FROM api_keys", COLUMNS))?;
+            let mut keys = stmt
+                .query_map([], key_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            // RFC 3339 text with varying fractional digits does not sort
+            // chronologically, so order after parsing.
+            keys.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+            Ok(keys)
+        })
+        .await
+    }
+
+    async fn revoke(&self, id: &str, at: SystemTime) -> Result<bool, StorageError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE api_keys SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+                params![id, rfc3339(at)],
+            )?;
+            let exists = conn
+                .query_row("SELECT 1 FROM api_keys WHERE id = ?1", params![id], |_| {
+                    Ok(())
+                })
+                .optional()?
+                .is_some();
+            Ok(exists)
+        })
+        .await
+    }
+}
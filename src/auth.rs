@@ -0,0 +1,625 @@
+//! Bearer-token (JWT) authentication and role-based authorization.
+//!
+//! Reads are open to everyone. Creating and updating movies requires the
+//! `editor` role and deleting them requires `admin`. The gRPC server
+//! authenticates in [`AuthInterceptor`] and each mutating RPC checks the
+//! caller with [`authorize`]; the gateway does the same per HTTP method in
+//! its middleware and forwards the caller's token to the server.
+//!
+//! A gateway calls with the service token on behalf of its API key callers
+//! and names the key in [`API_KEY_ID_METADATA`], and passes on every
+//! client's address in [`CLIENT_ADDR_METADATA`]. The server only believes
+//! either from the trusted proxies it is configured with, and then uses the
+//! key as the caller's subject and the address as their [`ForwardedFor`].
+
+use std::{fmt, net::IpAddr};
+
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use tonic::{metadata::MetadataMap, service::Interceptor, Request, Status};
+
+/// Roles in increasing order of privilege; each includes those before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata naming the API key a gateway calls on behalf of.
+pub const API_KEY_ID_METADATA: &str = "x-api-key-id";
+
+/// Metadata with the address of the client a gateway calls on behalf of.
+pub const CLIENT_ADDR_METADATA: &str = "x-forwarded-for";
+
+/// Address of the client a trusted proxy made the call for, stored in
+/// request extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardedFor(pub IpAddr);
+
+/// The authenticated caller, stored in request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub subject: String,
+    pub role: Role,
+}
+
+impl Identity {
+    /// The caller when authentication is disabled: everything is allowed,
+    /// matching the behaviour before authentication existed.
+    pub fn anonymous() -> Self {
+        Self {
+            subject: "anonymous".to_string(),
+            role: Role::Admin,
+        }
+    }
+}
+
+/// Key used to verify token signatures. HS256 tokens are checked against a
+/// shared secret and RS256 tokens against a PEM public key.
+#[derive(Clone, PartialEq, Eq)]
+pub enum JwtKey {
+    Hs256Secret(Vec<u8>),
+    Rs256PublicKey(Vec<u8>),
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtKey::Hs256Secret(_) => f.write_str("Hs256Secret(<redacted>)"),
+            JwtKey::Rs256PublicKey(_) => f.write_str("Rs256PublicKey(..)"),
+        }
+    }
+}
+
+impl JwtKey {
+    pub fn decoding_key(&self) -> Result<DecodingKey, jsonwebtoken::errors::Error> {
+        match self {
+            JwtKey::Hs256Secret(secret) => Ok(DecodingKey::from_secret(secret)),
+            JwtKey::Rs256PublicKey(pem) => DecodingKey::from_rsa_pem(pem),
+        }
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            JwtKey::Hs256Secret(_) => Algorithm::HS256,
+            JwtKey::Rs256PublicKey(_) => Algorithm::RS256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthConfig {
+    pub key: JwtKey,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// The `authorization` value is not `Bearer <token>`.
+    Malformed,
+    Invalid(jsonwebtoken::errors::Error),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Malformed => write!(f, "expected a Bearer token"),
+            AuthError::Invalid(err) => write!(f, "invalid token: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<AuthError> for Status {
+    fn from(err: AuthError) -> Self {
+        Status::unauthenticated(err.to_string())
+    }
+}
+
+/// Checks token signatures, expiry and, when configured, issuer and
+/// audience.
+#[derive(Clone)]
+pub struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl fmt::Debug for JwtVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtVerifier")
+            .field("algorithms", &self.validation.algorithms)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwtVerifier {
+    /// The key was already parsed once while loading the config, so this
+    /// only fails if it has somehow become unreadable.
+    pub fn new(config: &AuthConfig) -> Result<Self, jsonwebtoken::errors::Error> {
+        let mut validation = Validation::new(config.key.algorithm());
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        Ok(Self {
+            key: config.key.decoding_key()?,
+            validation,
+        })
+    }
+
+    /// Verifies an `authorization` header value. A token without any known
+    /// role is treated as a viewer.
+    pub fn verify(&self, authorization: &str) -> Result<Identity, AuthError> {
+        let token = bearer_token(authorization).ok_or(AuthError::Malformed)?;
+        let claims = decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(AuthError::Invalid)?
+            .claims;
+
+        let role = claims
+            .roles
+            .iter()
+            .filter_map(|role| Role::parse(role))
+            .max()
+            .unwrap_or(Role::Viewer);
+
+        Ok(Identity {
+            subject: claims.sub,
+            role,
+        })
+    }
+}
+
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Authenticates gRPC calls from their `authorization` metadata and stores
+/// the resulting [`Identity`] in the request extensions. Calls without a
+/// token pass through unauthenticated, so open RPCs keep working; a token
+/// that is present but invalid is always rejected.
+#[derive(Debug, Clone)]
+pub struct AuthInterceptor {
+    verifier: Option<JwtVerifier>,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl AuthInterceptor {
+    /// With no verifier, every caller is [`Identity::anonymous`].
+    pub fn new(verifier: Option<JwtVerifier>) -> Self {
+        Self {
+            verifier,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    /// Peers whose forwarded caller metadata is believed, i.e. the
+    /// gateways.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Metadata `key` of a call from a trusted proxy.
+    fn forwarded<'a>(&self, request: &'a Request<()>, key: &str) -> Option<&'a str> {
+        let peer = request.remote_addr()?.ip();
+        if !self.trusted_proxies.contains(&peer) {
+            return None;
+        }
+        request
+            .metadata()
+            .get(key)?
+            .to_str()
+            .ok()
+            .filter(|value| !value.is_empty())
+    }
+
+    fn identify(&self, metadata: &MetadataMap) -> Result<Option<Identity>, Status> {
+        let Some(verifier) = &self.verifier else {
+            return Ok(Some(Identity::anonymous()));
+        };
+        match metadata.get("authorization") {
+            Some(value) => {
+                let value = value.to_str().map_err(|_| AuthError::Malformed)?;
+                Ok(Some(verifier.verify(value)?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(mut identity) = self.identify(request.metadata())? {
+            // The key keeps the role of the token it was forwarded with.
+            if let Some(id) = self.forwarded(&request, API_KEY_ID_METADATA) {
+                identity.subject = format!("api-key:{}", id);
+            }
+            request.extensions_mut().insert(identity);
+        }
+        if let Some(addr) = self
+            .forwarded(&request, CLIENT_ADDR_METADATA)
+            .and_then(|addr| addr.trim().parse().ok())
+        {
+            request.extensions_mut().insert(ForwardedFor(addr));
+        }
+        Ok(request)
+    }
+}
+
+/// Returns the caller if they hold at least `required`.
+pub fn authorize<T>(request: &Request<T>, required: Role) -> Result<&Identity, Status> {
+    let identity = request
+        .extensions()
+        .get::<Identity>()
+        .ok_or_else(|| Status::unauthenticated("A bearer token is required"))?;
+    if identity.role < required {
+        return Err(Status::permission_denied(format!(
+            "Role {} is required",
+            required.as_str()
+        )));
+    }
+    Ok(identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
+    use tonic::Code;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn claims(roles: &[&str]) -> Value {
+        json!({
+            "sub": "alice",
+            "roles": roles,
+            "exp": now() + 600,
+            "iss": "https://issuer.test",
+            "aud": "movies",
+        })
+    }
+
+    fn hs256(claims: &Value) -> String {
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            claims,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap();
+        format!("Bearer {}", token)
+    }
+
+    fn config(key: JwtKey) -> AuthConfig {
+        AuthConfig {
+            key,
+            issuer: Some("https://issuer.test".to_string()),
+            audience: Some("movies".to_string()),
+        }
+    }
+
+    fn verifier() -> JwtVerifier {
+        JwtVerifier::new(&config(JwtKey::Hs256Secret(SECRET.to_vec()))).unwrap()
+    }
+
+    fn rejection(result: Result<Identity, AuthError>) -> AuthError {
+        match result {
+            Ok(identity) => panic!("accepted as {:?}", identity),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_hs256_token() {
+        let identity = verifier().verify(&hs256(&claims(&["editor"]))).unwrap();
+
+        assert_eq!(
+            identity,
+            Identity {
+                subject: "alice".to_string(),
+                role: Role::Editor,
+            }
+        );
+    }
+
+    #[test]
+    fn accepts_a_valid_rs256_token() {
+        let rsa = openssl::rsa::Rsa::generate(2048).unwrap();
+        let private_pem = rsa.private_key_to_pem().unwrap();
+        let public_pem = rsa.public_key_to_pem().unwrap();
+        let token = encode(
+            &Header::new(Algorithm::RS256),
+            &claims(&["admin"]),
+            &EncodingKey::from_rsa_pem(&private_pem).unwrap(),
+        )
+        .unwrap();
+        let verifier = JwtVerifier::new(&config(JwtKey::Rs256PublicKey(public_pem))).unwrap();
+
+        let identity = verifier.verify(&format!("Bearer {}", token)).unwrap();
+        assert_eq!(identity.role, Role::Admin);
+
+        // An HS256 token is not accepted where RS256 is configured.
+        let err = rejection(verifier.verify(&hs256(&claims(&["admin"]))));
+        assert!(matches!(err, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn rejects_an_expired_token() {
+        let mut claims = claims(&["admin"]);
+        // Past the default leeway of a minute.
+        claims["exp"] = json!(now() - 120);
+
+        let err = rejection(verifier().verify(&hs256(&claims)));
+        assert!(matches!(err, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn rejects_the_wrong_issuer() {
+        let mut claims = claims(&["admin"]);
+        claims["iss"] = json!("https://elsewhere.test");
+
+        let err = rejection(verifier().verify(&hs256(&claims)));
+        assert!(matches!(err, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn rejects_the_wrong_audience() {
+        let mut claims = claims(&["admin"]);
+        claims["aud"] = json!("billing");
+
+        let err = rejection(verifier().verify(&hs256(&claims)));
+        assert!(matches!(err, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn rejects_a_bad_signature() {
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims(&["admin"]),
+            &EncodingKey::from_secret(b"another-secret"),
+        )
+        .unwrap();
+
+        let err = rejection(verifier().verify(&format!("Bearer {}", token)));
+        assert!(matches!(err, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_bearer_tokens() {
+        let token = hs256(&claims(&["admin"]));
+        let raw = token.trim_start_matches("Bearer ");
+
+        for value in [
+            raw.to_string(),
+            format!("Basic {}", raw),
+            "Bearer".to_string(),
+            "Bearer    ".to_string(),
+            String::new(),
+        ] {
+            let err = rejection(verifier().verify(&value));
+            assert!(matches!(err, AuthError::Malformed), "value {:?}", value);
+        }
+
+        // The scheme is case-insensitive.
+        verifier().verify(&format!("bearer {}", raw)).unwrap();
+        // A token that is not a JWT at all.
+        let err = rejection(verifier().verify("Bearer not.a.jwt"));
+        assert!(matches!(err, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn takes_the_highest_known_role() {
+        let verify = |roles: &[&str]| verifier().verify(&hs256(&claims(roles))).unwrap().role;
+
+        assert_eq!(verify(&["viewer", "admin", "editor"]), Role::Admin);
+        assert_eq!(verify(&["editor", "superuser"]), Role::Editor);
+        assert_eq!(verify(&["superuser"]), Role::Viewer);
+        assert_eq!(verify(&[]), Role::Viewer);
+    }
+
+    fn intercept(
+        verifier: Option<JwtVerifier>,
+        authorization: Option<&str>,
+    ) -> Result<Request<()>, Status> {
+        let mut request = Request::new(());
+        if let Some(value) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", value.parse().unwrap());
+        }
+        AuthInterceptor::new(verifier).call(request)
+    }
+
+    #[test]
+    fn interceptor_stores_the_verified_identity() {
+        let request = intercept(Some(verifier()), Some(&hs256(&claims(&["editor"])))).unwrap();
+
+        let identity = authorize(&request, Role::Editor).unwrap();
+        assert_eq!(identity.subject, "alice");
+    }
+
+    #[test]
+    fn interceptor_lets_calls_without_a_token_through_unauthenticated() {
+        let request = intercept(Some(verifier()), None).unwrap();
+
+        assert!(request.extensions().get::<Identity>().is_none());
+        let status = authorize(&request, Role::Viewer).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn interceptor_rejects_an_invalid_token() {
+        let status = intercept(Some(verifier()), Some("Bearer not.a.jwt")).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let status = intercept(Some(verifier()), Some("Token abc")).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn without_a_verifier_every_caller_is_an_anonymous_admin() {
+        // Even a token that would not verify is ignored.
+        let request = intercept(None, Some("Bearer not.a.jwt")).unwrap();
+
+        let identity = authorize(&request, Role::Admin).unwrap();
+        assert_eq!(*identity, Identity::anonymous());
+        assert_eq!(identity.role, Role::Admin);
+    }
+
+    #[test]
+    fn authorize_requires_at_least_the_role() {
+        let as_role = |role| {
+            let mut request = Request::new(());
+            request.extensions_mut().insert(Identity {
+                subject: "alice".to_string(),
+                role,
+            });
+            request
+        };
+
+        authorize(&as_role(Role::Editor), Role::Editor).unwrap();
+        authorize(&as_role(Role::Admin), Role::Editor).unwrap();
+        let status = authorize(&as_role(Role::Viewer), Role::Editor).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let status = authorize(&as_role(Role::Editor), Role::Admin).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    fn from_peer(peer: &str, metadata: &[(&'static str, &str)]) -> Request<()> {
+        let mut request = Request::new(());
+        request
+            .extensions_mut()
+            .insert(tonic::transport::server::TcpConnectInfo {
+                local_addr: None,
+                remote_addr: Some(peer.parse().unwrap()),
+            });
+        for (key, value) in metadata {
+            request.metadata_mut().insert(*key, value.parse().unwrap());
+        }
+        request
+    }
+
+    fn gateway_interceptor(verifier: Option<JwtVerifier>) -> AuthInterceptor {
+        AuthInterceptor::new(verifier).with_trusted_proxies(vec!["10.0.0.5".parse().unwrap()])
+    }
+
+    #[test]
+    fn trusted_proxy_names_the_api_key_caller() {
+        let service_token = hs256(&claims(&["editor"]));
+        let request = from_peer(
+            "10.0.0.5:40000",
+            &[
+                ("authorization", &service_token),
+                (API_KEY_ID_METADATA, "key-1"),
+            ],
+        );
+
+        let request = gateway_interceptor(Some(verifier())).call(request).unwrap();
+
+        let identity = request.extensions().get::<Identity>().unwrap();
+        assert_eq!(identity.subject, "api-key:key-1");
+        // The role is still the service token's.
+        assert_eq!(identity.role, Role::Editor);
+    }
+
+    #[test]
+    fn other_peers_cannot_name_an_api_key() {
+        let token = hs256(&claims(&["editor"]));
+        let request = from_peer(
+            "10.0.0.6:40000",
+            &[("authorization", &token), (API_KEY_ID_METADATA, "key-1")],
+        );
+
+        let request = gateway_interceptor(Some(verifier())).call(request).unwrap();
+
+        assert_eq!(
+            request.extensions().get::<Identity>().unwrap().subject,
+            "alice"
+        );
+    }
+
+    #[test]
+    fn client_address_is_only_taken_from_trusted_proxies() {
+        let mut interceptor = gateway_interceptor(None);
+
+        let request = from_peer("10.0.0.5:40000", &[(CLIENT_ADDR_METADATA, "203.0.113.7")]);
+        let request = interceptor.call(request).unwrap();
+        assert_eq!(
+            request.extensions().get::<ForwardedFor>(),
+            Some(&ForwardedFor("203.0.113.7".parse().unwrap()))
+        );
+
+        let request = from_peer("10.0.0.6:40000", &[(CLIENT_ADDR_METADATA, "203.0.113.7")]);
+        let request = interceptor.call(request).unwrap();
+        assert_eq!(request.extensions().get::<ForwardedFor>(), None);
+
+        let request = from_peer("10.0.0.5:40000", &[(CLIENT_ADDR_METADATA, "not-an-ip")]);
+        let request = interceptor.call(request).unwrap();
+        assert_eq!(request.extensions().get::<ForwardedFor>(), None);
+    }
+
+    #[test]
+    fn forwarded_api_key_is_the_caller_without_authentication() {
+        let request = from_peer("10.0.0.5:40000", &[(API_KEY_ID_METADATA, "key-1")]);
+
+        let request = gateway_interceptor(None).call(request).unwrap();
+
+        let identity = request.extensions().get::<Identity>().unwrap();
+        assert_eq!(identity.subject, "api-key:key-1");
+        assert_ne!(*identity, Identity::anonymous());
+    }
+
+    #[test]
+    fn forwarded_api_key_does_not_authenticate_on_its_own() {
+        let request = from_peer("10.0.0.5:40000", &[(API_KEY_ID_METADATA, "key-1")]);
+
+        let request = gateway_interceptor(Some(verifier())).call(request).unwrap();
+
+        assert!(request.extensions().get::<Identity>().is_none());
+    }
+}
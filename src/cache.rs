@@ -0,0 +1,354 @@
+//! Read-through response cache for the gateway's movie reads.
+//!
+//! Entries expire after a fixed TTL and the least recently used entry is
+//! evicted once the cache is full. The gateway drops entries itself when it
+//! writes a movie, and can additionally follow the server's change stream
+//! to drop entries for writes made elsewhere. This module knows nothing of
+//! the responses it holds; the gateway picks the keys and values.
+
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    future::Future,
+    hash::Hash,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Entries kept per kind of read (single movies and list pages).
+    pub capacity: usize,
+    pub ttl: Duration,
+    /// Follow the server's change stream to drop entries for movies
+    /// changed by other clients.
+    pub watch: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    Hit,
+    Miss,
+    /// The caller asked to skip the cache; the fresh result still replaces
+    /// the cached one.
+    Bypass,
+}
+
+impl Lookup {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Lookup::Hit => "hit",
+            Lookup::Miss => "miss",
+            Lookup::Bypass => "bypass",
+        }
+    }
+}
+
+/// How a read was answered and how much longer its result may be reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Freshness {
+    pub lookup: Lookup,
+    /// Time since the result was fetched from the server.
+    pub age: Duration,
+    pub ttl: Duration,
+}
+
+impl Freshness {
+    pub fn max_age(&self) -> Duration {
+        self.ttl.saturating_sub(self.age)
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    stored_at: Instant,
+    used: u64,
+}
+
+/// Entries by key plus their order of last use. `epoch` changes whenever
+/// anything is invalidated, so a fetch that started before an invalidation
+/// does not store its possibly stale result.
+struct Lru<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    epoch: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    fn touch(&mut self, key: &K) -> u64 {
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.tick
+    }
+
+    fn remove<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.used);
+        }
+    }
+}
+
+/// One kind of cached read, keyed by `K`.
+pub struct ResponseCache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<Lru<K, V>>,
+}
+
+impl<K, V> fmt::Debug for ResponseCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ResponseCache<K, V> {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            capacity: config.capacity,
+            ttl: config.ttl,
+            inner: Mutex::new(Lru {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                epoch: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru<K, V>> {
+        // Entries are only ever replaced whole, so a panic cannot leave one
+        // half written.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the cached value for `key` unless it has expired, or else
+    /// awaits `fetch` and caches its result. With `bypass`, always fetches.
+    /// Errors are never cached.
+    pub async fn get_or_fetch<E, F>(
+        &self,
+        key: K,
+        bypass: bool,
+        fetch: F,
+    ) -> Result<(V, Freshness), E>
+    where
+        F: Future<Output = Result<V, E>>,
+    {
+        let epoch = {
+            let mut lru = self.lock();
+            if !bypass {
+                if let Some((value, age)) = self.get(&mut lru, &key, Instant::now()) {
+                    return Ok((
+                        value,
+                        Freshness {
+                            lookup: Lookup::Hit,
+                            age,
+                            ttl: self.ttl,
+                        },
+                    ));
+                }
+            }
+            lru.epoch
+        };
+
+        let value = fetch.await?;
+        self.insert(key, value.clone(), epoch, Instant::now());
+        Ok((
+            value,
+            Freshness {
+                lookup: if bypass { Lookup::Bypass } else { Lookup::Miss },
+                age: Duration::ZERO,
+                ttl: self.ttl,
+            },
+        ))
+    }
+
+    fn get(&self, lru: &mut Lru<K, V>, key: &K, now: Instant) -> Option<(V, Duration)> {
+        let entry = lru.entries.get(key)?;
+        let age = now.saturating_duration_since(entry.stored_at);
+        if age >= self.ttl {
+            lru.remove(key);
+            return None;
+        }
+
+        let previous = entry.used;
+        lru.order.remove(&previous);
+        let used = lru.touch(key);
+        let entry = lru.entries.get_mut(key)?;
+        entry.used = used;
+        Some((entry.value.clone(), age))
+    }
+
+    fn insert(&self, key: K, value: V, epoch: u64, now: Instant) {
+        let mut lru = self.lock();
+        if lru.epoch != epoch {
+            return;
+        }
+
+        lru.remove(&key);
+        while lru.entries.len() >= self.capacity {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            lru.entries.remove(&oldest);
+        }
+        let used = lru.touch(&key);
+        lru.entries.insert(
+            key,
+            Entry {
+                value,
+                stored_at: now,
+                used,
+            },
+        );
+    }
+
+    pub fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut lru = self.lock();
+        lru.remove(key);
+        lru.epoch += 1;
+    }
+
+    pub fn clear(&self) {
+        let mut lru = self.lock();
+        lru.entries.clear();
+        lru.order.clear();
+        lru.epoch += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn cache(capacity: usize) -> ResponseCache<&'static str, u32> {
+        ResponseCache::new(&CacheConfig {
+            capacity,
+            ttl: TTL,
+            watch: false,
+        })
+    }
+
+    async fn fetch(
+        cache: &ResponseCache<&'static str, u32>,
+        key: &'static str,
+        value: u32,
+    ) -> (u32, Lookup) {
+        let (value, freshness) = cache
+            .get_or_fetch(key, false, async { Ok::<_, ()>(value) })
+            .await
+            .unwrap();
+        (value, freshness.lookup)
+    }
+
+    fn cached(cache: &ResponseCache<&'static str, u32>, key: &'static str, at: Instant) -> bool {
+        let mut lru = cache.lock();
+        cache.get(&mut lru, &key, at).is_some()
+    }
+
+    #[tokio::test]
+    async fn serves_hits_until_bypassed() {
+        let cache = cache(10);
+        assert_eq!(fetch(&cache, "a", 1).await, (1, Lookup::Miss));
+        assert_eq!(fetch(&cache, "a", 2).await, (1, Lookup::Hit));
+
+        let (value, freshness) = cache
+            .get_or_fetch("a", true, async { Ok::<_, ()>(3) })
+            .await
+            .unwrap();
+        assert_eq!((value, freshness.lookup), (3, Lookup::Bypass));
+        assert_eq!(fetch(&cache, "a", 4).await, (3, Lookup::Hit));
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_errors() {
+        let cache = cache(10);
+        let result = cache.get_or_fetch("a", false, async { Err("down") }).await;
+        assert_eq!(result.unwrap_err(), "down");
+        assert_eq!(fetch(&cache, "a", 1).await, (1, Lookup::Miss));
+    }
+
+    #[test]
+    fn expires_entries_after_ttl() {
+        let cache = cache(10);
+        let start = Instant::now();
+        cache.insert("a", 1, 0, start);
+
+        assert!(cached(&cache, "a", start + TTL - Duration::from_millis(1)));
+        assert!(!cached(&cache, "a", start + TTL));
+        // Expired entries are dropped rather than kept around.
+        assert!(cache.lock().entries.is_empty());
+        assert!(cache.lock().order.is_empty());
+    }
+
+    #[test]
+    fn evicts_least_recently_used_when_full() {
+        let cache = cache(2);
+        let now = Instant::now();
+        cache.insert("a", 1, 0, now);
+        cache.insert("b", 2, 0, now);
+        assert!(cached(&cache, "a", now));
+
+        cache.insert("c", 3, 0, now);
+        assert!(cached(&cache, "a", now));
+        assert!(!cached(&cache, "b", now));
+        assert!(cached(&cache, "c", now));
+
+        // Replacing an entry does not evict another.
+        cache.insert("c", 4, 0, now);
+        assert!(cached(&cache, "a", now));
+        assert_eq!(cache.lock().entries.len(), 2);
+        assert_eq!(cache.lock().order.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn drops_fill_that_started_before_a_remove() {
+        let cache = cache(10);
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
+        let (filled, ()) = tokio::join!(
+            cache.get_or_fetch("a", false, async { receiver.await.map_err(|_| ()) }),
+            async {
+                // Runs once the fetch above is waiting on the server.
+                cache.remove("a");
+                sender.send(1).unwrap();
+            },
+        );
+        assert_eq!(filled.unwrap().0, 1);
+        assert_eq!(fetch(&cache, "a", 2).await, (2, Lookup::Miss));
+    }
+
+    #[tokio::test]
+    async fn drops_fill_that_started_before_a_clear() {
+        let cache = cache(10);
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
+        let (filled, ()) = tokio::join!(
+            cache.get_or_fetch("a", false, async { receiver.await.map_err(|_| ()) }),
+            async {
+                cache.clear();
+                sender.send(1).unwrap();
+            },
+        );
+        assert_eq!(filled.unwrap().0, 1);
+        assert_eq!(fetch(&cache, "a", 2).await, (2, Lookup::Miss));
+        // Fills that start after the invalidation are kept.
+        assert_eq!(fetch(&cache, "a", 3).await, (2, Lookup::Hit));
+    }
+}
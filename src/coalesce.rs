@@ -0,0 +1,301 @@
+//! Single-flight deduplication: concurrent identical calls share one
+//! in-flight call instead of each making their own.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    sync::{Mutex, PoisonError},
+};
+
+use opentelemetry::trace::SpanContext;
+use tokio::sync::watch;
+
+struct Flight<V> {
+    id: u64,
+    done: watch::Receiver<Option<V>>,
+    span: SpanContext,
+}
+
+/// Calls in flight by key. The first caller for a key becomes the leader
+/// and makes the call; callers arriving before it finishes follow it and
+/// receive a clone of its result.
+pub struct SingleFlight<K, V> {
+    flights: Mutex<Flights<K, V>>,
+}
+
+struct Flights<K, V> {
+    by_key: HashMap<K, Flight<V>>,
+    next_id: u64,
+}
+
+impl<K, V> fmt::Debug for SingleFlight<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleFlight").finish_non_exhaustive()
+    }
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            flights: Mutex::new(Flights {
+                by_key: HashMap::new(),
+                next_id: 0,
+            }),
+        }
+    }
+}
+
+pub enum Joined<'a, K: Hash + Eq, V> {
+    Leader(Leader<'a, K, V>),
+    Follower(Follower<V>),
+}
+
+impl<K: Hash + Eq, V> SingleFlight<K, V> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Flights<K, V>> {
+        // Every update is a single insert or remove.
+        self.flights.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stops callers from joining the calls in flight for keys matching
+    /// `detach`, e.g. because their results may predate a write. Those
+    /// calls still finish for whoever already follows them.
+    pub fn forget(&self, detach: impl Fn(&K) -> bool) {
+        self.lock().by_key.retain(|key, _| !detach(key));
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SingleFlight<K, V> {
+    /// Follows the call in flight for `key`, or else starts one led by the
+    /// caller, whose `span` followers can link to.
+    pub fn join(&self, key: K, span: SpanContext) -> Joined<'_, K, V> {
+        let mut flights = self.lock();
+        if let Some(flight) = flights.by_key.get(&key) {
+            return Joined::Follower(Follower {
+                done: flight.done.clone(),
+                span: flight.span.clone(),
+            });
+        }
+
+        let id = flights.next_id;
+        flights.next_id += 1;
+        let (done, receiver) = watch::channel(None);
+        flights.by_key.insert(
+            key.clone(),
+            Flight {
+                id,
+                done: receiver,
+                span,
+            },
+        );
+        Joined::Leader(Leader {
+            flights: self,
+            id,
+            key: Some(key),
+            done,
+        })
+    }
+}
+
+/// Makes the call. Dropping it without calling [`Leader::finish`], for
+/// example because the leading request was cancelled, releases its
+/// followers empty-handed.
+pub struct Leader<'a, K: Hash + Eq, V> {
+    flights: &'a SingleFlight<K, V>,
+    id: u64,
+    key: Option<K>,
+    done: watch::Sender<Option<V>>,
+}
+
+impl<K: Hash + Eq, V> Leader<'_, K, V> {
+    /// Hands `value` to every follower. Callers arriving from now on start
+    /// a new call.
+    pub fn finish(mut self, value: V) {
+        self.release();
+        self.done.send_replace(Some(value));
+    }
+
+    fn release(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        // After `forget`, the key may belong to a newer call.
+        let mut flights = self.flights.lock();
+        if flights
+            .by_key
+            .get(&key)
+            .is_some_and(|flight| flight.id == self.id)
+        {
+            flights.by_key.remove(&key);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+pub struct Follower<V> {
+    done: watch::Receiver<Option<V>>,
+    span: SpanContext,
+}
+
+impl<V: Clone> Follower<V> {
+    /// The span of the leader's call.
+    pub fn span_context(&self) -> &SpanContext {
+        &self.span
+    }
+
+    /// Waits for the leader's result; `None` if the leader gave up, in
+    /// which case the caller should try again.
+    pub async fn wait(mut self) -> Option<V> {
+        self.done
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .and_then(|value| value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Long enough that a follower still waiting afterwards is stuck.
+    const HANG: Duration = Duration::from_secs(5);
+
+    fn join<'a>(
+        flights: &'a SingleFlight<&'static str, u32>,
+        key: &'static str,
+    ) -> Joined<'a, &'static str, u32> {
+        flights.join(key, SpanContext::empty_context())
+    }
+
+    fn leader<'a>(joined: Joined<'a, &'static str, u32>) -> Leader<'a, &'static str, u32> {
+        match joined {
+            Joined::Leader(leader) => leader,
+            Joined::Follower(_) => panic!("expected to lead"),
+        }
+    }
+
+    fn follower(joined: Joined<'_, &'static str, u32>) -> Follower<u32> {
+        match joined {
+            Joined::Follower(follower) => follower,
+            Joined::Leader(_) => panic!("expected to follow"),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_call() {
+        let flights = SingleFlight::default();
+        let leader = leader(join(&flights, "a"));
+        let followers: Vec<_> = (0..3).map(|_| follower(join(&flights, "a"))).collect();
+        // Other keys get their own call.
+        let _other = self::leader(join(&flights, "b"));
+
+        leader.finish(7);
+        for follower in followers {
+            assert_eq!(follower.wait().await, Some(7));
+        }
+
+        // The finished call is not joined by later callers.
+        let _next = self::leader(join(&flights, "a"));
+    }
+
+    /// What the gateway does per `GetMovie`: lead a call, or follow one and
+    /// retry if its leader gives up.
+    async fn lookup(flights: &SingleFlight<&'static str, u32>, calls: &AtomicUsize) -> u32 {
+        loop {
+            match join(flights, "a") {
+                Joined::Leader(leader) => {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    leader.finish(42);
+                    return 42;
+                }
+                Joined::Follower(follower) => {
+                    if let Some(value) = follower.wait().await {
+                        return value;
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_tasks_make_one_call() {
+        let flights = Arc::new(SingleFlight::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let flights = flights.clone();
+                let calls = calls.clone();
+                tokio::spawn(async move { lookup(&flights, &calls).await })
+            })
+            .collect();
+        for task in tasks {
+            let value = tokio::time::timeout(HANG, task).await.unwrap().unwrap();
+            assert_eq!(value, 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_leader_releases_followers() {
+        let flights = SingleFlight::default();
+        let leader = leader(join(&flights, "a"));
+        let follower = follower(join(&flights, "a"));
+
+        drop(leader);
+        let result = tokio::time::timeout(HANG, follower.wait()).await.unwrap();
+        assert_eq!(result, None);
+        // The follower's retry makes a new call.
+        let _retry = self::leader(join(&flights, "a"));
+    }
+
+    #[tokio::test]
+    async fn cancelled_leader_releases_followers() {
+        let flights = SingleFlight::default();
+        let joined = join(&flights, "a");
+        let follower = follower(join(&flights, "a"));
+
+        // The leading request times out while its call is outstanding.
+        let call = async {
+            let leader = leader(joined);
+            std::future::pending::<()>().await;
+            leader.finish(1);
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(10), call)
+            .await
+            .is_err());
+
+        let result = tokio::time::timeout(HANG, follower.wait()).await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn forgotten_call_still_finishes_for_its_followers() {
+        let flights = SingleFlight::default();
+        let stale = leader(join(&flights, "a"));
+        let follower = follower(join(&flights, "a"));
+
+        flights.forget(|key| *key == "a");
+        let fresh = leader(join(&flights, "a"));
+        let late = self::follower(join(&flights, "a"));
+
+        stale.finish(1);
+        assert_eq!(follower.wait().await, Some(1));
+        // Finishing the stale call leaves the newer one in place.
+        let _ = self::follower(join(&flights, "a"));
+
+        fresh.finish(2);
+        assert_eq!(late.wait().await, Some(2));
+    }
+}
@@ -0,0 +1,1360 @@
+//! Startup configuration for `movie-server` and `movie-client`.
+//!
+//! Every setting is resolved with the precedence
+//! command-line flag > environment variable > TOML file > built-in default.
+//! Both binaries can share one file: each reads `[telemetry]` and only the
+//! sections it needs (`[server]` and `[storage]`, or `[gateway]`).
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use axum::http::HeaderValue;
+use clap::{Args, Parser};
+use serde::Deserialize;
+use tonic::transport::Uri;
+use tracing_subscriber::EnvFilter;
+
+use crate::api_keys::ApiKeyStoreConfig;
+use crate::auth::{AuthConfig, JwtKey};
+use crate::cache::{self, CacheConfig};
+use crate::gateway;
+use crate::rate_limit::{RateLimit, RateLimitConfig};
+use crate::resilience::{self, BreakerConfig, ResilienceConfig, RetryPolicy};
+use crate::service;
+use crate::shutdown;
+use crate::storage::{JournalConfig, StorageConfig};
+use crate::tls::{ClientTlsFiles, ServerTlsFiles};
+use crate::upstream::{self, BalanceConfig, Discovery};
+use crate::watch;
+
+pub const DEFAULT_SERVER_ADDR: &str = "0.0.0.0:50051";
+pub const DEFAULT_GATEWAY_ADDR: &str = "0.0.0.0:5000";
+pub const DEFAULT_UPSTREAM: &str = "http://movie-server:50051";
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://otel-collector:4317";
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const DEFAULT_SQLITE_PATH: &str = "movies.db";
+pub const DEFAULT_JOURNAL_COMPACT_SECS: u64 = 60;
+pub const DEFAULT_API_KEY_SQLITE_PATH: &str = "api_keys.db";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        message: String,
+    },
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    Invalid {
+        field: &'static str,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, message } => {
+                write!(f, "cannot read config file {}: {}", path.display(), message)
+            }
+            ConfigError::Parse { path, message } => {
+                write!(f, "invalid config file {}: {}", path.display(), message)
+            }
+            ConfigError::Invalid { field, message } => {
+                write!(f, "invalid value for {}: {}", field, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn invalid(field: &'static str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        field,
+        message: message.into(),
+    }
+}
+
+/// Flags shared by both binaries.
+#[derive(Debug, Default, Args)]
+pub struct TelemetryArgs {
+    /// OTLP gRPC endpoint for traces, metrics and logs.
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Log filter directive, e.g. `info` or `movie_tonic=debug,info`.
+    #[arg(long, env = "MOVIE_LOG_LEVEL")]
+    pub log_level: Option<String>,
+}
+
+/// JWT verification flags shared by both binaries. Authentication is
+/// enabled by setting exactly one of `--jwt-secret` or `--jwt-public-key`.
+#[derive(Debug, Default, Args)]
+pub struct AuthArgs {
+    /// Shared secret for HS256-signed tokens.
+    #[arg(long, env = "MOVIE_JWT_SECRET", hide_env_values = true)]
+    pub jwt_secret: Option<String>,
+
+    /// PEM RSA public key file for RS256-signed tokens.
+    #[arg(long, env = "MOVIE_JWT_PUBLIC_KEY")]
+    pub jwt_public_key: Option<PathBuf>,
+
+    /// Required `iss` claim.
+    #[arg(long, env = "MOVIE_JWT_ISSUER")]
+    pub jwt_issuer: Option<String>,
+
+    /// Required `aud` claim.
+    #[arg(long, env = "MOVIE_JWT_AUDIENCE")]
+    pub jwt_audience: Option<String>,
+}
+
+/// Default per-client rate limit, shared by both binaries. Limits for
+/// individual routes or RPCs can only be set in the config file.
+#[derive(Debug, Default, Args)]
+pub struct RateLimitArgs {
+    /// Requests per second each client may make to each route or RPC.
+    #[arg(long, env = "MOVIE_RATE_LIMIT_PER_SECOND")]
+    pub rate_limit_per_second: Option<u32>,
+
+    /// Requests a client may make at once before being limited to
+    /// `--rate-limit-per-second`; defaults to that rate.
+    #[arg(long, env = "MOVIE_RATE_LIMIT_BURST")]
+    pub rate_limit_burst: Option<u32>,
+}
+
+/// How the gateway calls the gRPC server. Per-RPC deadlines can only be set
+/// in the config file.
+#[derive(Debug, Default, Args)]
+pub struct ResilienceArgs {
+    /// Deadline in milliseconds for each unary call to the server,
+    /// including retries.
+    #[arg(long, env = "MOVIE_UPSTREAM_DEADLINE_MS")]
+    pub upstream_deadline_ms: Option<u64>,
+
+    /// Limit in milliseconds on connecting to the server.
+    #[arg(long, env = "MOVIE_UPSTREAM_CONNECT_TIMEOUT_MS")]
+    pub upstream_connect_timeout_ms: Option<u64>,
+
+    /// Attempts per idempotent call, including the first.
+    #[arg(long, env = "MOVIE_UPSTREAM_MAX_ATTEMPTS")]
+    pub upstream_max_attempts: Option<u32>,
+
+    /// Consecutive upstream failures that open the circuit breaker.
+    #[arg(long, env = "MOVIE_BREAKER_FAILURE_THRESHOLD")]
+    pub breaker_failure_threshold: Option<u32>,
+
+    /// Seconds an open circuit fails calls fast before trying again.
+    #[arg(long, env = "MOVIE_BREAKER_OPEN_SECS")]
+    pub breaker_open_secs: Option<u64>,
+}
+
+/// How the gateway finds server replicas and spreads calls across them.
+#[derive(Debug, Default, Args)]
+pub struct BalanceArgs {
+    /// `static` (each upstream URL is one replica) or `dns` (every address
+    /// each upstream host resolves to is one).
+    #[arg(long, env = "MOVIE_UPSTREAM_DISCOVERY")]
+    pub upstream_discovery: Option<String>,
+
+    /// Seconds between DNS lookups with `dns` discovery.
+    #[arg(long, env = "MOVIE_DNS_REFRESH_SECS")]
+    pub dns_refresh_secs: Option<u64>,
+
+    /// Seconds between health checks of each replica.
+    #[arg(long, env = "MOVIE_UPSTREAM_HEALTH_CHECK_SECS")]
+    pub upstream_health_check_secs: Option<u64>,
+}
+
+/// Response cache flags for the gateway; the cache is off unless a TTL is
+/// set.
+#[derive(Debug, Default, Args)]
+pub struct CacheArgs {
+    /// Seconds a cached movie or list page may be served for.
+    #[arg(long, env = "MOVIE_CACHE_TTL_SECS")]
+    pub cache_ttl_secs: Option<u64>,
+
+    /// Entries kept for movies and, separately, for list pages.
+    #[arg(long, env = "MOVIE_CACHE_CAPACITY")]
+    pub cache_capacity: Option<usize>,
+
+    /// Also drop entries for movies the server reports as changed.
+    #[arg(
+        long,
+        env = "MOVIE_CACHE_WATCH",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    pub cache_watch: Option<bool>,
+}
+
+/// API key store flags for the gateway.
+#[derive(Debug, Default, Args)]
+pub struct ApiKeyArgs {
+    /// API key store: `memory` or `sqlite`.
+    #[arg(long, env = "MOVIE_API_KEY_BACKEND")]
+    pub api_key_backend: Option<String>,
+
+    /// SQLite database file, used by the `sqlite` API key store.
+    #[arg(long, env = "MOVIE_API_KEY_SQLITE_PATH")]
+    pub api_key_sqlite_path: Option<String>,
+
+    /// Bearer token the gateway sends upstream on behalf of API key
+    /// callers, for when the server verifies tokens itself.
+    #[arg(long, env = "MOVIE_API_KEY_SERVICE_TOKEN", hide_env_values = true)]
+    pub api_key_service_token: Option<String>,
+}
+
+#[derive(Debug, Default, Parser)]
+#[command(name = "movie-server", about = "Movie gRPC server")]
+pub struct ServerArgs {
+    /// Path to a TOML config file.
+    #[arg(long, env = "MOVIE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the gRPC server listens on.
+    #[arg(long, env = "MOVIE_SERVER_ADDR")]
+    pub listen_addr: Option<SocketAddr>,
+
+    #[command(flatten)]
+    pub telemetry: TelemetryArgs,
+
+    #[command(flatten)]
+    pub auth: AuthArgs,
+
+    #[command(flatten)]
+    pub rate_limit: RateLimitArgs,
+
+    /// Storage backend: `memory` or `sqlite`.
+    #[arg(long, env = "MOVIE_STORAGE_BACKEND")]
+    pub storage_backend: Option<String>,
+
+    /// SQLite database file, used by the `sqlite` backend.
+    #[arg(long, env = "MOVIE_SQLITE_PATH")]
+    pub sqlite_path: Option<String>,
+
+    /// Journal directory; makes the `memory` backend durable.
+    #[arg(long, env = "MOVIE_JOURNAL_DIR")]
+    pub journal_dir: Option<String>,
+
+    /// Seconds between journal compactions.
+    #[arg(long, env = "MOVIE_JOURNAL_COMPACT_SECS")]
+    pub journal_compact_secs: Option<u64>,
+
+    /// Number of change events retained for resuming `WatchMovies`.
+    #[arg(long, env = "MOVIE_WATCH_HISTORY")]
+    pub watch_history: Option<usize>,
+
+    /// Seconds to let in-flight requests finish after SIGINT/SIGTERM.
+    #[arg(long, env = "MOVIE_DRAIN_TIMEOUT_SECS")]
+    pub drain_timeout_secs: Option<u64>,
+
+    /// Milliseconds to keep serving after reporting not ready on
+    /// SIGINT/SIGTERM, before connections start to drain.
+    #[arg(long, env = "MOVIE_SHUTDOWN_DELAY_MS")]
+    pub shutdown_delay_ms: Option<u64>,
+
+    /// PEM certificate chain; enables TLS together with `--tls-key`.
+    #[arg(long, env = "MOVIE_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--tls-cert`.
+    #[arg(long, env = "MOVIE_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA bundle; requires clients to present a certificate it signed.
+    #[arg(long, env = "MOVIE_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Gateway addresses whose forwarded caller metadata is trusted, comma
+    /// separated.
+    #[arg(long, env = "MOVIE_TRUSTED_PROXIES", value_delimiter = ',')]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Debug, Default, Parser)]
+#[command(
+    name = "movie-client",
+    about = "HTTP gateway for the movie gRPC server"
+)]
+pub struct GatewayArgs {
+    /// Path to a TOML config file.
+    #[arg(long, env = "MOVIE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the HTTP gateway listens on.
+    #[arg(long, env = "MOVIE_GATEWAY_ADDR")]
+    pub listen_addr: Option<SocketAddr>,
+
+    /// URLs of the movie gRPC server replicas, comma separated.
+    #[arg(long, env = "MOVIE_UPSTREAM", value_delimiter = ',')]
+    pub upstream: Vec<String>,
+
+    /// Seconds to let in-flight requests finish after SIGINT/SIGTERM.
+    #[arg(long, env = "MOVIE_DRAIN_TIMEOUT_SECS")]
+    pub drain_timeout_secs: Option<u64>,
+
+    /// Milliseconds to keep serving after reporting not ready on
+    /// SIGINT/SIGTERM, before connections start to drain.
+    #[arg(long, env = "MOVIE_SHUTDOWN_DELAY_MS")]
+    pub shutdown_delay_ms: Option<u64>,
+
+    /// PEM CA bundle used to verify an `https://` upstream.
+    #[arg(long, env = "MOVIE_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate chain presented to the upstream (mTLS).
+    #[arg(long, env = "MOVIE_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--tls-cert`.
+    #[arg(long, env = "MOVIE_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Name to verify every replica's certificate against; defaults to the
+    /// host of each upstream URL.
+    #[arg(long, env = "MOVIE_TLS_DOMAIN")]
+    pub tls_domain: Option<String>,
+
+    #[command(flatten)]
+    pub telemetry: TelemetryArgs,
+
+    #[command(flatten)]
+    pub auth: AuthArgs,
+
+    #[command(flatten)]
+    pub api_keys: ApiKeyArgs,
+
+    #[command(flatten)]
+    pub resilience: ResilienceArgs,
+
+    #[command(flatten)]
+    pub balance: BalanceArgs,
+
+    #[command(flatten)]
+    pub cache: CacheArgs,
+
+    #[command(flatten)]
+    pub rate_limit: RateLimitArgs,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    telemetry: TelemetryFile,
+    auth: AuthFile,
+    server: ServerFile,
+    storage: StorageFile,
+    gateway: GatewayFile,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TelemetryFile {
+    otlp_endpoint: Option<String>,
+    log_level: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthFile {
+    jwt_secret: Option<String>,
+    jwt_public_key: Option<PathBuf>,
+    jwt_issuer: Option<String>,
+    jwt_audience: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerFile {
+    listen_addr: Option<SocketAddr>,
+    watch_history: Option<usize>,
+    drain_timeout_secs: Option<u64>,
+    shutdown_delay_ms: Option<u64>,
+    tls: ServerTlsFile,
+    rate_limit: ServerRateLimitFile,
+    trusted_proxies: Option<Vec<IpAddr>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerTlsFile {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RateLimitFile {
+    per_second: Option<u32>,
+    burst: Option<u32>,
+}
+
+/// `[server.rate_limit]`, with per-RPC limits in `[server.rate_limit.rpcs]`
+/// keyed by method name, e.g. `CreateMovie`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerRateLimitFile {
+    per_second: Option<u32>,
+    burst: Option<u32>,
+    rpcs: BTreeMap<String, RateLimitFile>,
+}
+
+/// `[gateway.rate_limit]`, with per-route limits in
+/// `[gateway.rate_limit.routes]` keyed by method and path, e.g.
+/// `"POST /movies"`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GatewayRateLimitFile {
+    per_second: Option<u32>,
+    burst: Option<u32>,
+    routes: BTreeMap<String, RateLimitFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageFile {
+    backend: Option<String>,
+    sqlite_path: Option<String>,
+    journal_dir: Option<String>,
+    journal_compact_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GatewayFile {
+    listen_addr: Option<SocketAddr>,
+    upstream: Option<Upstreams>,
+    drain_timeout_secs: Option<u64>,
+    shutdown_delay_ms: Option<u64>,
+    tls: GatewayTlsFile,
+    api_keys: ApiKeysFile,
+    rate_limit: GatewayRateLimitFile,
+    resilience: ResilienceFile,
+    balance: BalanceFile,
+    cache: CacheFile,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CacheFile {
+    ttl_secs: Option<u64>,
+    capacity: Option<usize>,
+    watch: Option<bool>,
+}
+
+/// `upstream` is either one URL or a list of replicas.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Upstreams {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BalanceFile {
+    discovery: Option<String>,
+    dns_refresh_secs: Option<u64>,
+    health_check_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GatewayTlsFile {
+    ca: Option<PathBuf>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    domain: Option<String>,
+}
+
+/// `[gateway.resilience]`, with per-RPC deadlines in
+/// `[gateway.resilience.deadlines_ms]` keyed by method name.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ResilienceFile {
+    deadline_ms: Option<u64>,
+    connect_timeout_ms: Option<u64>,
+    max_attempts: Option<u32>,
+    initial_backoff_ms: Option<u64>,
+    max_backoff_ms: Option<u64>,
+    breaker_failure_threshold: Option<u32>,
+    breaker_open_secs: Option<u64>,
+    deadlines_ms: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ApiKeysFile {
+    backend: Option<String>,
+    sqlite_path: Option<String>,
+    service_token: Option<String>,
+}
+
+impl FileConfig {
+    fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError::Read {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelemetryConfig {
+    pub otlp_endpoint: String,
+    pub log_level: String,
+}
+
+impl TelemetryConfig {
+    fn resolve(args: TelemetryArgs, file: TelemetryFile) -> Result<Self, ConfigError> {
+        let otlp_endpoint = args
+            .otlp_endpoint
+            .or(file.otlp_endpoint)
+            .unwrap_or_else(|| DEFAULT_OTLP_ENDPOINT.to_string());
+        validate_endpoint("otlp_endpoint", &otlp_endpoint)?;
+
+        let log_level = args
+            .log_level
+            .or(file.log_level)
+            .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+        EnvFilter::try_new(&log_level).map_err(|e| invalid("log_level", e.to_string()))?;
+
+        Ok(Self {
+            otlp_endpoint,
+            log_level,
+        })
+    }
+
+    /// Builds a filter from `log_level`, which was validated on load.
+    pub fn env_filter(&self) -> EnvFilter {
+        EnvFilter::new(&self.log_level)
+    }
+}
+
+/// Resolves the JWT settings; `None` leaves authentication disabled.
+fn resolve_auth(args: AuthArgs, file: AuthFile) -> Result<Option<AuthConfig>, ConfigError> {
+    let key = match (
+        args.jwt_secret.or(file.jwt_secret),
+        args.jwt_public_key.or(file.jwt_public_key),
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(invalid(
+                "jwt_secret",
+                "set either jwt_secret or jwt_public_key, not both",
+            ))
+        }
+        (Some(secret), None) => {
+            if secret.len() < 32 {
+                return Err(invalid("jwt_secret", "must be at least 32 bytes"));
+            }
+            JwtKey::Hs256Secret(secret.into_bytes())
+        }
+        (None, Some(path)) => {
+            let pem = fs::read(&path).map_err(|e| ConfigError::Read {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+            let key = JwtKey::Rs256PublicKey(pem);
+            key.decoding_key().map_err(|e| ConfigError::Parse {
+                path,
+                message: e.to_string(),
+            })?;
+            key
+        }
+        (None, None) => return Ok(None),
+    };
+
+    Ok(Some(AuthConfig {
+        key,
+        issuer: args.jwt_issuer.or(file.jwt_issuer),
+        audience: args.jwt_audience.or(file.jwt_audience),
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub listen_addr: SocketAddr,
+    pub telemetry: TelemetryConfig,
+    pub storage: StorageConfig,
+    pub watch_history: usize,
+    /// Time between reporting not ready and starting to drain.
+    pub shutdown_delay: Duration,
+    pub drain_timeout: Duration,
+    pub tls: Option<ServerTlsFiles>,
+    pub auth: Option<AuthConfig>,
+    /// Per-RPC limits are keyed by method name.
+    pub rate_limit: RateLimitConfig,
+    /// Peers allowed to say whom they call on behalf of.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ServerConfig {
+    /// Parses the process arguments and environment, then merges in the
+    /// config file they point at.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_args(ServerArgs::parse())
+    }
+
+    pub fn from_args(args: ServerArgs) -> Result<Self, ConfigError> {
+        let file = FileConfig::load(args.config.as_deref())?;
+
+        let listen_addr = match args.listen_addr.or(file.server.listen_addr) {
+            Some(addr) => addr,
+            None => DEFAULT_SERVER_ADDR.parse().expect("valid default address"),
+        };
+
+        let watch_history = args
+            .watch_history
+            .or(file.server.watch_history)
+            .unwrap_or(watch::DEFAULT_HISTORY);
+        if watch_history == 0 {
+            return Err(invalid("watch_history", "must be at least 1"));
+        }
+
+        let backend = args
+            .storage_backend
+            .or(file.storage.backend)
+            .unwrap_or_else(|| "memory".to_string());
+        let storage = match backend.as_str() {
+            "memory" => {
+                let journal = args.journal_dir.or(file.storage.journal_dir);
+                let compact_secs = args
+                    .journal_compact_secs
+                    .or(file.storage.journal_compact_secs)
+                    .unwrap_or(DEFAULT_JOURNAL_COMPACT_SECS);
+                if compact_secs == 0 {
+                    return Err(invalid("journal_compact_secs", "must be at least 1"));
+                }
+                StorageConfig::Memory {
+                    journal: journal.map(|dir| JournalConfig {
+                        dir,
+                        compact_interval: Duration::from_secs(compact_secs),
+                    }),
+                }
+            }
+            "sqlite" => StorageConfig::Sqlite {
+                path: args
+                    .sqlite_path
+                    .or(file.storage.sqlite_path)
+                    .unwrap_or_else(|| DEFAULT_SQLITE_PATH.to_string()),
+            },
+            other => {
+                return Err(invalid(
+                    "storage_backend",
+                    format!("expected `memory` or `sqlite`, got `{}`", other),
+                ))
+            }
+        };
+
+        let tls = match (
+            args.tls_cert.or(file.server.tls.cert),
+            args.tls_key.or(file.server.tls.key),
+            args.tls_client_ca.or(file.server.tls.client_ca),
+        ) {
+            (Some(cert), Some(key), client_ca) => Some(ServerTlsFiles {
+                cert,
+                key,
+                client_ca,
+            }),
+            (None, None, None) => None,
+            (None, None, Some(_)) => {
+                return Err(invalid(
+                    "tls_client_ca",
+                    "client certificate verification requires tls_cert and tls_key",
+                ))
+            }
+            _ => {
+                return Err(invalid(
+                    "tls_cert",
+                    "tls_cert and tls_key must be set together",
+                ))
+            }
+        };
+
+        Ok(Self {
+            listen_addr,
+            telemetry: TelemetryConfig::resolve(args.telemetry, file.telemetry)?,
+            storage,
+            watch_history,
+            shutdown_delay: shutdown_delay(
+                args.shutdown_delay_ms.or(file.server.shutdown_delay_ms),
+            ),
+            drain_timeout: drain_timeout(
+                args.drain_timeout_secs.or(file.server.drain_timeout_secs),
+            ),
+            tls,
+            auth: resolve_auth(args.auth, file.auth)?,
+            rate_limit: resolve_rate_limit(
+                args.rate_limit,
+                RateLimitFile {
+                    per_second: file.server.rate_limit.per_second,
+                    burst: file.server.rate_limit.burst,
+                },
+                file.server.rate_limit.rpcs,
+                service::RPCS,
+            )?,
+            trusted_proxies: if args.trusted_proxies.is_empty() {
+                file.server.trusted_proxies.unwrap_or_default()
+            } else {
+                args.trusted_proxies
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewayConfig {
+    pub listen_addr: SocketAddr,
+    /// Never empty; all `http://` or all `https://`.
+    pub upstreams: Vec<Uri>,
+    pub balance: BalanceConfig,
+    pub telemetry: TelemetryConfig,
+    /// Time between reporting not ready and starting to drain.
+    pub shutdown_delay: Duration,
+    pub drain_timeout: Duration,
+    /// Set exactly when the upstreams are `https://` URLs.
+    pub tls: Option<ClientTlsFiles>,
+    pub auth: Option<AuthConfig>,
+    pub api_keys: ApiKeysConfig,
+    /// Per-route limits are keyed by `"<METHOD> <path>"`.
+    pub rate_limit: RateLimitConfig,
+    pub resilience: ResilienceConfig,
+    /// `None` disables the response cache.
+    pub cache: Option<CacheConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeysConfig {
+    pub store: ApiKeyStoreConfig,
+    /// Complete `Authorization` value (`Bearer <token>`), marked sensitive
+    /// so it is redacted from debug output.
+    pub service_token: Option<HeaderValue>,
+}
+
+impl GatewayConfig {
+    /// Parses the process arguments and environment, then merges in the
+    /// config file they point at.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_args(GatewayArgs::parse())
+    }
+
+    pub fn from_args(args: GatewayArgs) -> Result<Self, ConfigError> {
+        let file = FileConfig::load(args.config.as_deref())?;
+
+        let listen_addr = match args.listen_addr.or(file.gateway.listen_addr) {
+            Some(addr) => addr,
+            None => DEFAULT_GATEWAY_ADDR.parse().expect("valid default address"),
+        };
+
+        let upstreams = if !args.upstream.is_empty() {
+            args.upstream
+        } else {
+            match file.gateway.upstream {
+                Some(Upstreams::One(upstream)) => vec![upstream],
+                Some(Upstreams::Many(upstreams)) => upstreams,
+                None => vec![DEFAULT_UPSTREAM.to_string()],
+            }
+        };
+        let upstreams = upstreams
+            .iter()
+            .map(|upstream| validate_endpoint("upstream", upstream.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let https = match upstreams.first() {
+            Some(first) => first.scheme_str() == Some("https"),
+            None => return Err(invalid("upstream", "at least one URL is required")),
+        };
+        if upstreams
+            .iter()
+            .any(|upstream| (upstream.scheme_str() == Some("https")) != https)
+        {
+            return Err(invalid(
+                "upstream",
+                "replicas must all be http:// or all be https:// URLs",
+            ));
+        }
+
+        let ca = args.tls_ca.or(file.gateway.tls.ca);
+        let identity = match (
+            args.tls_cert.or(file.gateway.tls.cert),
+            args.tls_key.or(file.gateway.tls.key),
+        ) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => {
+                return Err(invalid(
+                    "tls_cert",
+                    "tls_cert and tls_key must be set together",
+                ))
+            }
+        };
+        let domain = args.tls_domain.or(file.gateway.tls.domain);
+
+        let tls = if https {
+            let ca = ca.ok_or_else(|| invalid("tls_ca", "required for an https:// upstream"))?;
+            Some(ClientTlsFiles {
+                ca,
+                domain,
+                identity,
+            })
+        } else if ca.is_some() || identity.is_some() || domain.is_some() {
+            return Err(invalid(
+                "upstream",
+                "TLS settings require an https:// upstream",
+            ));
+        } else {
+            None
+        };
+
+        let auth = resolve_auth(args.auth, file.auth)?;
+        let api_keys = ApiKeysConfig::resolve(args.api_keys, file.gateway.api_keys)?;
+        // The server then verifies tokens too, and API key callers would have
+        // nothing to authenticate with.
+        if auth.is_some() && api_keys.service_token.is_none() {
+            return Err(invalid(
+                "api_key_service_token",
+                "required when JWT authentication is enabled",
+            ));
+        }
+
+        Ok(Self {
+            listen_addr,
+            upstreams,
+            balance: resolve_balance(args.balance, file.gateway.balance)?,
+            tls,
+            telemetry: TelemetryConfig::resolve(args.telemetry, file.telemetry)?,
+            shutdown_delay: shutdown_delay(
+                args.shutdown_delay_ms.or(file.gateway.shutdown_delay_ms),
+            ),
+            drain_timeout: drain_timeout(
+                args.drain_timeout_secs.or(file.gateway.drain_timeout_secs),
+            ),
+            auth,
+            api_keys,
+            rate_limit: resolve_rate_limit(
+                args.rate_limit,
+                RateLimitFile {
+                    per_second: file.gateway.rate_limit.per_second,
+                    burst: file.gateway.rate_limit.burst,
+                },
+                file.gateway.rate_limit.routes,
+                gateway::LIMITED_ROUTES,
+            )?,
+            resilience: resolve_resilience(args.resilience, file.gateway.resilience)?,
+            cache: resolve_cache(args.cache, file.gateway.cache)?,
+        })
+    }
+}
+
+impl ApiKeysConfig {
+    fn resolve(args: ApiKeyArgs, file: ApiKeysFile) -> Result<Self, ConfigError> {
+        let backend = args
+            .api_key_backend
+            .or(file.backend)
+            .unwrap_or_else(|| "memory".to_string());
+        let store = match backend.as_str() {
+            "memory" => ApiKeyStoreConfig::Memory,
+            "sqlite" => ApiKeyStoreConfig::Sqlite {
+                path: args
+                    .api_key_sqlite_path
+                    .or(file.sqlite_path)
+                    .unwrap_or_else(|| DEFAULT_API_KEY_SQLITE_PATH.to_string()),
+            },
+            other => {
+                return Err(invalid(
+                    "api_key_backend",
+                    format!("expected `memory` or `sqlite`, got `{}`", other),
+                ))
+            }
+        };
+
+        let service_token = args
+            .api_key_service_token
+            .or(file.service_token)
+            .map(|token| {
+                let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+                    .map_err(|_| invalid("api_key_service_token", "not a valid header value"))?;
+                value.set_sensitive(true);
+                Ok(value)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            store,
+            service_token,
+        })
+    }
+}
+
+fn rate_limit(
+    field: &'static str,
+    per_second: Option<u32>,
+    burst: Option<u32>,
+) -> Result<Option<RateLimit>, ConfigError> {
+    match (per_second, burst) {
+        (None, None) => Ok(None),
+        (None, Some(_)) => Err(invalid(field, "burst requires per_second")),
+        (Some(0), _) => Err(invalid(field, "per_second must be at least 1")),
+        (Some(_), Some(0)) => Err(invalid(field, "burst must be at least 1")),
+        (Some(per_second), burst) => Ok(Some(RateLimit {
+            per_second,
+            burst: burst.unwrap_or(per_second),
+        })),
+    }
+}
+
+/// Resolves the default limit and the per-route or per-RPC overrides, whose
+/// keys must be among `known`.
+fn resolve_rate_limit(
+    args: RateLimitArgs,
+    file: RateLimitFile,
+    overrides: BTreeMap<String, RateLimitFile>,
+    known: &[&str],
+) -> Result<RateLimitConfig, ConfigError> {
+    let default = rate_limit(
+        "rate_limit",
+        args.rate_limit_per_second.or(file.per_second),
+        args.rate_limit_burst.or(file.burst),
+    )?;
+
+    let overrides = overrides
+        .into_iter()
+        .map(|(name, entry)| {
+            if !known.contains(&name.as_str()) {
+                return Err(invalid(
+                    "rate_limit",
+                    format!("unknown `{}`, expected one of: {}", name, known.join(", ")),
+                ));
+            }
+            let limit = rate_limit("rate_limit", entry.per_second, entry.burst)?
+                .ok_or_else(|| invalid("rate_limit", format!("`{}` needs per_second", name)))?;
+            Ok((name, limit))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(RateLimitConfig { default, overrides })
+}
+
+/// A duration setting that must be positive when given.
+fn positive(
+    field: &'static str,
+    value: Option<u64>,
+    unit: fn(u64) -> Duration,
+    default: Duration,
+) -> Result<Duration, ConfigError> {
+    match value {
+        Some(0) => Err(invalid(field, "must be at least 1")),
+        Some(value) => Ok(unit(value)),
+        None => Ok(default),
+    }
+}
+
+fn resolve_cache(args: CacheArgs, file: CacheFile) -> Result<Option<CacheConfig>, ConfigError> {
+    let capacity = args.cache_capacity.or(file.capacity);
+    let watch = args.cache_watch.or(file.watch);
+    let Some(ttl_secs) = args.cache_ttl_secs.or(file.ttl_secs) else {
+        if capacity.is_some() || watch.is_some() {
+            return Err(invalid(
+                "cache_ttl_secs",
+                "required when other cache settings are given",
+            ));
+        }
+        return Ok(None);
+    };
+
+    if ttl_secs == 0 {
+        return Err(invalid("cache_ttl_secs", "must be at least 1"));
+    }
+    let capacity = capacity.unwrap_or(cache::DEFAULT_CAPACITY);
+    if capacity == 0 {
+        return Err(invalid("cache_capacity", "must be at least 1"));
+    }
+
+    Ok(Some(CacheConfig {
+        capacity,
+        ttl: Duration::from_secs(ttl_secs),
+        watch: watch.unwrap_or(false),
+    }))
+}
+
+fn resolve_balance(args: BalanceArgs, file: BalanceFile) -> Result<BalanceConfig, ConfigError> {
+    let dns_refresh_secs = args.dns_refresh_secs.or(file.dns_refresh_secs);
+    let discovery = args
+        .upstream_discovery
+        .or(file.discovery)
+        .unwrap_or_else(|| "static".to_string());
+    let discovery = match discovery.as_str() {
+        "static" if dns_refresh_secs.is_some() => {
+            return Err(invalid(
+                "dns_refresh_secs",
+                "only applies to `dns` discovery",
+            ))
+        }
+        "static" => Discovery::Static,
+        "dns" => Discovery::Dns {
+            refresh: positive(
+                "dns_refresh_secs",
+                dns_refresh_secs,
+                Duration::from_secs,
+                upstream::DEFAULT_DNS_REFRESH,
+            )?,
+        },
+        other => {
+            return Err(invalid(
+                "upstream_discovery",
+                format!("expected `static` or `dns`, got `{}`", other),
+            ))
+        }
+    };
+
+    Ok(BalanceConfig {
+        discovery,
+        health_check_interval: positive(
+            "upstream_health_check_secs",
+            args.upstream_health_check_secs.or(file.health_check_secs),
+            Duration::from_secs,
+            upstream::DEFAULT_HEALTH_CHECK_INTERVAL,
+        )?,
+    })
+}
+
+fn resolve_resilience(
+    args: ResilienceArgs,
+    file: ResilienceFile,
+) -> Result<ResilienceConfig, ConfigError> {
+    let defaults = ResilienceConfig::default();
+
+    let deadlines = file
+        .deadlines_ms
+        .into_iter()
+        .map(|(rpc, ms)| {
+            if !service::RPCS.contains(&rpc.as_str()) {
+                return Err(invalid(
+                    "deadlines_ms",
+                    format!(
+                        "unknown RPC `{}`, expected one of: {}",
+                        rpc,
+                        service::RPCS.join(", ")
+                    ),
+                ));
+            }
+            if resilience::STREAMING_RPCS.contains(&rpc.as_str()) {
+                return Err(invalid(
+                    "deadlines_ms",
+                    format!("`{}` is a stream and has no deadline", rpc),
+                ));
+            }
+            let deadline = positive(
+                "deadlines_ms",
+                Some(ms),
+                Duration::from_millis,
+                defaults.deadline,
+            )?;
+            Ok((rpc, deadline))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let max_attempts = args
+        .upstream_max_attempts
+        .or(file.max_attempts)
+        .unwrap_or(defaults.retry.max_attempts);
+    if max_attempts == 0 {
+        return Err(invalid("upstream_max_attempts", "must be at least 1"));
+    }
+    let initial_backoff = positive(
+        "initial_backoff_ms",
+        file.initial_backoff_ms,
+        Duration::from_millis,
+        defaults.retry.initial_backoff,
+    )?;
+    let max_backoff = positive(
+        "max_backoff_ms",
+        file.max_backoff_ms,
+        Duration::from_millis,
+        defaults.retry.max_backoff,
+    )?;
+    if max_backoff < initial_backoff {
+        return Err(invalid(
+            "max_backoff_ms",
+            "must not be less than initial_backoff_ms",
+        ));
+    }
+
+    let failure_threshold = args
+        .breaker_failure_threshold
+        .or(file.breaker_failure_threshold)
+        .unwrap_or(defaults.breaker.failure_threshold);
+    if failure_threshold == 0 {
+        return Err(invalid("breaker_failure_threshold", "must be at least 1"));
+    }
+
+    Ok(ResilienceConfig {
+        connect_timeout: positive(
+            "upstream_connect_timeout_ms",
+            args.upstream_connect_timeout_ms.or(file.connect_timeout_ms),
+            Duration::from_millis,
+            defaults.connect_timeout,
+        )?,
+        deadline: positive(
+            "upstream_deadline_ms",
+            args.upstream_deadline_ms.or(file.deadline_ms),
+            Duration::from_millis,
+            defaults.deadline,
+        )?,
+        deadlines,
+        retry: RetryPolicy {
+            max_attempts,
+            initial_backoff,
+            max_backoff,
+        },
+        breaker: BreakerConfig {
+            failure_threshold,
+            open_for: positive(
+                "breaker_open_secs",
+                args.breaker_open_secs.or(file.breaker_open_secs),
+                Duration::from_secs,
+                defaults.breaker.open_for,
+            )?,
+        },
+    })
+}
+
+fn drain_timeout(secs: Option<u64>) -> Duration {
+    secs.map(Duration::from_secs)
+        .unwrap_or(shutdown::DEFAULT_DRAIN_TIMEOUT)
+}
+
+fn shutdown_delay(millis: Option<u64>) -> Duration {
+    millis
+        .map(Duration::from_millis)
+        .unwrap_or(shutdown::DEFAULT_SHUTDOWN_DELAY)
+}
+
+fn validate_endpoint(field: &'static str, value: &str) -> Result<Uri, ConfigError> {
+    let uri = value
+        .parse::<Uri>()
+        .map_err(|e| invalid(field, format!("{}: {}", value, e)))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") if uri.host().is_some() => Ok(uri),
+        _ => Err(invalid(
+            field,
+            format!("{}: expected an http:// or https:// URL", value),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    /// Held while arguments are parsed, since clap reads every flag's
+    /// environment variable and tests run in parallel.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Parses `args` with `env` set, restoring the environment afterwards.
+    fn parse<P: Parser>(name: &str, env: &[(&str, &str)], args: &[&str]) -> P {
+        let _lock = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let saved: Vec<_> = env
+            .iter()
+            .map(|(key, _)| (*key, std::env::var_os(key)))
+            .collect();
+        for (key, value) in env {
+            std::env::set_var(key, value);
+        }
+        let parsed = P::try_parse_from(std::iter::once(name).chain(args.iter().copied()));
+        for (key, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
+        parsed.unwrap()
+    }
+
+    fn server_with_env(env: &[(&str, &str)], args: &[&str]) -> Result<ServerConfig, ConfigError> {
+        ServerConfig::from_args(parse("movie-server", env, args))
+    }
+
+    fn server(args: &[&str]) -> Result<ServerConfig, ConfigError> {
+        server_with_env(&[], args)
+    }
+
+    fn gateway(args: &[&str]) -> Result<GatewayConfig, ConfigError> {
+        GatewayConfig::from_args(parse("movie-client", &[], args))
+    }
+
+    /// A TOML config file removed again when dropped.
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(contents: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("movie-config-{}.toml", uuid::Uuid::new_v4()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn invalid_field(result: Result<impl fmt::Debug, ConfigError>) -> &'static str {
+        match result {
+            Err(ConfigError::Invalid { field, .. }) => field,
+            other => panic!("expected an invalid field, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn authentication_requires_a_service_token_for_api_keys() {
+        assert_eq!(
+            invalid_field(gateway(&["--jwt-secret", SECRET])),
+            "api_key_service_token"
+        );
+
+        let config = gateway(&["--jwt-secret", SECRET, "--api-key-service-token", "t"]).unwrap();
+        assert!(config.auth.is_some());
+        assert_eq!(
+            config.api_keys.service_token.unwrap(),
+            HeaderValue::from_static("Bearer t")
+        );
+    }
+
+    #[test]
+    fn flags_override_env_which_overrides_the_file() {
+        let file = ConfigFile::new(
+            r#"
+            [server]
+            watch_history = 10
+            drain_timeout_secs = 7
+
+            [storage]
+            backend = "sqlite"
+            "#,
+        );
+        let env = [("MOVIE_WATCH_HISTORY", "20")];
+
+        let config =
+            server_with_env(&env, &["--config", file.path(), "--watch-history", "30"]).unwrap();
+        assert_eq!(config.watch_history, 30);
+
+        let config = server_with_env(&env, &["--config", file.path()]).unwrap();
+        assert_eq!(config.watch_history, 20);
+        // Settings only in the file still apply, and the rest keep their
+        // defaults.
+        assert_eq!(config.drain_timeout, Duration::from_secs(7));
+        assert_eq!(
+            config.storage,
+            StorageConfig::Sqlite {
+                path: DEFAULT_SQLITE_PATH.to_string()
+            }
+        );
+        assert_eq!(config.shutdown_delay, shutdown::DEFAULT_SHUTDOWN_DELAY);
+
+        let config = server(&["--config", file.path()]).unwrap();
+        assert_eq!(config.watch_history, 10);
+
+        let config = server(&[]).unwrap();
+        assert_eq!(config.watch_history, watch::DEFAULT_HISTORY);
+        assert_eq!(config.drain_timeout, shutdown::DEFAULT_DRAIN_TIMEOUT);
+        assert_eq!(config.listen_addr, DEFAULT_SERVER_ADDR.parse().unwrap());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for contents in [
+            "[server]\nwatch_histroy = 10\n",
+            "[servers]\nwatch_history = 10\n",
+            "[gateway.rate_limit.routes]\n\"GET /movies\" = { per_second = 1, bust = 2 }\n",
+        ] {
+            let file = ConfigFile::new(contents);
+            match server(&["--config", file.path()]) {
+                Err(ConfigError::Parse { message, .. }) => {
+                    assert!(message.contains("unknown field"), "{}", message)
+                }
+                other => panic!("expected a parse error for {:?}, got {:?}", contents, other),
+            }
+        }
+
+        match server(&["--config", "/nonexistent/movie.toml"]) {
+            Err(ConfigError::Read { .. }) => {}
+            other => panic!("expected a read error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn inconsistent_settings_name_the_offending_field() {
+        assert_eq!(
+            invalid_field(server(&["--tls-client-ca", "ca.pem"])),
+            "tls_client_ca"
+        );
+        assert_eq!(
+            invalid_field(server(&["--tls-cert", "cert.pem"])),
+            "tls_cert"
+        );
+        assert_eq!(
+            invalid_field(server(&[
+                "--jwt-secret",
+                SECRET,
+                "--jwt-public-key",
+                "jwt.pem"
+            ])),
+            "jwt_secret"
+        );
+        assert_eq!(
+            invalid_field(server(&["--rate-limit-burst", "5"])),
+            "rate_limit"
+        );
+        assert_eq!(
+            invalid_field(server(&["--storage-backend", "redis"])),
+            "storage_backend"
+        );
+
+        assert_eq!(
+            invalid_field(gateway(&["--upstream", "https://a:50051"])),
+            "tls_ca"
+        );
+        assert_eq!(
+            invalid_field(gateway(&["--upstream", "http://a:50051,https://b:50051"])),
+            "upstream"
+        );
+        assert_eq!(
+            invalid_field(gateway(&[
+                "--upstream",
+                "http://a:50051",
+                "--tls-ca",
+                "ca.pem"
+            ])),
+            "upstream"
+        );
+        assert_eq!(
+            invalid_field(gateway(&["--dns-refresh-secs", "5"])),
+            "dns_refresh_secs"
+        );
+    }
+}
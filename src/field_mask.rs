@@ -0,0 +1,250 @@
+use prost_types::FieldMask;
+use tonic::Status;
+
+use crate::movie::Movie;
+
+/// Paths accepted in `UpdateMovieRequest.update_mask`. Server-owned fields
+/// (`id`, `version` and the timestamps) cannot be updated.
+pub const UPDATABLE_FIELDS: [&str; 10] = [
+    "title",
+    "genre",
+    "genres",
+    "release_year",
+    "runtime_minutes",
+    "directors",
+    "cast",
+    "certification",
+    "synopsis",
+    "language",
+];
+
+/// Copies the fields named by `mask` from `patch` onto `movie`, leaving the
+/// rest untouched. Setting `genre` replaces the primary genre, i.e. the
+/// first entry of `genres`; when both are listed, `genres` wins.
+pub fn apply_update_mask(mask: &FieldMask, patch: Movie, movie: &mut Movie) -> Result<(), Status> {
+    if let Some(path) = mask
+        .paths
+        .iter()
+        .find(|path| !UPDATABLE_FIELDS.contains(&path.as_str()))
+    {
+        return Err(Status::invalid_argument(format!(
+            "update_mask path {:?} is not one of {}",
+            path,
+            UPDATABLE_FIELDS.join(", ")
+        )));
+    }
+
+    let Movie {
+        title,
+        genre,
+        genres,
+        release_year,
+        runtime_minutes,
+        directors,
+        cast,
+        certification,
+        synopsis,
+        language,
+        ..
+    } = patch;
+    let has = |field: &str| mask.paths.iter().any(|path| path == field);
+
+    if has("title") {
+        movie.title = title;
+    }
+    if has("genres") {
+        movie.genres = genres;
+    } else if has("genre") {
+        match (genre.is_empty(), movie.genres.is_empty()) {
+            (true, true) => {}
+            (true, false) => {
+                movie.genres.remove(0);
+            }
+            (false, true) => movie.genres.push(genre),
+            (false, false) => movie.genres[0] = genre,
+        }
+    }
+    if has("genre") || has("genres") {
+        movie.genre = movie.genres.first().cloned().unwrap_or_default();
+    }
+    if has("release_year") {
+        movie.release_year = release_year;
+    }
+    if has("runtime_minutes") {
+        movie.runtime_minutes = runtime_minutes;
+    }
+    if has("directors") {
+        movie.directors = directors;
+    }
+    if has("cast") {
+        movie.cast = cast;
+    }
+    if has("certification") {
+        movie.certification = certification;
+    }
+    if has("synopsis") {
+        movie.synopsis = synopsis;
+    }
+    if has("language") {
+        movie.language = language;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+    use crate::movie::CastMember;
+
+    fn mask(paths: &[&str]) -> FieldMask {
+        FieldMask {
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+
+    fn stored() -> Movie {
+        Movie {
+            id: "heat".to_string(),
+            title: "Heat".to_string(),
+            genre: "Crime".to_string(),
+            genres: vec!["Crime".to_string(), "Drama".to_string()],
+            release_year: 1995,
+            runtime_minutes: 170,
+            directors: vec!["Michael Mann".to_string()],
+            cast: vec![CastMember {
+                name: "Al Pacino".to_string(),
+                character: "Vincent Hanna".to_string(),
+            }],
+            certification: "R".to_string(),
+            synopsis: "A heist crew and the detective after them.".to_string(),
+            language: "en".to_string(),
+            version: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn changes_only_the_listed_fields() {
+        let patch = Movie {
+            id: "other".to_string(),
+            title: "Heat (1995)".to_string(),
+            runtime_minutes: 171,
+            synopsis: "Not applied".to_string(),
+            version: 9,
+            ..Default::default()
+        };
+        let mut movie = stored();
+
+        apply_update_mask(&mask(&["title", "runtime_minutes"]), patch, &mut movie).unwrap();
+
+        let expected = Movie {
+            title: "Heat (1995)".to_string(),
+            runtime_minutes: 171,
+            ..stored()
+        };
+        assert_eq!(movie, expected);
+    }
+
+    #[test]
+    fn listed_fields_left_empty_in_the_patch_are_cleared() {
+        let mut movie = stored();
+
+        apply_update_mask(
+            &mask(&["synopsis", "cast", "release_year"]),
+            Movie::default(),
+            &mut movie,
+        )
+        .unwrap();
+
+        assert_eq!(movie.synopsis, "");
+        assert!(movie.cast.is_empty());
+        assert_eq!(movie.release_year, 0);
+        assert_eq!(movie.title, "Heat");
+    }
+
+    #[test]
+    fn rejects_unknown_nested_and_server_owned_paths() {
+        for path in [
+            "rating",
+            "cast.name",
+            "genres[0]",
+            "id",
+            "version",
+            "created_at",
+            "",
+        ] {
+            let mut movie = stored();
+            let patch = Movie {
+                title: "Changed".to_string(),
+                ..Default::default()
+            };
+
+            let status = apply_update_mask(&mask(&["title", path]), patch, &mut movie).unwrap_err();
+
+            assert_eq!(status.code(), Code::InvalidArgument, "path {:?}", path);
+            assert!(status.message().contains(&format!("{:?}", path)));
+            // Nothing is applied when any path is rejected.
+            assert_eq!(movie, stored(), "path {:?}", path);
+        }
+    }
+
+    #[test]
+    fn genre_replaces_the_primary_genre() {
+        let patch = Movie {
+            genre: "Action".to_string(),
+            ..Default::default()
+        };
+        let mut movie = stored();
+
+        apply_update_mask(&mask(&["genre"]), patch, &mut movie).unwrap();
+
+        assert_eq!(movie.genre, "Action");
+        assert_eq!(movie.genres, ["Action", "Drama"]);
+    }
+
+    #[test]
+    fn clearing_genre_promotes_the_next_one() {
+        let mut movie = stored();
+
+        apply_update_mask(&mask(&["genre"]), Movie::default(), &mut movie).unwrap();
+
+        assert_eq!(movie.genre, "Drama");
+        assert_eq!(movie.genres, ["Drama"]);
+    }
+
+    #[test]
+    fn genre_is_added_to_a_movie_without_genres() {
+        let patch = Movie {
+            genre: "Action".to_string(),
+            ..Default::default()
+        };
+        let mut movie = Movie {
+            genre: String::new(),
+            genres: Vec::new(),
+            ..stored()
+        };
+
+        apply_update_mask(&mask(&["genre"]), patch, &mut movie).unwrap();
+
+        assert_eq!(movie.genre, "Action");
+        assert_eq!(movie.genres, ["Action"]);
+    }
+
+    #[test]
+    fn genres_wins_over_genre() {
+        let patch = Movie {
+            genre: "Action".to_string(),
+            genres: vec!["Thriller".to_string(), "Crime".to_string()],
+            ..Default::default()
+        };
+        let mut movie = stored();
+
+        apply_update_mask(&mask(&["genre", "genres"]), patch, &mut movie).unwrap();
+
+        assert_eq!(movie.genre, "Thriller");
+        assert_eq!(movie.genres, ["Thriller", "Crime"]);
+    }
+}
@@ -0,0 +1,3520 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, Path, Query, State},
+    http::{
+        header::{
+            AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH,
+            LOCATION, RETRY_AFTER, WWW_AUTHENTICATE,
+        },
+        Extensions, HeaderMap, HeaderValue, Method as HttpMethod, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post},
+    Json, Router,
+};
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    propagation::Injector,
+    trace::{Span, SpanKind, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use prometheus_client::encoding::text::encode;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+use prometheus_client_derive_encode::{EncodeLabelSet, EncodeLabelValue};
+use prost_types::FieldMask;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::{Request, Status, Streaming};
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::{health_client::HealthClient, HealthCheckRequest};
+use tonic_types::{ErrorDetails, StatusExt};
+use uuid::Uuid;
+
+use crate::api_keys::{parse_rfc3339, rfc3339, ApiKey, ApiKeyError, ApiKeys, Scope};
+use crate::auth::{
+    AuthError, Identity, JwtVerifier, Role, API_KEY_ID_METADATA, CLIENT_ADDR_METADATA,
+};
+use crate::cache::{CacheConfig, Freshness, Lookup, ResponseCache};
+use crate::coalesce::{Joined, SingleFlight};
+use crate::movie::movie_service_server::SERVICE_NAME;
+use crate::movie::{self, movie_service_client::MovieServiceClient};
+use crate::movie::{
+    delete_result, movie_result, BatchCreateMoviesRequest, BatchDeleteMoviesRequest,
+    BatchGetMoviesRequest, CreateMovieRequest, DeleteMovieRequest, ReadMovieRequest,
+    UpdateMovieRequest,
+};
+use crate::rate_limit::{self, Client, RateLimiter};
+use crate::resilience::{self, BreakerState, CircuitBreaker, ResilienceConfig};
+use crate::shutdown;
+use crate::upstream::Upstream;
+use crate::validation;
+use crate::watch::Position;
+
+/// How long `/readyz` waits for the upstream health check.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Header carrying an API key, as an alternative to a bearer token.
+const API_KEY_HEADER: &str = "x-api-key";
+
+const MAX_API_KEY_NAME_CHARS: usize = 100;
+
+/// Request header that makes a cacheable read skip the cache; any value
+/// counts.
+const CACHE_BYPASS_HEADER: &str = "x-cache-bypass";
+
+/// Response header telling whether a cacheable read was a hit, a miss or
+/// bypassed the cache.
+const CACHE_STATUS_HEADER: &str = "x-cache";
+
+/// Backoff between attempts to reopen the change stream that keeps the
+/// cache fresh.
+const WATCH_RETRY_INITIAL: Duration = Duration::from_secs(1);
+const WATCH_RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// Routes that can be rate limited, as `"<METHOD> <path>"` with the path
+/// pattern as registered in [`router`].
+pub const LIMITED_ROUTES: &[&str] = &[
+    "GET /movies",
+    "POST /movies",
+    "GET /movies/search",
+    "GET /movies/events",
+    "GET /movies/{id}",
+    "PUT /movies/{id}",
+    "PATCH /movies/{id}",
+    "DELETE /movies/{id}",
+    "POST /movies:batchGet",
+    "POST /movies:batch",
+    "GET /api-keys",
+    "POST /api-keys",
+    "DELETE /api-keys/{id}",
+];
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+/// `api_key` is the id of the API key the request was made with, or empty
+/// for requests authenticated any other way.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct MethodLabels {
+    pub method: Method,
+    pub api_key: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RpcLabels {
+    pub rpc: String,
+}
+
+/// `backend` is the replica's `host:port` as configured, or its resolved
+/// address with DNS discovery.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct BackendLabels {
+    pub backend: String,
+}
+
+/// `resource` is `movie` or `list`; `result` is `hit`, `miss` or `bypass`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct CacheLabels {
+    pub resource: String,
+    pub result: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ThrottleLabels {
+    pub route: String,
+    pub client_type: String,
+}
+
+/// Request counters exported as `movie_requests`. The per-method counters
+/// for requests without an API key are resolved from the family once up
+/// front, so counting one is a single atomic increment rather than a family
+/// lookup.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    requests: Family<MethodLabels, Counter>,
+    throttled: Family<ThrottleLabels, Counter>,
+    retries: Family<RpcLabels, Counter>,
+    deadline_exceeded: Family<RpcLabels, Counter>,
+    circuit_rejections: Family<RpcLabels, Counter>,
+    circuit_state: Gauge,
+    backend_requests: Family<BackendLabels, Counter>,
+    backend_healthy: Family<BackendLabels, Gauge>,
+    cache: Family<CacheLabels, Counter>,
+    coalesced: Counter,
+    get: Counter,
+    post: Counter,
+    put: Counter,
+    patch: Counter,
+    delete: Counter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let requests = Family::<MethodLabels, Counter>::default();
+        let counter = |method| {
+            requests
+                .get_or_create(&MethodLabels {
+                    method,
+                    api_key: String::new(),
+                })
+                .clone()
+        };
+
+        Self {
+            get: counter(Method::Get),
+            post: counter(Method::Post),
+            put: counter(Method::Put),
+            patch: counter(Method::Patch),
+            delete: counter(Method::Delete),
+            requests,
+            throttled: Family::default(),
+            retries: Family::default(),
+            deadline_exceeded: Family::default(),
+            circuit_rejections: Family::default(),
+            circuit_state: Gauge::default(),
+            backend_requests: Family::default(),
+            backend_healthy: Family::default(),
+            cache: Family::default(),
+            coalesced: Counter::default(),
+        }
+    }
+
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "movie_requests",
+            "Total number of movie service requests",
+            self.requests.clone(),
+        );
+        registry.register(
+            "movie_throttled_requests",
+            "Requests rejected by the per-client rate limit",
+            self.throttled.clone(),
+        );
+        registry.register(
+            "movie_upstream_retries",
+            "Retries of upstream gRPC calls after UNAVAILABLE",
+            self.retries.clone(),
+        );
+        registry.register(
+            "movie_upstream_deadline_exceeded",
+            "Upstream gRPC calls that ran out of time",
+            self.deadline_exceeded.clone(),
+        );
+        registry.register(
+            "movie_upstream_circuit_rejections",
+            "Upstream gRPC calls failed fast by the open circuit breaker",
+            self.circuit_rejections.clone(),
+        );
+        registry.register(
+            "movie_upstream_circuit_state",
+            "Upstream circuit breaker state: 0 closed, 1 half-open, 2 open",
+            self.circuit_state.clone(),
+        );
+        registry.register(
+            "movie_upstream_backend_requests",
+            "Upstream gRPC calls sent to each server replica",
+            self.backend_requests.clone(),
+        );
+        registry.register(
+            "movie_upstream_backend_healthy",
+            "Whether each server replica passed its last health check",
+            self.backend_healthy.clone(),
+        );
+        registry.register(
+            "movie_cache_requests",
+            "Cacheable reads, by resource and whether the cache answered them",
+            self.cache.clone(),
+        );
+        registry.register(
+            "movie_coalesced_requests",
+            "GetMovie lookups that shared an identical call already in flight",
+            self.coalesced.clone(),
+        );
+    }
+
+    fn rpc_counter(family: &Family<RpcLabels, Counter>, rpc: &str) {
+        family
+            .get_or_create(&RpcLabels {
+                rpc: rpc.to_string(),
+            })
+            .inc();
+    }
+
+    pub fn inc_retries(&self, rpc: &str) {
+        Self::rpc_counter(&self.retries, rpc);
+    }
+
+    pub fn inc_deadline_exceeded(&self, rpc: &str) {
+        Self::rpc_counter(&self.deadline_exceeded, rpc);
+    }
+
+    pub fn inc_circuit_rejections(&self, rpc: &str) {
+        Self::rpc_counter(&self.circuit_rejections, rpc);
+    }
+
+    pub fn set_circuit_state(&self, state: BreakerState) {
+        self.circuit_state.set(state.as_gauge());
+    }
+
+    /// The request counter for one replica, to be incremented by whatever
+    /// routes calls to it.
+    pub fn backend_requests(&self, backend: &str) -> Counter {
+        self.backend_requests
+            .get_or_create(&BackendLabels {
+                backend: backend.to_string(),
+            })
+            .clone()
+    }
+
+    pub fn set_backend_healthy(&self, backend: &str, healthy: bool) {
+        self.backend_healthy
+            .get_or_create(&BackendLabels {
+                backend: backend.to_string(),
+            })
+            .set(i64::from(healthy));
+    }
+
+    /// Drops the series of a replica that is no longer discovered.
+    pub fn remove_backend(&self, backend: &str) {
+        let labels = BackendLabels {
+            backend: backend.to_string(),
+        };
+        self.backend_requests.remove(&labels);
+        self.backend_healthy.remove(&labels);
+    }
+
+    pub fn inc_cache(&self, resource: &str, lookup: Lookup) {
+        self.cache
+            .get_or_create(&CacheLabels {
+                resource: resource.to_string(),
+                result: lookup.as_str().to_string(),
+            })
+            .inc();
+    }
+
+    pub fn inc_coalesced(&self) {
+        self.coalesced.inc();
+    }
+
+    pub fn inc_throttled(&self, route: &str, client: &Client) {
+        self.throttled
+            .get_or_create(&ThrottleLabels {
+                route: route.to_string(),
+                client_type: client.kind().to_string(),
+            })
+            .inc();
+    }
+
+    pub fn inc_requests(&self, method: Method, api_key: Option<&str>) {
+        if let Some(api_key) = api_key {
+            self.requests
+                .get_or_create(&MethodLabels {
+                    method,
+                    api_key: api_key.to_string(),
+                })
+                .inc();
+            return;
+        }
+
+        let counter = match method {
+            Method::Get => &self.get,
+            Method::Post => &self.post,
+            Method::Put => &self.put,
+            Method::Patch => &self.patch,
+            Method::Delete => &self.delete,
+        };
+        counter.inc();
+    }
+}
+
+/// State shared by every handler. Cloning it is cheap: the registry is
+/// behind an `Arc` and `MovieService` holds an [`Upstream`] and counters,
+/// so concurrent requests never wait on each other here.
+#[derive(Debug, Clone)]
+pub struct AppState {
+    pub registry: Arc<Registry>,
+    pub movie_service: MovieService,
+    /// Health client on the same upstream as `movie_service`, used by
+    /// `/readyz`.
+    pub upstream_health: HealthClient<Upstream>,
+    /// Verifies bearer tokens on `/movies` routes; `None` disables
+    /// authentication in the gateway.
+    pub auth: Option<JwtVerifier>,
+    pub api_keys: ApiKeys,
+    /// `Authorization` value sent upstream for requests made with an API
+    /// key, which the gRPC server cannot verify itself.
+    pub service_token: Option<HeaderValue>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Cleared once the gateway starts draining on shutdown, so `/readyz`
+    /// takes it out of rotation before connections close.
+    pub ready: Arc<AtomicBool>,
+}
+
+/// Who the HTTP request being handled was made by, as far as the gRPC
+/// calls it makes need to know.
+#[derive(Debug, Clone, Default)]
+struct Caller {
+    /// Passed on to the gRPC server so it can authorize the caller itself.
+    authorization: Option<HeaderValue>,
+    api_key_id: Option<String>,
+    /// Passed on so the gRPC server can rate limit per client rather than
+    /// count the whole gateway as one.
+    client_addr: Option<IpAddr>,
+}
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+fn current_caller() -> Caller {
+    CALLER.try_with(Caller::clone).unwrap_or_default()
+}
+
+struct MetadataMap<'a>(&'a mut tonic::metadata::MetadataMap);
+
+impl<'a> Injector for MetadataMap<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let Ok(key) = tonic::metadata::MetadataKey::from_bytes(key.as_bytes()) {
+            if let Ok(val) = tonic::metadata::MetadataValue::try_from(&value) {
+                self.0.insert(key, val);
+            }
+        }
+    }
+}
+
+pub async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let mut buffer = String::new();
+    encode(&mut buffer, &state.registry).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )
+        .body(Body::from(buffer))
+        .unwrap()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CastMember {
+    name: String,
+    #[serde(default)]
+    character: String,
+}
+
+impl From<CastMember> for movie::CastMember {
+    fn from(member: CastMember) -> Self {
+        Self {
+            name: member.name,
+            character: member.character,
+        }
+    }
+}
+
+impl From<movie::CastMember> for CastMember {
+    fn from(member: movie::CastMember) -> Self {
+        Self {
+            name: member.name,
+            character: member.character,
+        }
+    }
+}
+
+/// Request body for creating or replacing a movie. Older clients send a
+/// single `genre`; when `genres` is also given its first entry wins.
+#[derive(Serialize, Deserialize)]
+pub struct MovieInput {
+    id: Option<String>,
+    title: String,
+    #[serde(default)]
+    genre: String,
+    #[serde(default)]
+    genres: Vec<String>,
+    release_year: Option<i32>,
+    runtime_minutes: Option<i32>,
+    #[serde(default)]
+    directors: Vec<String>,
+    #[serde(default)]
+    cast: Vec<CastMember>,
+    certification: Option<String>,
+    synopsis: Option<String>,
+    language: Option<String>,
+}
+
+impl MovieInput {
+    fn into_movie(self, id: String) -> movie::Movie {
+        movie::Movie {
+            id,
+            title: self.title,
+            genre: self.genre,
+            genres: self.genres,
+            release_year: self.release_year.unwrap_or_default(),
+            runtime_minutes: self.runtime_minutes.unwrap_or_default(),
+            directors: self.directors,
+            cast: self.cast.into_iter().map(movie::CastMember::from).collect(),
+            certification: self.certification.unwrap_or_default(),
+            synopsis: self.synopsis.unwrap_or_default(),
+            language: self.language.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Distinguishes a member explicitly set to `null` (`Some(None)`) from an
+/// absent one, which `#[serde(default)]` leaves as `None`.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// JSON Merge Patch (RFC 7396) body for `PATCH /movies/{id}`. Absent members
+/// are left unchanged and members set to `null` are cleared.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MoviePatch {
+    #[serde(default, deserialize_with = "present")]
+    title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    genre: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    genres: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "present")]
+    release_year: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "present")]
+    runtime_minutes: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "present")]
+    directors: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "present")]
+    cast: Option<Option<Vec<CastMember>>>,
+    #[serde(default, deserialize_with = "present")]
+    certification: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    synopsis: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    language: Option<Option<String>>,
+}
+
+fn patch_field<T: Default>(
+    value: Option<Option<T>>,
+    path: &str,
+    target: &mut T,
+    paths: &mut Vec<String>,
+) {
+    if let Some(value) = value {
+        *target = value.unwrap_or_default();
+        paths.push(path.to_string());
+    }
+}
+
+impl MoviePatch {
+    /// Translates the patch into a sparse movie plus the mask naming the
+    /// fields it sets.
+    fn into_update(self, id: String) -> (movie::Movie, FieldMask) {
+        let mut movie = movie::Movie {
+            id,
+            ..Default::default()
+        };
+        let mut paths = Vec::new();
+
+        patch_field(self.title, "title", &mut movie.title, &mut paths);
+        patch_field(self.genre, "genre", &mut movie.genre, &mut paths);
+        patch_field(self.genres, "genres", &mut movie.genres, &mut paths);
+        patch_field(
+            self.release_year,
+            "release_year",
+            &mut movie.release_year,
+            &mut paths,
+        );
+        patch_field(
+            self.runtime_minutes,
+            "runtime_minutes",
+            &mut movie.runtime_minutes,
+            &mut paths,
+        );
+        patch_field(
+            self.directors,
+            "directors",
+            &mut movie.directors,
+            &mut paths,
+        );
+        let mut cast = Vec::new();
+        patch_field(self.cast, "cast", &mut cast, &mut paths);
+        movie.cast = cast.into_iter().map(movie::CastMember::from).collect();
+        patch_field(
+            self.certification,
+            "certification",
+            &mut movie.certification,
+            &mut paths,
+        );
+        patch_field(self.synopsis, "synopsis", &mut movie.synopsis, &mut paths);
+        patch_field(self.language, "language", &mut movie.language, &mut paths);
+
+        (movie, FieldMask { paths })
+    }
+}
+
+/// JSON shape of a movie. Unknown scalar metadata is omitted rather than
+/// rendered as zero or an empty string.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MovieResponse {
+    id: String,
+    title: String,
+    genre: String,
+    genres: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runtime_minutes: Option<i32>,
+    directors: Vec<String>,
+    cast: Vec<CastMember>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    certification: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    synopsis: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    version: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated_at: Option<String>,
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+impl From<movie::Movie> for MovieResponse {
+    fn from(movie: movie::Movie) -> Self {
+        Self {
+            id: movie.id,
+            title: movie.title,
+            genre: movie.genre,
+            genres: movie.genres,
+            release_year: (movie.release_year != 0).then_some(movie.release_year),
+            runtime_minutes: (movie.runtime_minutes != 0).then_some(movie.runtime_minutes),
+            directors: movie.directors,
+            cast: movie.cast.into_iter().map(CastMember::from).collect(),
+            certification: non_empty(movie.certification),
+            synopsis: non_empty(movie.synopsis),
+            language: non_empty(movie.language),
+            version: movie.version,
+            created_at: movie.created_at.map(|timestamp| timestamp.to_string()),
+            updated_at: movie.updated_at.map(|timestamp| timestamp.to_string()),
+        }
+    }
+}
+
+/// Query parameters accepted by `GET /movies`. `sort` is a field name,
+/// prefixed with `-` for descending order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+pub struct ListMoviesQuery {
+    limit: Option<i32>,
+    cursor: Option<String>,
+    genre: Option<String>,
+    title: Option<String>,
+    sort: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MovieListResponse {
+    movies: Vec<MovieResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchMoviesQuery {
+    q: String,
+    limit: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HighlightResponse {
+    field: String,
+    snippet: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResultResponse {
+    movie: MovieResponse,
+    score: f64,
+    highlights: Vec<HighlightResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResponse {
+    results: Vec<SearchResultResponse>,
+}
+
+/// Request body for `POST /movies:batchGet`.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchGetInput {
+    ids: Vec<String>,
+}
+
+/// Request body for `POST /movies:batch`: movies to create or movies to
+/// delete, but not both. With `atomic`, either every item is applied or
+/// none is.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchInput {
+    #[serde(default)]
+    atomic: bool,
+    #[serde(default)]
+    create: Vec<MovieInput>,
+    #[serde(default)]
+    delete: Vec<BatchDeleteInput>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchDeleteInput {
+    id: String,
+    expected_version: Option<u64>,
+}
+
+/// Why one item of a batch failed, with the same members as a problem
+/// response for the equivalent single request.
+#[derive(Serialize, Deserialize)]
+pub struct BatchItemError {
+    status: u16,
+    grpc_status: i32,
+    detail: String,
+}
+
+impl From<movie::ItemError> for BatchItemError {
+    fn from(error: movie::ItemError) -> Self {
+        Self {
+            status: http_status(tonic::Code::from_i32(error.code)).as_u16(),
+            grpc_status: error.code,
+            detail: error.message,
+        }
+    }
+}
+
+/// Outcome of one batch item: `{"movie": ...}`, `{"success": ...}` for a
+/// delete, or `{"error": ...}`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
+pub enum BatchItemResponse {
+    Movie(MovieResponse),
+    Success(bool),
+    Error(BatchItemError),
+}
+
+impl BatchItemResponse {
+    /// For an item the server sent no outcome for, which only a newer
+    /// server with outcomes unknown to this gateway would do.
+    fn missing() -> Self {
+        BatchItemResponse::Error(BatchItemError {
+            status: StatusCode::BAD_GATEWAY.as_u16(),
+            grpc_status: tonic::Code::Unknown as i32,
+            detail: "The server sent no result for this item".to_string(),
+        })
+    }
+}
+
+impl From<movie::MovieResult> for BatchItemResponse {
+    fn from(result: movie::MovieResult) -> Self {
+        match result.outcome {
+            Some(movie_result::Outcome::Movie(movie)) => BatchItemResponse::Movie(movie.into()),
+            Some(movie_result::Outcome::Error(error)) => BatchItemResponse::Error(error.into()),
+            None => BatchItemResponse::missing(),
+        }
+    }
+}
+
+impl From<movie::DeleteResult> for BatchItemResponse {
+    fn from(result: movie::DeleteResult) -> Self {
+        match result.outcome {
+            Some(delete_result::Outcome::Success(success)) => BatchItemResponse::Success(success),
+            Some(delete_result::Outcome::Error(error)) => BatchItemResponse::Error(error.into()),
+            None => BatchItemResponse::missing(),
+        }
+    }
+}
+
+/// One result per requested item, in request order.
+#[derive(Serialize, Deserialize)]
+pub struct BatchResponse {
+    results: Vec<BatchItemResponse>,
+}
+
+impl<T: Into<BatchItemResponse>> FromIterator<T> for BatchResponse {
+    fn from_iter<I: IntoIterator<Item = T>>(results: I) -> Self {
+        Self {
+            results: results.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Cached `GET /movies/{id}` and `GET /movies` results.
+#[derive(Debug)]
+pub struct MovieCache {
+    pub movies: ResponseCache<String, MovieResponse>,
+    pub lists: ResponseCache<ListMoviesQuery, MovieListResponse>,
+}
+
+impl MovieCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            movies: ResponseCache::new(config),
+            lists: ResponseCache::new(config),
+        }
+    }
+
+    /// Drops everything a change to movie `id` may have made stale: the
+    /// movie itself and every list page, since any of them may include it.
+    pub fn invalidate(&self, id: &str) {
+        self.movies.remove(id);
+        self.lists.clear();
+    }
+
+    pub fn clear(&self) {
+        self.movies.clear();
+        self.lists.clear();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MovieService {
+    grpc_client: MovieServiceClient<Upstream>,
+    metrics: Metrics,
+    resilience: Arc<ResilienceConfig>,
+    breaker: Arc<CircuitBreaker>,
+    cache: Option<Arc<MovieCache>>,
+    lookups: Arc<SingleFlight<LookupKey, Result<MovieResponse, Status>>>,
+    shutdown: CancellationToken,
+}
+
+/// A `GetMovie` call is only shared between callers forwarding the same
+/// credentials, since the server authenticates and rate limits each call.
+type LookupKey = (String, Option<HeaderValue>);
+
+impl MovieService {
+    /// Uses the default deadlines, retry policy and circuit breaker, and
+    /// caches nothing.
+    pub fn new(grpc_client: MovieServiceClient<Upstream>, metrics: Metrics) -> Self {
+        let config = ResilienceConfig::default();
+        Self {
+            grpc_client,
+            metrics,
+            breaker: Arc::new(CircuitBreaker::new(config.breaker)),
+            resilience: Arc::new(config),
+            cache: None,
+            lookups: Arc::default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Serves `read_movie` and `read_movies` from a cache.
+    pub fn with_cache(mut self, config: &CacheConfig) -> Self {
+        self.cache = Some(Arc::new(MovieCache::new(config)));
+        self
+    }
+
+    /// Ends change streams, and stops following them for the cache, once
+    /// `shutdown` is cancelled.
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn with_resilience(mut self, config: ResilienceConfig) -> Self {
+        self.breaker = Arc::new(CircuitBreaker::new(config.breaker));
+        self.resilience = Arc::new(config);
+        self
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    fn get_tracer(&self) -> BoxedTracer {
+        global::tracer("movie-client")
+    }
+
+    pub async fn create_movie(&self, mut input: MovieInput) -> Result<MovieResponse, ApiError> {
+        self.inc_requests(Method::Post);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("CreateMovie")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("movie.title", input.title.clone()),
+                KeyValue::new("movie.genre", input.genre.clone()),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let movie_id = input
+            .id
+            .take()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let mut movie = input.into_movie(movie_id);
+        validation::normalize_movie(&mut movie)
+            .map_err(|status| ApiError::from_grpc(status, &cx))?;
+
+        let id = movie.id.clone();
+        let message = CreateMovieRequest { movie: Some(movie) };
+        let response_result = self
+            .send(
+                &cx,
+                "CreateMovie",
+                message,
+                |mut client, request| async move { client.create_movie(request).await },
+            )
+            .await;
+        self.invalidate(&id);
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Create movie request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => Ok(MovieResponse::from(response.into_inner().movie.unwrap())),
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn get_movie(&self, id: String) -> Result<MovieResponse, ApiError> {
+        self.inc_requests(Method::Get);
+        self.fetch_movie(id).await
+    }
+
+    /// Like `get_movie`, but answered from the cache when there is one,
+    /// unless `bypass` is set. `None` means there is no cache.
+    pub async fn read_movie(
+        &self,
+        id: String,
+        bypass: bool,
+    ) -> Result<(MovieResponse, Option<Freshness>), ApiError> {
+        self.inc_requests(Method::Get);
+        let Some(cache) = &self.cache else {
+            return Ok((self.fetch_movie(id).await?, None));
+        };
+
+        let (movie, freshness) = cache
+            .movies
+            .get_or_fetch(id.clone(), bypass, self.fetch_movie(id))
+            .await?;
+        self.metrics.inc_cache("movie", freshness.lookup);
+        Ok((movie, Some(freshness)))
+    }
+
+    /// Concurrent lookups of the same movie share one `GetMovie` call. Each
+    /// waiting lookup still gets its own span, linked to the shared call's.
+    async fn fetch_movie(&self, id: String) -> Result<MovieResponse, ApiError> {
+        let tracer = self.get_tracer();
+        let key = (id.clone(), current_caller().authorization);
+        loop {
+            let mut span = tracer
+                .span_builder("GetMovie")
+                .with_kind(SpanKind::Client)
+                .with_attributes([
+                    KeyValue::new("component", "grpc"),
+                    KeyValue::new("movie.id", id.clone()),
+                ])
+                .start(&tracer);
+
+            let flight = self.lookups.join(key.clone(), span.span_context().clone());
+            if let Joined::Follower(follower) = &flight {
+                span.add_link(follower.span_context().clone(), Vec::new());
+                span.set_attribute(KeyValue::new("movie.coalesced", true));
+            }
+            let cx = Context::current_with_span(span);
+
+            let result = match flight {
+                Joined::Leader(leader) => {
+                    let result = self.call_get_movie(&cx, id.clone()).await;
+                    leader.finish(result.clone());
+                    result
+                }
+                Joined::Follower(follower) => {
+                    self.metrics.inc_coalesced();
+                    // The request that made the call went away before it
+                    // finished; try again, most likely making the call.
+                    let Some(result) = follower.wait().await else {
+                        continue;
+                    };
+                    result
+                }
+            };
+            self.add_completion_event(&cx, &result, "Get movie request completed".to_string());
+
+            return result.map_err(|status| ApiError::from_grpc(status, &cx));
+        }
+    }
+
+    async fn call_get_movie(&self, cx: &Context, id: String) -> Result<MovieResponse, Status> {
+        validation::validate_id(&id)?;
+
+        let message = ReadMovieRequest { id };
+        let response = self
+            .send(cx, "GetMovie", message, |mut client, request| async move {
+                client.get_movie(request).await
+            })
+            .await?;
+        Ok(MovieResponse::from(response.into_inner().movie.unwrap()))
+    }
+
+    pub async fn list_movies(&self, query: ListMoviesQuery) -> Result<MovieListResponse, ApiError> {
+        self.inc_requests(Method::Get);
+        self.fetch_movies(query).await
+    }
+
+    /// Like `list_movies`, but answered from the cache when there is one,
+    /// unless `bypass` is set. `None` means there is no cache.
+    pub async fn read_movies(
+        &self,
+        query: ListMoviesQuery,
+        bypass: bool,
+    ) -> Result<(MovieListResponse, Option<Freshness>), ApiError> {
+        self.inc_requests(Method::Get);
+        let Some(cache) = &self.cache else {
+            return Ok((self.fetch_movies(query).await?, None));
+        };
+
+        let (movies, freshness) = cache
+            .lists
+            .get_or_fetch(query.clone(), bypass, self.fetch_movies(query))
+            .await?;
+        self.metrics.inc_cache("list", freshness.lookup);
+        Ok((movies, Some(freshness)))
+    }
+
+    async fn fetch_movies(&self, query: ListMoviesQuery) -> Result<MovieListResponse, ApiError> {
+        let order_by = match query.sort.as_deref() {
+            Some(sort) => match sort.strip_prefix('-') {
+                Some(field) => format!("{} desc", field),
+                None => sort.to_string(),
+            },
+            None => String::new(),
+        };
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("ListMovies")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("movies.order_by", order_by.clone()),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let message = movie::ReadMoviesRequest {
+            page_size: query.limit.unwrap_or_default(),
+            page_token: query.cursor.unwrap_or_default(),
+            filter: Some(movie::MovieFilter {
+                genre: query.genre.unwrap_or_default(),
+                title_prefix: query.title.unwrap_or_default(),
+            }),
+            order_by,
+        };
+        let response_result = self
+            .send(
+                &cx,
+                "GetMovies",
+                message,
+                |mut client, request| async move { client.get_movies(request).await },
+            )
+            .await;
+
+        match &response_result {
+            Ok(response) => {
+                cx.span().add_event(
+                    "List movies request completed",
+                    vec![
+                        KeyValue::new("status", "OK"),
+                        KeyValue::new("movie_count", response.get_ref().movies.len() as i64),
+                    ],
+                );
+            }
+            Err(status) => {
+                cx.span().add_event(
+                    "List movies request completed",
+                    vec![KeyValue::new("status", status.code().to_string())],
+                );
+            }
+        }
+
+        match response_result {
+            Ok(response) => {
+                let response = response.into_inner();
+                let movie_responses: Vec<MovieResponse> = response
+                    .movies
+                    .into_iter()
+                    .map(MovieResponse::from)
+                    .collect();
+                Ok(MovieListResponse {
+                    movies: movie_responses,
+                    next_cursor: (!response.next_page_token.is_empty())
+                        .then_some(response.next_page_token),
+                })
+            }
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn update_movie(
+        &self,
+        id: String,
+        input: MovieInput,
+        expected_version: Option<u64>,
+    ) -> Result<MovieResponse, ApiError> {
+        self.inc_requests(Method::Put);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("UpdateMovie")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("movie.id", id.clone()),
+                KeyValue::new("movie.title", input.title.clone()),
+                KeyValue::new("movie.genre", input.genre.clone()),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let mut movie = input.into_movie(id);
+        validation::normalize_movie(&mut movie)
+            .map_err(|status| ApiError::from_grpc(status, &cx))?;
+
+        let id = movie.id.clone();
+        let message = UpdateMovieRequest {
+            movie: Some(movie),
+            expected_version,
+            update_mask: None,
+        };
+        let response_result = self
+            .send(
+                &cx,
+                "UpdateMovie",
+                message,
+                |mut client, request| async move { client.update_movie(request).await },
+            )
+            .await;
+        self.invalidate(&id);
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Update movie request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => Ok(MovieResponse::from(response.into_inner().movie.unwrap())),
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn patch_movie(
+        &self,
+        id: String,
+        patch: MoviePatch,
+        expected_version: Option<u64>,
+    ) -> Result<MovieResponse, ApiError> {
+        validation::validate_id(&id)?;
+        let (movie, update_mask) = patch.into_update(id);
+
+        // An empty mask means a full replace to the server, so a no-op patch
+        // is answered with a read instead.
+        if update_mask.paths.is_empty() {
+            let current = self.get_movie(movie.id).await?;
+            return match expected_version {
+                Some(expected) if expected != current.version => Err(Status::aborted(format!(
+                    "Movie version mismatch: expected {}, current {}",
+                    expected, current.version
+                ))
+                .into()),
+                _ => Ok(current),
+            };
+        }
+
+        self.inc_requests(Method::Patch);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("PatchMovie")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("movie.id", movie.id.clone()),
+                KeyValue::new("movie.update_mask", update_mask.paths.join(",")),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let id = movie.id.clone();
+        let message = UpdateMovieRequest {
+            movie: Some(movie),
+            expected_version,
+            update_mask: Some(update_mask),
+        };
+        let response_result = self
+            .send(
+                &cx,
+                "UpdateMovie",
+                message,
+                |mut client, request| async move { client.update_movie(request).await },
+            )
+            .await;
+        self.invalidate(&id);
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Patch movie request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => Ok(MovieResponse::from(response.into_inner().movie.unwrap())),
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn delete_movie(
+        &self,
+        id: String,
+        expected_version: Option<u64>,
+    ) -> Result<bool, ApiError> {
+        self.inc_requests(Method::Delete);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("DeleteMovie")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("movie.id", id.clone()),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        validation::validate_id(&id).map_err(|status| ApiError::from_grpc(status, &cx))?;
+
+        let message = DeleteMovieRequest {
+            id: id.clone(),
+            expected_version,
+        };
+        let response_result = self
+            .send(
+                &cx,
+                "DeleteMovie",
+                message,
+                |mut client, request| async move { client.delete_movie(request).await },
+            )
+            .await;
+        self.invalidate(&id);
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Delete movie request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => Ok(response.into_inner().success),
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn batch_get_movies(&self, ids: Vec<String>) -> Result<BatchResponse, ApiError> {
+        self.inc_requests(Method::Post);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("BatchGetMovies")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("batch.size", ids.len() as i64),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let message = BatchGetMoviesRequest { ids };
+        let response_result = self
+            .send(
+                &cx,
+                "BatchGetMovies",
+                message,
+                |mut client, request| async move { client.batch_get_movies(request).await },
+            )
+            .await;
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Batch get movies request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => Ok(response.into_inner().results.into_iter().collect()),
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    /// Creates movies in one call. Like `create_movie`, ids are assigned
+    /// here when missing so the cached reads they affect are known, and each
+    /// movie is normalized before it is sent.
+    pub async fn batch_create_movies(
+        &self,
+        inputs: Vec<MovieInput>,
+        atomic: bool,
+    ) -> Result<BatchResponse, ApiError> {
+        self.inc_requests(Method::Post);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("BatchCreateMovies")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("batch.size", inputs.len() as i64),
+                KeyValue::new("batch.atomic", atomic),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let movies: Vec<movie::Movie> = inputs
+            .into_iter()
+            .map(|mut input| {
+                let id = input
+                    .id
+                    .take()
+                    .unwrap_or_else(|| Uuid::new_v4().to_string());
+                let mut movie = input.into_movie(id);
+                // An invalid movie is still sent: the server rejects it as
+                // that item's error, leaving the rest of the batch to it.
+                let _ = validation::normalize_movie(&mut movie);
+                movie
+            })
+            .collect();
+        let ids: Vec<String> = movies.iter().map(|movie| movie.id.clone()).collect();
+
+        let message = BatchCreateMoviesRequest { movies, atomic };
+        let response_result = self
+            .send(
+                &cx,
+                "BatchCreateMovies",
+                message,
+                |mut client, request| async move { client.batch_create_movies(request).await },
+            )
+            .await;
+        for id in &ids {
+            self.invalidate(id);
+        }
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Batch create movies request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => Ok(response.into_inner().results.into_iter().collect()),
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn batch_delete_movies(
+        &self,
+        inputs: Vec<BatchDeleteInput>,
+        atomic: bool,
+    ) -> Result<BatchResponse, ApiError> {
+        self.inc_requests(Method::Post);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("BatchDeleteMovies")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("batch.size", inputs.len() as i64),
+                KeyValue::new("batch.atomic", atomic),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let deletes: Vec<DeleteMovieRequest> = inputs
+            .into_iter()
+            .map(|input| DeleteMovieRequest {
+                id: input.id,
+                expected_version: input.expected_version,
+            })
+            .collect();
+        let ids: Vec<String> = deletes.iter().map(|delete| delete.id.clone()).collect();
+
+        let message = BatchDeleteMoviesRequest { deletes, atomic };
+        let response_result = self
+            .send(
+                &cx,
+                "BatchDeleteMovies",
+                message,
+                |mut client, request| async move { client.batch_delete_movies(request).await },
+            )
+            .await;
+        for id in &ids {
+            self.invalidate(id);
+        }
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Batch delete movies request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => Ok(response.into_inner().results.into_iter().collect()),
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn search_movies(
+        &self,
+        query: SearchMoviesQuery,
+    ) -> Result<SearchResponse, ApiError> {
+        self.inc_requests(Method::Get);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("SearchMovies")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("component", "grpc"),
+                KeyValue::new("search.query", query.q.clone()),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let message = movie::SearchMoviesRequest {
+            query: query.q,
+            limit: query.limit.unwrap_or_default(),
+        };
+        let response_result = self
+            .send(
+                &cx,
+                "SearchMovies",
+                message,
+                |mut client, request| async move { client.search_movies(request).await },
+            )
+            .await;
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Search movies request completed".to_string(),
+        );
+
+        match response_result {
+            Ok(response) => {
+                let results = response
+                    .into_inner()
+                    .results
+                    .into_iter()
+                    .filter_map(|result| {
+                        let movie = result.movie?;
+                        Some(SearchResultResponse {
+                            movie: MovieResponse::from(movie),
+                            score: result.score,
+                            highlights: result
+                                .highlights
+                                .into_iter()
+                                .map(|highlight| HighlightResponse {
+                                    field: highlight.field,
+                                    snippet: highlight.snippet,
+                                })
+                                .collect(),
+                        })
+                    })
+                    .collect();
+                Ok(SearchResponse { results })
+            }
+            Err(status) => Err(ApiError::from_grpc(status, &cx)),
+        }
+    }
+
+    pub async fn watch_movies(
+        &self,
+        after: Option<Position>,
+    ) -> Result<Streaming<movie::MovieEvent>, ApiError> {
+        self.inc_requests(Method::Get);
+
+        let tracer = self.get_tracer();
+        let span = tracer
+            .span_builder("WatchMovies")
+            .with_kind(SpanKind::Client)
+            .with_attributes([KeyValue::new("component", "grpc")])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+
+        let message = movie::WatchMoviesRequest {
+            after_revision: after.map(|after| after.revision),
+            epoch: after.map_or(0, |after| after.epoch),
+        };
+        let response_result = self
+            .send(
+                &cx,
+                "WatchMovies",
+                message,
+                |mut client, request| async move { client.watch_movies(request).await },
+            )
+            .await;
+        self.add_completion_event(
+            &cx,
+            &response_result,
+            "Watch movies request completed".to_string(),
+        );
+
+        response_result
+            .map(|response| response.into_inner())
+            .map_err(|status| ApiError::from_grpc(status, &cx))
+    }
+
+    /// Drops cached reads that a write to movie `id` may have made stale,
+    /// and keeps later lookups from sharing a `GetMovie` call made before
+    /// it. Called whatever the outcome, since a write that failed on the
+    /// way back (for example by timing out) may still have been applied.
+    fn invalidate(&self, id: &str) {
+        self.lookups.forget(|(movie_id, _)| movie_id == id);
+        if let Some(cache) = &self.cache {
+            cache.invalidate(id);
+        }
+    }
+
+    /// Follows the server's change stream until shutdown, dropping cached
+    /// reads of every movie reported as changed. After the stream fails it
+    /// reconnects, resuming after the last event seen; if that event is no
+    /// longer retained, or the server has restarted since, the whole cache
+    /// is dropped instead. Returns at
+    /// once without a cache.
+    pub async fn invalidate_cache_from_events(self) {
+        let Some(cache) = self.cache.clone() else {
+            return;
+        };
+        self.shutdown
+            .run_until_cancelled(self.follow_events(&cache))
+            .await;
+    }
+
+    async fn follow_events(&self, cache: &MovieCache) {
+        let mut after = None;
+        let mut delay = WATCH_RETRY_INITIAL;
+        loop {
+            match self.watch_movies(after).await {
+                Ok(mut events) => {
+                    delay = WATCH_RETRY_INITIAL;
+                    while let Some(item) = events.next().await {
+                        match item {
+                            Ok(event) => {
+                                after = Some(Position::of(&event));
+                                if let Some(movie) = &event.movie {
+                                    self.invalidate(&movie.id);
+                                }
+                            }
+                            Err(status) => {
+                                tracing::warn!("Movie change stream failed: {}", status.message());
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(error) if error.code == Some(tonic::Code::OutOfRange) => {
+                    tracing::warn!("Missed movie changes, dropping the whole cache");
+                    self.lookups.forget(|_| true);
+                    cache.clear();
+                    after = None;
+                    continue;
+                }
+                Err(error) => {
+                    tracing::warn!("Cannot watch movie changes: {}", error.detail);
+                }
+            }
+
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(WATCH_RETRY_MAX);
+        }
+    }
+
+    /// Sends one RPC with its deadline, through the circuit breaker,
+    /// retrying idempotent RPCs on `UNAVAILABLE` with jittered backoff for
+    /// as long as the deadline allows. `call` is invoked once per attempt.
+    async fn send<M, R, F, Fut>(
+        &self,
+        cx: &Context,
+        rpc: &'static str,
+        message: M,
+        mut call: F,
+    ) -> Result<tonic::Response<R>, Status>
+    where
+        M: Clone,
+        F: FnMut(MovieServiceClient<Upstream>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<R>, Status>>,
+    {
+        let deadline = self.resilience.deadline_for(rpc);
+        let retry = self.resilience.retry;
+        let started = Instant::now();
+        let remaining = || deadline.map(|deadline| deadline.saturating_sub(started.elapsed()));
+        let mut attempt = 1;
+
+        loop {
+            if let Err(retry_after) = self.breaker.admit() {
+                self.metrics.inc_circuit_rejections(rpc);
+                cx.span().add_event(
+                    "Circuit open, failing fast",
+                    vec![KeyValue::new(
+                        "retry_after_ms",
+                        retry_after.as_millis() as i64,
+                    )],
+                );
+                return Err(Status::with_error_details(
+                    tonic::Code::Unavailable,
+                    "Movie service is unavailable",
+                    ErrorDetails::with_retry_info(Some(retry_after)),
+                ));
+            }
+
+            let mut request = Request::new(message.clone());
+            self.inject_metadata(cx, &mut request);
+            let result = match remaining() {
+                Some(remaining) => {
+                    // Sent as `grpc-timeout` so the server can give up too.
+                    request.set_timeout(remaining);
+                    tokio::time::timeout(remaining, call(self.grpc_client.clone(), request))
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(Status::deadline_exceeded(
+                                "Deadline exceeded calling the movie service",
+                            ))
+                        })
+                }
+                None => call(self.grpc_client.clone(), request).await,
+            };
+
+            let code = result.as_ref().err().map(Status::code);
+            self.breaker
+                .record(code.is_some_and(resilience::is_upstream_failure));
+            self.metrics.set_circuit_state(self.breaker.state());
+
+            match code {
+                Some(tonic::Code::Unavailable)
+                    if self.resilience.is_retryable(rpc) && attempt < retry.max_attempts =>
+                {
+                    let backoff = retry.backoff(attempt);
+                    if remaining().is_some_and(|remaining| backoff >= remaining) {
+                        return result;
+                    }
+                    self.metrics.inc_retries(rpc);
+                    cx.span().add_event(
+                        "Retrying after UNAVAILABLE",
+                        vec![
+                            KeyValue::new("attempt", attempt as i64),
+                            KeyValue::new("backoff_ms", backoff.as_millis() as i64),
+                        ],
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Some(tonic::Code::DeadlineExceeded) => {
+                    self.metrics.inc_deadline_exceeded(rpc);
+                    cx.span().add_event(
+                        "Deadline exceeded",
+                        vec![KeyValue::new("attempt", attempt as i64)],
+                    );
+                    return result;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    fn inc_requests(&self, method: Method) {
+        self.metrics
+            .inc_requests(method, current_caller().api_key_id.as_deref());
+    }
+
+    /// Adds the trace context and the caller's bearer token, if any, to an
+    /// outgoing gRPC request, along with the caller's address. For an API
+    /// key caller, also names the key to the server and tags the call's span
+    /// with it.
+    fn inject_metadata<T>(&self, cx: &Context, request: &mut Request<T>) {
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(cx, &mut MetadataMap(request.metadata_mut()))
+        });
+
+        let caller = current_caller();
+        if let Some(id) = caller.api_key_id {
+            if let Ok(value) = AsciiMetadataValue::try_from(id.as_str()) {
+                request.metadata_mut().insert(API_KEY_ID_METADATA, value);
+            }
+            cx.span().set_attribute(KeyValue::new("api_key.id", id));
+        }
+        if let Some(addr) = caller.client_addr {
+            if let Ok(value) = AsciiMetadataValue::try_from(addr.to_string()) {
+                request.metadata_mut().insert(CLIENT_ADDR_METADATA, value);
+            }
+        }
+        if let Some(value) = caller
+            .authorization
+            .and_then(|value| AsciiMetadataValue::try_from(value.as_bytes()).ok())
+        {
+            request.metadata_mut().insert("authorization", value);
+        }
+    }
+
+    fn add_completion_event<T>(
+        &self,
+        cx: &Context,
+        result: &Result<T, Status>,
+        event_name: String,
+    ) {
+        let status = match result {
+            Ok(_) => "OK".to_string(),
+            Err(status) => status.code().to_string(),
+        };
+
+        cx.span()
+            .add_event(event_name, vec![KeyValue::new("status", status)]);
+    }
+}
+
+/// Maps a gRPC status code to the HTTP status the gateway responds with.
+fn http_status(code: tonic::Code) -> StatusCode {
+    match code {
+        tonic::Code::Ok => StatusCode::OK,
+        tonic::Code::Cancelled => StatusCode::from_u16(499).expect("499 is a valid status code"),
+        tonic::Code::InvalidArgument
+        | tonic::Code::FailedPrecondition
+        | tonic::Code::OutOfRange => StatusCode::BAD_REQUEST,
+        tonic::Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        tonic::Code::PermissionDenied => StatusCode::FORBIDDEN,
+        tonic::Code::NotFound => StatusCode::NOT_FOUND,
+        tonic::Code::AlreadyExists | tonic::Code::Aborted => StatusCode::CONFLICT,
+        tonic::Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        tonic::Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        tonic::Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        tonic::Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        tonic::Code::Unknown | tonic::Code::Internal | tonic::Code::DataLoss => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldViolationResponse {
+    field: String,
+    description: String,
+}
+
+/// Gateway error, rendered as an RFC 7807 `application/problem+json` body.
+/// Errors from the movie service carry the trace id of the failed call so
+/// they can be looked up in Jaeger.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    detail: String,
+    code: Option<tonic::Code>,
+    trace_id: Option<String>,
+    violations: Vec<FieldViolationResponse>,
+    /// Sent as `Retry-After` when the request was rate limited or the
+    /// upstream circuit is open.
+    retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+            code: None,
+            trace_id: None,
+            violations: Vec::new(),
+            retry_after: None,
+        }
+    }
+
+    /// Wraps a status returned while `cx` was the active call context.
+    pub fn from_grpc(status: Status, cx: &Context) -> Self {
+        let mut error = Self::from(status);
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            error.trace_id = Some(span_context.trace_id().to_string());
+        }
+        error
+    }
+
+    /// On a request made conditional with `If-Match`, a version conflict
+    /// or a missing movie is a failed precondition rather than a plain
+    /// conflict or 404, since no tag (not even `*`) matches a movie that
+    /// does not exist.
+    fn conditional(mut self, conditional: bool) -> Self {
+        if conditional
+            && matches!(
+                self.code,
+                Some(tonic::Code::Aborted | tonic::Code::NotFound)
+            )
+        {
+            self.status = StatusCode::PRECONDITION_FAILED;
+        }
+        self
+    }
+}
+
+impl From<Status> for ApiError {
+    /// `INVALID_ARGUMENT` with `google.rpc.BadRequest` details becomes 422
+    /// listing each field violation; everything else goes through
+    /// `http_status`. A `google.rpc.RetryInfo` delay on
+    /// `RESOURCE_EXHAUSTED` or `UNAVAILABLE` is passed on as `Retry-After`.
+    fn from(status: Status) -> Self {
+        let violations: Vec<FieldViolationResponse> =
+            if status.code() == tonic::Code::InvalidArgument {
+                status
+                    .get_details_bad_request()
+                    .map(|bad_request| bad_request.field_violations)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|violation| FieldViolationResponse {
+                        field: violation.field,
+                        description: violation.description,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+        let http_status = if violations.is_empty() {
+            http_status(status.code())
+        } else {
+            StatusCode::UNPROCESSABLE_ENTITY
+        };
+
+        let retry_after = if matches!(
+            status.code(),
+            tonic::Code::ResourceExhausted | tonic::Code::Unavailable
+        ) {
+            status
+                .get_details_retry_info()
+                .and_then(|retry_info| retry_info.retry_delay)
+        } else {
+            None
+        };
+
+        Self {
+            status: http_status,
+            detail: status.message().to_string(),
+            code: Some(status.code()),
+            trace_id: None,
+            violations,
+            retry_after,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "type": "about:blank",
+            "title": self.status.canonical_reason().unwrap_or("Error"),
+            "status": self.status.as_u16(),
+            "detail": self.detail,
+        });
+        if let Some(code) = self.code {
+            body["grpc_status"] = json!(code as i32);
+        }
+        if let Some(trace_id) = self.trace_id {
+            body["trace_id"] = json!(trace_id);
+        }
+        if !self.violations.is_empty() {
+            body["violations"] = json!(self.violations);
+        }
+
+        let mut response = (
+            self.status,
+            [(CONTENT_TYPE, "application/problem+json")],
+            Json(body),
+        )
+            .into_response();
+        if let Some(retry_after) = self.retry_after {
+            response.headers_mut().insert(
+                RETRY_AFTER,
+                HeaderValue::from(rate_limit::retry_after_secs(retry_after)),
+            );
+        }
+        response
+    }
+}
+
+pub async fn create_movie(
+    State(state): State<AppState>,
+    Json(input): Json<MovieInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    let movie = state.movie_service.create_movie(input).await?;
+    Ok(Json(json!(movie)))
+}
+
+fn entity_tag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Reads `If-Match` as the version a write is conditional on. An absent
+/// header or `*` imposes no version, though `*` still requires the movie to
+/// exist; only a single strong tag is supported.
+fn if_match_version(headers: &HeaderMap) -> Result<Option<u64>, ApiError> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "Invalid If-Match"))?
+        .trim();
+
+    if value == "*" {
+        return Ok(None);
+    }
+    if value.contains(',') {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "If-Match must be a single entity tag or *",
+        ));
+    }
+
+    // Weak tags never match under strong comparison.
+    value
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|tag| tag.parse::<u64>().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::PRECONDITION_FAILED,
+                "If-Match does not match the current entity tag",
+            )
+        })
+}
+
+/// Whether `If-None-Match` lists `etag` (or `*`), using weak comparison.
+fn if_none_match_hit(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        })
+}
+
+fn cache_bypass(headers: &HeaderMap) -> bool {
+    headers.contains_key(CACHE_BYPASS_HEADER)
+}
+
+/// `Cache-Control` for how long clients may reuse a cacheable read, plus
+/// `Age` when it came from the cache and `X-Cache` saying whether it did.
+/// Nothing is added when the gateway does not cache.
+fn cache_headers(freshness: Option<Freshness>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let Some(freshness) = freshness else {
+        return headers;
+    };
+
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_str(&format!("max-age={}", freshness.max_age().as_secs()))
+            .expect("valid header value"),
+    );
+    if freshness.lookup == Lookup::Hit {
+        headers.insert(AGE, HeaderValue::from(freshness.age.as_secs()));
+    }
+    headers.insert(
+        CACHE_STATUS_HEADER,
+        HeaderValue::from_static(match freshness.lookup {
+            Lookup::Hit => "HIT",
+            Lookup::Miss => "MISS",
+            Lookup::Bypass => "BYPASS",
+        }),
+    );
+    headers
+}
+
+pub async fn get_movie(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (movie, freshness) = state
+        .movie_service
+        .read_movie(id, cache_bypass(&headers))
+        .await?;
+    let etag = entity_tag(movie.version);
+    let cache_headers = cache_headers(freshness);
+    if if_none_match_hit(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers, [(ETAG, etag)]).into_response());
+    }
+    Ok((cache_headers, [(ETAG, etag)], Json(json!(movie))).into_response())
+}
+
+pub async fn list_movies(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListMoviesQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (movies, freshness) = state
+        .movie_service
+        .read_movies(query, cache_bypass(&headers))
+        .await?;
+    Ok((
+        cache_headers(freshness),
+        Json(serde_json::to_value(movies).unwrap()),
+    ))
+}
+
+pub async fn search_movies(
+    State(state): State<AppState>,
+    Query(query): Query<SearchMoviesQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let results = state.movie_service.search_movies(query).await?;
+    Ok(Json(json!(results)))
+}
+
+fn movie_event_to_sse(event: movie::MovieEvent) -> Event {
+    let name = match event.r#type() {
+        movie::MovieEventType::Created => "created",
+        movie::MovieEventType::Updated => "updated",
+        movie::MovieEventType::Deleted => "deleted",
+        movie::MovieEventType::Unspecified => "unspecified",
+    };
+    let position = Position::of(&event);
+    let movie = event.movie.unwrap_or_default();
+
+    Event::default()
+        .id(position.to_string())
+        .event(name)
+        .json_data(MovieResponse::from(movie))
+        .expect("movie serializes to JSON")
+}
+
+/// Streams movie changes as Server-Sent Events. Each event id is the change's
+/// `<epoch>-<revision>`, so browsers reconnecting with `Last-Event-ID` resume
+/// where they left off; if that event is no longer retained, or the server
+/// has restarted since, the request fails with 410 and the client must
+/// reload its view. The stream ends when the gateway shuts down.
+pub async fn movie_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let after = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<Position>().ok())
+                .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Invalid Last-Event-ID"))?,
+        ),
+        None => None,
+    };
+
+    let events = match state.movie_service.watch_movies(after).await {
+        Ok(events) => events,
+        Err(mut error) if error.code == Some(tonic::Code::OutOfRange) => {
+            error.status = StatusCode::GONE;
+            return Err(error);
+        }
+        Err(error) => return Err(error),
+    };
+
+    let events = shutdown::until_shutdown(events, state.movie_service.shutdown.clone());
+    let stream = events.map(|item| {
+        Ok(match item {
+            Ok(event) => movie_event_to_sse(event),
+            Err(status) => Event::default().event("error").data(status.message()),
+        })
+    });
+
+    Ok(Sse::new(stream).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("heartbeat"),
+    ))
+}
+
+pub async fn update_movie(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(input): Json<MovieInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    let expected_version = if_match_version(&headers)?;
+    let movie = state
+        .movie_service
+        .update_movie(id, input, expected_version)
+        .await
+        .map_err(|error| error.conditional(headers.contains_key(IF_MATCH)))?;
+    Ok(([(ETAG, entity_tag(movie.version))], Json(json!(movie))))
+}
+
+/// Applies a JSON Merge Patch. An empty patch changes nothing but still
+/// returns the current movie.
+pub async fn patch_movie(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(patch): Json<MoviePatch>,
+) -> Result<impl IntoResponse, ApiError> {
+    let expected_version = if_match_version(&headers)?;
+    let movie = state
+        .movie_service
+        .patch_movie(id, patch, expected_version)
+        .await
+        .map_err(|error| error.conditional(headers.contains_key(IF_MATCH)))?;
+    Ok(([(ETAG, entity_tag(movie.version))], Json(json!(movie))))
+}
+
+pub async fn delete_movie(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let expected_version = if_match_version(&headers)?;
+    let success = state
+        .movie_service
+        .delete_movie(id, expected_version)
+        .await
+        .map_err(|error| error.conditional(headers.contains_key(IF_MATCH)))?;
+    if !success && headers.contains_key(IF_MATCH) {
+        return Err(ApiError::new(
+            StatusCode::PRECONDITION_FAILED,
+            "If-Match does not match the current entity tag",
+        ));
+    }
+    Ok(Json(json!({ "success": success })))
+}
+
+pub async fn batch_get_movies(
+    State(state): State<AppState>,
+    Json(input): Json<BatchGetInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    let response = state.movie_service.batch_get_movies(input.ids).await?;
+    Ok(Json(json!(response)))
+}
+
+/// Creates or deletes movies in one request, authorized like the equivalent
+/// `POST /movies` or `DELETE /movies/{id}` requests.
+pub async fn batch_movies(
+    State(state): State<AppState>,
+    extensions: Extensions,
+    Json(input): Json<BatchInput>,
+) -> Result<Response, Response> {
+    let BatchInput {
+        atomic,
+        create,
+        delete,
+    } = input;
+    if !create.is_empty() && !delete.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Send creates and deletes in separate batches",
+        )
+        .into_response());
+    }
+
+    let required = if delete.is_empty() { WRITE } else { DELETE };
+    if let Some(key) = extensions.get::<ApiKey>() {
+        check_scope(key, required)?;
+    } else if state.auth.is_some() {
+        check_role(extensions.get::<Identity>(), required)?;
+    }
+
+    let response = if !delete.is_empty() {
+        state
+            .movie_service
+            .batch_delete_movies(delete, atomic)
+            .await
+    } else if !create.is_empty() {
+        state
+            .movie_service
+            .batch_create_movies(create, atomic)
+            .await
+    } else {
+        Ok(BatchResponse {
+            results: Vec::new(),
+        })
+    };
+    let response = response.map_err(IntoResponse::into_response)?;
+    Ok(Json(json!(response)).into_response())
+}
+
+/// Liveness: the gateway process is up and serving HTTP.
+pub async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness: the gateway is not draining and a server replica, picked like
+/// any other call, reports `movie.MovieService` as `SERVING` on its health
+/// service. Unhealthy replicas are out of rotation, so this fails only when
+/// none are healthy.
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    if !state.ready.load(Ordering::Relaxed) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "ready": false, "draining": true })),
+        )
+            .into_response();
+    }
+
+    let mut client = state.upstream_health.clone();
+    let request = Request::new(HealthCheckRequest {
+        service: SERVICE_NAME.to_string(),
+    });
+
+    let (status, upstream) =
+        match tokio::time::timeout(READINESS_TIMEOUT, client.check(request)).await {
+            Ok(Ok(response)) => match response.into_inner().status() {
+                ServingStatus::Serving => (StatusCode::OK, "SERVING".to_string()),
+                other => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    other.as_str_name().to_string(),
+                ),
+            },
+            Ok(Err(status)) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("UNREACHABLE: {}", status.message()),
+            ),
+            Err(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "UNREACHABLE: health check timed out".to_string(),
+            ),
+        };
+
+    (
+        status,
+        Json(json!({ "ready": status.is_success(), "upstream": upstream })),
+    )
+        .into_response()
+}
+
+/// What a route asks of its caller: a role when authenticated with a bearer
+/// token, or a scope when authenticated with an API key.
+#[derive(Debug, Clone, Copy)]
+struct Requirement {
+    role: Role,
+    scope: Scope,
+}
+
+/// Managing API keys needs `admin` or the `keys:admin` scope.
+const ADMIN: Requirement = Requirement {
+    role: Role::Admin,
+    scope: Scope::KeysAdmin,
+};
+
+const WRITE: Requirement = Requirement {
+    role: Role::Editor,
+    scope: Scope::MoviesWrite,
+};
+
+const DELETE: Requirement = Requirement {
+    role: Role::Admin,
+    scope: Scope::MoviesDelete,
+};
+
+/// Requirement of a request to a `/movies` route: reads (including
+/// `POST /movies:batchGet`) are open, writes need `editor` or
+/// `movies:write`, and deletes need `admin` or `movies:delete`. What a
+/// `POST /movies:batch` needs depends on its body, so its handler checks.
+fn movies_requirement(method: &HttpMethod, path: Option<&str>) -> Option<Requirement> {
+    match *method {
+        HttpMethod::POST if matches!(path, Some("/movies:batchGet" | "/movies:batch")) => None,
+        HttpMethod::POST | HttpMethod::PUT | HttpMethod::PATCH => Some(WRITE),
+        HttpMethod::DELETE => Some(DELETE),
+        _ => None,
+    }
+}
+
+fn unauthorized(detail: impl Into<String>) -> Response {
+    (
+        [(WWW_AUTHENTICATE, "Bearer")],
+        ApiError::new(StatusCode::UNAUTHORIZED, detail),
+    )
+        .into_response()
+}
+
+fn forbidden(detail: impl Into<String>) -> Response {
+    ApiError::new(StatusCode::FORBIDDEN, detail).into_response()
+}
+
+fn check_scope(key: &ApiKey, required: Requirement) -> Result<(), Response> {
+    if !key.has_scope(required.scope) {
+        return Err(forbidden(format!(
+            "Scope {} is required",
+            required.scope.as_str()
+        )));
+    }
+    Ok(())
+}
+
+fn check_role(identity: Option<&Identity>, required: Requirement) -> Result<(), Response> {
+    match identity {
+        None => Err(unauthorized("A bearer token or API key is required")),
+        Some(identity) if identity.role < required.role => Err(forbidden(format!(
+            "Role {} is required",
+            required.role.as_str()
+        ))),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Authenticates the caller and checks them against `required`. An
+/// `X-API-Key` header takes precedence over `Authorization`; calls made with
+/// a key are forwarded upstream with the service token instead of the
+/// caller's own credentials, naming the key alongside. A bearer token is forwarded even when the
+/// gateway does not verify tokens itself, so the server still enforces its
+/// own policy.
+async fn identify(
+    state: &AppState,
+    request: &mut axum::extract::Request,
+    required: Option<Requirement>,
+) -> Result<Caller, Response> {
+    // Copied out so no borrow of the (non-`Sync`) request is held across
+    // the store lookup.
+    let presented = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .map(|value| value.to_str().map(|value| value.trim().to_string()));
+    if let Some(presented) = presented {
+        let authenticated = match presented {
+            Ok(presented) => state.api_keys.authenticate(&presented).await,
+            Err(_) => Err(ApiKeyError::Malformed),
+        };
+        let key = match authenticated {
+            Ok(key) => key,
+            Err(ApiKeyError::Storage(e)) => {
+                tracing::error!("API key lookup failed: {}", e);
+                return Err(ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "API keys cannot be checked right now",
+                )
+                .into_response());
+            }
+            Err(e) => return Err(unauthorized(e.to_string())),
+        };
+
+        if let Some(required) = required {
+            check_scope(&key, required)?;
+        }
+
+        let caller = Caller {
+            authorization: state.service_token.clone(),
+            api_key_id: Some(key.id.clone()),
+            client_addr: client_addr(request),
+        };
+        request.extensions_mut().insert(key);
+        return Ok(caller);
+    }
+
+    let authorization = request.headers().get(AUTHORIZATION).cloned();
+
+    if let Some(verifier) = &state.auth {
+        let identity = match &authorization {
+            Some(value) => {
+                let verified = value
+                    .to_str()
+                    .map_err(|_| AuthError::Malformed)
+                    .and_then(|value| verifier.verify(value));
+                match verified {
+                    Ok(identity) => Some(identity),
+                    Err(e) => return Err(unauthorized(e.to_string())),
+                }
+            }
+            None => None,
+        };
+
+        if let Some(required) = required {
+            check_role(identity.as_ref(), required)?;
+        }
+
+        if let Some(identity) = identity {
+            request.extensions_mut().insert(identity);
+        }
+    }
+
+    Ok(Caller {
+        authorization,
+        api_key_id: None,
+        client_addr: client_addr(request),
+    })
+}
+
+async fn run_as_caller(
+    state: &AppState,
+    mut request: axum::extract::Request,
+    required: Option<Requirement>,
+    next: Next,
+) -> Response {
+    match identify(state, &mut request, required).await {
+        Ok(caller) => CALLER.scope(caller, next.run(request)).await,
+        Err(response) => response,
+    }
+}
+
+/// Authenticates and authorizes `/movies` requests, then runs the handler
+/// with the caller available to the gRPC calls it makes.
+pub async fn authenticate(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let required = movies_requirement(request.method(), path.as_deref());
+    run_as_caller(&state, request, required, next).await
+}
+
+/// Like [`authenticate`], for the `/api-keys` routes. Unlike `/movies`,
+/// these always need an admin credential: keys act upstream with the service
+/// token, so with authentication disabled in the gateway only an existing
+/// key with the `keys:admin` scope may manage them.
+pub async fn authenticate_admin(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    if state.auth.is_none() && !request.headers().contains_key(API_KEY_HEADER) {
+        return unauthorized("An API key with the keys:admin scope is required");
+    }
+    run_as_caller(&state, request, Some(ADMIN), next).await
+}
+
+fn client_addr(request: &axum::extract::Request) -> Option<IpAddr> {
+    let ConnectInfo(addr) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+    Some(addr.ip())
+}
+
+fn route(request: &axum::extract::Request) -> Option<String> {
+    let path = request.extensions().get::<MatchedPath>()?;
+    Some(format!("{} {}", request.method(), path.as_str()))
+}
+
+fn throttled(state: &AppState, route: &str, client: &Client, retry_after: Duration) -> Response {
+    state.movie_service.metrics().inc_throttled(route, client);
+    let mut error = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded");
+    error.retry_after = Some(retry_after);
+    error.into_response()
+}
+
+/// Runs before authentication and turns away addresses whose limit for the
+/// matched route is used up, without counting the request itself. Requests
+/// whose credentials are rejected count against their address, so guessing
+/// API keys or tokens is limited like any unauthenticated traffic.
+pub async fn throttle_address(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let Some(route) = route(&request) else {
+        return next.run(request).await;
+    };
+    let client = client_addr(&request).map_or(Client::Unknown, Client::Ip);
+
+    if let Err(retry_after) = state.rate_limiter.peek(&route, &client) {
+        return throttled(&state, &route, &client, retry_after);
+    }
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        let _ = state.rate_limiter.check(&route, &client);
+    }
+    response
+}
+
+/// Counts the request against its caller's limit for the matched route.
+/// Runs after authentication, so callers are told apart by API key, then by
+/// verified token subject, then by peer address.
+pub async fn throttle(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let Some(route) = route(&request) else {
+        return next.run(request).await;
+    };
+
+    let extensions = request.extensions();
+    let client = if let Some(key) = extensions.get::<ApiKey>() {
+        Client::ApiKey(key.id.clone())
+    } else if let Some(identity) = extensions.get::<Identity>() {
+        Client::Subject(identity.subject.clone())
+    } else {
+        client_addr(&request).map_or(Client::Unknown, Client::Ip)
+    };
+
+    match state.rate_limiter.check(&route, &client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => throttled(&state, &route, &client, retry_after),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateApiKeyInput {
+    name: String,
+    #[serde(default)]
+    scopes: Vec<Scope>,
+    /// RFC 3339 time after which the key stops working.
+    expires_at: Option<String>,
+}
+
+/// A stored key as shown to administrators; never includes the key itself
+/// or its hash.
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    id: String,
+    name: String,
+    scopes: Vec<Scope>,
+    status: &'static str,
+    created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revoked_at: Option<String>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        let status = if key.revoked_at.is_some() {
+            "revoked"
+        } else if key.is_expired(SystemTime::now()) {
+            "expired"
+        } else {
+            "active"
+        };
+
+        Self {
+            id: key.id,
+            name: key.name,
+            scopes: key.scopes,
+            status,
+            created_at: rfc3339(key.created_at),
+            expires_at: key.expires_at.map(rfc3339),
+            revoked_at: key.revoked_at.map(rfc3339),
+        }
+    }
+}
+
+/// Response to creating a key: the only time the full key is returned.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyResponse {
+    #[serde(flatten)]
+    key: ApiKeyResponse,
+    api_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyListResponse {
+    api_keys: Vec<ApiKeyResponse>,
+}
+
+fn invalid_field(field: &str, description: &str) -> ApiError {
+    let mut error = ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "Invalid API key request");
+    error.violations.push(FieldViolationResponse {
+        field: field.to_string(),
+        description: description.to_string(),
+    });
+    error
+}
+
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    Json(input): Json<CreateApiKeyInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = input.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_API_KEY_NAME_CHARS {
+        return Err(invalid_field(
+            "name",
+            &format!("must be 1 to {} characters", MAX_API_KEY_NAME_CHARS),
+        ));
+    }
+
+    let expires_at = match input.expires_at.as_deref() {
+        Some(value) => {
+            let expires_at = parse_rfc3339(value)
+                .ok_or_else(|| invalid_field("expires_at", "must be an RFC 3339 time"))?;
+            if expires_at <= SystemTime::now() {
+                return Err(invalid_field("expires_at", "must be in the future"));
+            }
+            Some(expires_at)
+        }
+        None => None,
+    };
+
+    let (key, api_key) = state
+        .api_keys
+        .create(name, input.scopes, expires_at)
+        .await
+        .map_err(Status::from)?;
+    tracing::info!("Created API key {}", key.id);
+
+    Ok((
+        StatusCode::CREATED,
+        [(LOCATION, format!("/api-keys/{}", key.id))],
+        Json(CreatedApiKeyResponse {
+            key: ApiKeyResponse::from(key),
+            api_key,
+        }),
+    ))
+}
+
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+) -> Result<Json<ApiKeyListResponse>, ApiError> {
+    let keys = state.api_keys.list().await.map_err(Status::from)?;
+    Ok(Json(ApiKeyListResponse {
+        api_keys: keys.into_iter().map(ApiKeyResponse::from).collect(),
+    }))
+}
+
+/// Revokes a key; it keeps being listed with status `revoked`.
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if !state.api_keys.revoke(&id).await.map_err(Status::from)? {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "API key not found"));
+    }
+    tracing::info!("Revoked API key {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn router(state: AppState) -> Router {
+    let movies = Router::new()
+        .route("/movies", get(list_movies).post(create_movie))
+        .route("/movies/search", get(search_movies))
+        .route("/movies/events", get(movie_events))
+        .route(
+            "/movies/{id}",
+            get(get_movie)
+                .put(update_movie)
+                .patch(patch_movie)
+                .delete(delete_movie),
+        )
+        .route("/movies:batchGet", post(batch_get_movies))
+        .route("/movies:batch", post(batch_movies))
+        .route_layer(middleware::from_fn_with_state(state.clone(), throttle))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            throttle_address,
+        ));
+
+    let api_keys = Router::new()
+        .route("/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/api-keys/{id}", delete(revoke_api_key))
+        .route_layer(middleware::from_fn_with_state(state.clone(), throttle))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            authenticate_admin,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            throttle_address,
+        ));
+
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics_handler))
+        .merge(movies)
+        .merge(api_keys)
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use axum::body::Body;
+    use tonic::transport::Channel;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::api_keys::MemoryApiKeyStore;
+    use crate::field_mask::apply_update_mask;
+    use crate::rate_limit::RateLimitConfig;
+
+    /// Gateway state with authentication disabled and an upstream that is
+    /// never connected to.
+    fn open_state() -> AppState {
+        let upstream = Upstream::from(Channel::from_static("http://127.0.0.1:1").connect_lazy());
+        AppState {
+            registry: Arc::new(Registry::default()),
+            movie_service: MovieService::new(
+                MovieServiceClient::new(upstream.clone()),
+                Metrics::new(),
+            ),
+            upstream_health: HealthClient::new(upstream),
+            auth: None,
+            api_keys: ApiKeys::new(Arc::new(MemoryApiKeyStore::default())),
+            service_token: Some(HeaderValue::from_static("Bearer service")),
+            rate_limiter: RateLimiter::new(RateLimitConfig::default()),
+            ready: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    fn mint_request(api_key: Option<&str>) -> axum::extract::Request {
+        let mut builder =
+            axum::http::Request::post("/api-keys").header(CONTENT_TYPE, "application/json");
+        if let Some(api_key) = api_key {
+            builder = builder.header(API_KEY_HEADER, api_key);
+        }
+        builder
+            .body(Body::from(
+                r#"{"name": "escalate", "scopes": ["movies:delete", "keys:admin"]}"#,
+            ))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_key_mint_is_refused_without_gateway_auth() {
+        let state = open_state();
+
+        let response = router(state.clone())
+            .oneshot(mint_request(None))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(state.api_keys.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn key_mint_needs_keys_admin_scope_without_gateway_auth() {
+        let state = open_state();
+        let (_, writer) = state
+            .api_keys
+            .create("writer".to_string(), vec![Scope::MoviesWrite], None)
+            .await
+            .unwrap();
+        let (_, admin) = state
+            .api_keys
+            .create("admin".to_string(), vec![Scope::KeysAdmin], None)
+            .await
+            .unwrap();
+
+        let response = router(state.clone())
+            .oneshot(mint_request(Some(&writer)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router(state.clone())
+            .oneshot(mint_request(Some(&admin)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(state.api_keys.list().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn readyz_fails_once_draining() {
+        let state = open_state();
+        state.ready.store(false, Ordering::Relaxed);
+
+        let request = axum::http::Request::get("/readyz")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(state).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn readyz_follows_the_upstream_health_service() {
+        let (reporter, health_service) = tonic_health::server::health_reporter();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health_service)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let channel = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect_lazy();
+        let state = AppState {
+            upstream_health: HealthClient::new(Upstream::from(channel)),
+            ..open_state()
+        };
+        let probe = |uri: &'static str| {
+            let state = state.clone();
+            async move {
+                let response = send(&state, "GET", uri, &[], None).await;
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (status, body)
+            }
+        };
+
+        // Nothing reported for movie.MovieService yet.
+        let (status, body) = probe("/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body["upstream"]
+            .as_str()
+            .unwrap()
+            .starts_with("UNREACHABLE"));
+
+        crate::health::set_status(&reporter, tonic_health::ServingStatus::Serving).await;
+        let (status, body) = probe("/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["upstream"], "SERVING");
+
+        crate::health::set_status(&reporter, tonic_health::ServingStatus::NotServing).await;
+        let (status, body) = probe("/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["upstream"], "NOT_SERVING");
+
+        // Liveness does not depend on the upstream at all.
+        assert_eq!(probe("/healthz").await.0, StatusCode::OK);
+        assert_eq!(
+            send(&open_state(), "GET", "/healthz", &[], None)
+                .await
+                .status(),
+            StatusCode::OK
+        );
+    }
+
+    fn patch(json: &str) -> (movie::Movie, FieldMask) {
+        serde_json::from_str::<MoviePatch>(json)
+            .unwrap()
+            .into_update("heat".to_string())
+    }
+
+    #[test]
+    fn merge_patch_masks_only_the_members_present() {
+        let (movie, mask) = patch(r#"{"title": "Heat", "runtime_minutes": 170}"#);
+
+        assert_eq!(mask.paths, ["title", "runtime_minutes"]);
+        assert_eq!(movie.id, "heat");
+        assert_eq!(movie.title, "Heat");
+        assert_eq!(movie.runtime_minutes, 170);
+    }
+
+    #[test]
+    fn merge_patch_null_clears_the_member() {
+        let (patch, mask) = patch(r#"{"synopsis": null, "cast": null, "title": "Heat"}"#);
+        assert_eq!(mask.paths, ["title", "cast", "synopsis"]);
+
+        let mut movie = movie::Movie {
+            id: "heat".to_string(),
+            title: "Heat?".to_string(),
+            cast: vec![movie::CastMember {
+                name: "Al Pacino".to_string(),
+                character: String::new(),
+            }],
+            synopsis: "A heist crew and the detective after them.".to_string(),
+            certification: "R".to_string(),
+            ..Default::default()
+        };
+        apply_update_mask(&mask, patch, &mut movie).unwrap();
+
+        assert_eq!(movie.title, "Heat");
+        assert!(movie.cast.is_empty());
+        assert_eq!(movie.synopsis, "");
+        // Absent members are left alone.
+        assert_eq!(movie.certification, "R");
+    }
+
+    #[test]
+    fn empty_merge_patch_masks_nothing() {
+        let (_, mask) = patch("{}");
+
+        assert!(mask.paths.is_empty());
+    }
+
+    #[test]
+    fn merge_patch_replaces_nested_members_whole() {
+        let (movie, mask) = patch(r#"{"cast": [{"name": "Robert De Niro"}]}"#);
+
+        assert_eq!(mask.paths, ["cast"]);
+        assert_eq!(movie.cast.len(), 1);
+        assert_eq!(movie.cast[0].name, "Robert De Niro");
+        assert_eq!(movie.cast[0].character, "");
+    }
+
+    #[test]
+    fn merge_patch_rejects_unknown_members() {
+        for json in [
+            r#"{"rating": 5}"#,
+            r#"{"id": "other"}"#,
+            r#"{"version": 2}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<MoviePatch>(json).is_err(),
+                "{}",
+                json
+            );
+        }
+    }
+
+    async fn problem(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn field_violations_render_as_422() {
+        let mut movie = movie::Movie {
+            id: "heat".to_string(),
+            title: " ".to_string(),
+            genres: vec!["Noir".to_string()],
+            ..Default::default()
+        };
+        let status = validation::normalize_movie(&mut movie).unwrap_err();
+
+        let (status, body) = problem(ApiError::from(status)).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["status"], 422);
+        assert_eq!(body["grpc_status"], tonic::Code::InvalidArgument as i32);
+        assert_eq!(body["violations"][0]["field"], "title");
+        assert_eq!(body["violations"][0]["description"], "must not be empty");
+        assert_eq!(body["violations"][1]["field"], "genres[0]");
+        assert_eq!(body["violations"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn every_grpc_code_maps_to_its_http_status() {
+        use tonic::Code;
+        let expected = [
+            (Code::Ok, 200),
+            (Code::Cancelled, 499),
+            (Code::Unknown, 500),
+            (Code::InvalidArgument, 400),
+            (Code::DeadlineExceeded, 504),
+            (Code::NotFound, 404),
+            (Code::AlreadyExists, 409),
+            (Code::PermissionDenied, 403),
+            (Code::ResourceExhausted, 429),
+            (Code::FailedPrecondition, 400),
+            (Code::Aborted, 409),
+            (Code::OutOfRange, 400),
+            (Code::Unimplemented, 501),
+            (Code::Internal, 500),
+            (Code::Unavailable, 503),
+            (Code::DataLoss, 500),
+            (Code::Unauthenticated, 401),
+        ];
+        for (code, status) in expected {
+            assert_eq!(http_status(code).as_u16(), status, "{:?}", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_upstream_is_503_not_404() {
+        for (method, uri, body) in [
+            ("GET", "/movies/heat", None),
+            ("GET", "/movies", None),
+            ("POST", "/movies", Some(r#"{"title": "Heat"}"#)),
+            ("DELETE", "/movies/heat", None),
+        ] {
+            let response = send(&open_state(), method, uri, &[], body).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+
+        let state = gateway_over(Arc::new(crate::watch::ChangeFeed::new(16))).await;
+        let response = send(&state, "GET", "/movies/heat", &[], None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let (status, body) = problem(ApiError::from(Status::unavailable("down"))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["grpc_status"], tonic::Code::Unavailable as i32);
+    }
+
+    #[tokio::test]
+    async fn invalid_argument_without_violations_renders_as_400() {
+        let (status, body) =
+            problem(ApiError::from(Status::invalid_argument("bad page token"))).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.get("violations").is_none());
+    }
+
+    /// Gateway state verifying HS256 tokens signed with `secret`.
+    fn authenticating_state() -> AppState {
+        let config = crate::auth::AuthConfig {
+            key: crate::auth::JwtKey::Hs256Secret(b"secret".to_vec()),
+            issuer: None,
+            audience: None,
+        };
+        AppState {
+            auth: Some(JwtVerifier::new(&config).unwrap()),
+            ..open_state()
+        }
+    }
+
+    fn token(role: Role) -> String {
+        let exp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600;
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &json!({ "sub": "alice", "roles": [role.as_str()], "exp": exp }),
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        format!("Bearer {}", token)
+    }
+
+    fn create_request(authorization: Option<&str>) -> axum::extract::Request {
+        let mut builder =
+            axum::http::Request::post("/movies").header(CONTENT_TYPE, "application/json");
+        if let Some(authorization) = authorization {
+            builder = builder.header(AUTHORIZATION, authorization);
+        }
+        builder
+            .body(Body::from(r#"{"title": "Heat", "genre": "Crime"}"#))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn missing_token_is_401_and_too_low_a_role_is_403() {
+        let state = authenticating_state();
+
+        let response = router(state.clone())
+            .oneshot(create_request(None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+
+        let response = router(state.clone())
+            .oneshot(create_request(Some("Bearer not.a.jwt")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let viewer = token(Role::Viewer);
+        let response = router(state)
+            .oneshot(create_request(Some(&viewer)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn api_key_callers_are_named_upstream() {
+        let state = open_state();
+        let caller = Caller {
+            authorization: Some(HeaderValue::from_static("Bearer service")),
+            api_key_id: Some("key-1".to_string()),
+            client_addr: None,
+        };
+
+        let mut request = Request::new(());
+        CALLER.sync_scope(caller, || {
+            state
+                .movie_service
+                .inject_metadata(&Context::new(), &mut request)
+        });
+
+        let metadata = request.metadata();
+        assert_eq!(metadata.get(API_KEY_ID_METADATA).unwrap(), "key-1");
+        assert_eq!(metadata.get("authorization").unwrap(), "Bearer service");
+    }
+
+    #[tokio::test]
+    async fn bearer_callers_are_not_named_as_api_keys() {
+        let state = open_state();
+        let caller = Caller {
+            authorization: Some(HeaderValue::from_static("Bearer user")),
+            api_key_id: None,
+            client_addr: Some("203.0.113.7".parse().unwrap()),
+        };
+
+        let mut request = Request::new(());
+        CALLER.sync_scope(caller, || {
+            state
+                .movie_service
+                .inject_metadata(&Context::new(), &mut request)
+        });
+
+        assert!(request.metadata().get(API_KEY_ID_METADATA).is_none());
+        assert_eq!(
+            request.metadata().get(CLIENT_ADDR_METADATA).unwrap(),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            request.metadata().get("authorization").unwrap(),
+            "Bearer user"
+        );
+    }
+
+    fn from_addr(mut request: axum::extract::Request, addr: &str) -> axum::extract::Request {
+        let addr: SocketAddr = addr.parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        request
+    }
+
+    #[tokio::test]
+    async fn failed_credentials_are_throttled_by_address() {
+        let state = AppState {
+            rate_limiter: RateLimiter::new(RateLimitConfig {
+                default: Some(rate_limit::RateLimit {
+                    per_second: 1,
+                    burst: 2,
+                }),
+                ..Default::default()
+            }),
+            ..authenticating_state()
+        };
+        let attempt = |addr| from_addr(create_request(Some("Bearer not.a.jwt")), addr);
+
+        for _ in 0..2 {
+            let response = router(state.clone())
+                .oneshot(attempt("203.0.113.7:4000"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = router(state.clone())
+            .oneshot(attempt("203.0.113.7:4001"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        let response = router(state)
+            .oneshot(attempt("203.0.113.8:4000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn upstream_retry_info_becomes_retry_after() {
+        let status = Status::with_error_details(
+            tonic::Code::ResourceExhausted,
+            "Rate limit exceeded; retry in 3s",
+            ErrorDetails::with_retry_info(Some(std::time::Duration::from_millis(2500))),
+        );
+        let response = ApiError::from(status).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "3");
+
+        let response = ApiError::from(Status::resource_exhausted("quota")).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(!response.headers().contains_key(RETRY_AFTER));
+    }
+
+    /// A gateway in front of a real, in-memory server using `changes`.
+    async fn gateway_over(changes: Arc<crate::watch::ChangeFeed>) -> AppState {
+        let service = crate::service::MovieServiceImpl::new(
+            Arc::new(crate::storage::MovieStore::default()),
+            changes,
+        )
+        .await
+        .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(
+                    movie::movie_service_server::MovieServiceServer::with_interceptor(
+                        service,
+                        crate::auth::AuthInterceptor::new(None),
+                    ),
+                )
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let channel = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect_lazy();
+        let upstream = Upstream::from(channel);
+        AppState {
+            movie_service: MovieService::new(MovieServiceClient::new(upstream), Metrics::new()),
+            ..open_state()
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_reach_the_upstream_concurrently() {
+        const REQUESTS: usize = 16;
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (counter, high) = (in_flight.clone(), peak.clone());
+        // Holds every upstream call open long enough for the others to
+        // arrive, unless something in the gateway serializes them.
+        let slow = tower::ServiceBuilder::new().map_future(move |call| {
+            let (in_flight, peak) = (counter.clone(), high.clone());
+            async move {
+                peak.fetch_max(
+                    in_flight.fetch_add(1, Ordering::SeqCst) + 1,
+                    Ordering::SeqCst,
+                );
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                call.await
+            }
+        });
+
+        let service = crate::service::MovieServiceImpl::new(
+            Arc::new(crate::storage::MovieStore::default()),
+            Arc::new(crate::watch::ChangeFeed::new(16)),
+        )
+        .await
+        .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .layer(slow)
+                .add_service(
+                    movie::movie_service_server::MovieServiceServer::with_interceptor(
+                        service,
+                        crate::auth::AuthInterceptor::new(None),
+                    ),
+                )
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let channel = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect_lazy();
+        let state = AppState {
+            movie_service: MovieService::new(
+                MovieServiceClient::new(Upstream::from(channel)),
+                Metrics::new(),
+            ),
+            ..open_state()
+        };
+
+        let requests: Vec<_> = (0..REQUESTS)
+            .map(|i| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    send(&state, "GET", &format!("/movies/movie-{}", i), &[], None).await
+                })
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.await.unwrap().status(), StatusCode::NOT_FOUND);
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), REQUESTS);
+    }
+
+    /// A gateway over a server whose change feed retains four events, ten of
+    /// which have been published.
+    async fn gateway_over_feed() -> (AppState, Arc<crate::watch::ChangeFeed>) {
+        let changes = Arc::new(crate::watch::ChangeFeed::new(4));
+        for _ in 0..10 {
+            changes.publish(movie::MovieEventType::Created, movie::Movie::default());
+        }
+        (gateway_over(changes.clone()).await, changes)
+    }
+
+    fn events_request(last_event_id: &str) -> axum::extract::Request {
+        axum::http::Request::get("/movies/events")
+            .header("last-event-id", last_event_id)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn stale_last_event_ids_are_410() {
+        let (state, changes) = gateway_over_feed().await;
+        let epoch = changes.epoch();
+
+        let response = router(state.clone())
+            .oneshot(events_request(&format!("{}-8", epoch)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Compacted away, and from before a restart.
+        for stale in [format!("{}-2", epoch), format!("{}-8", epoch ^ 1)] {
+            let response = router(state.clone())
+                .oneshot(events_request(&stale))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::GONE, "{}", stale);
+        }
+
+        let response = router(state).oneshot(events_request("8")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Reads an SSE response until it has delivered `count` events, and
+    /// returns the `(id, event)` lines of each.
+    async fn read_events(response: Response, count: usize) -> Vec<(String, String)> {
+        let mut body = response.into_body().into_data_stream();
+        let mut text = String::new();
+        let mut events = Vec::new();
+        while events.len() < count {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+                .await
+                .expect("event within 5s")
+                .expect("stream still open")
+                .unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+            while let Some(end) = text.find("\n\n") {
+                let frame: String = text.drain(..end + 2).collect();
+                let field = |name: &str| {
+                    frame
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(str::to_string)
+                };
+                if let (Some(id), Some(event)) = (field("id: "), field("event: ")) {
+                    events.push((id, event));
+                }
+            }
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn events_resume_after_last_event_id_and_follow_live_changes() {
+        let (state, changes) = gateway_over_feed().await;
+        let epoch = changes.epoch();
+
+        let response = router(state)
+            .oneshot(events_request(&format!("{}-8", epoch)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+
+        let publisher = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            changes.publish(movie::MovieEventType::Deleted, movie::Movie::default());
+        });
+        let events = read_events(response, 3).await;
+        publisher.await.unwrap();
+        assert_eq!(
+            events,
+            [
+                (format!("{}-9", epoch), "created".to_string()),
+                (format!("{}-10", epoch), "created".to_string()),
+                (format!("{}-11", epoch), "deleted".to_string()),
+            ]
+        );
+    }
+
+    async fn send(
+        state: &AppState,
+        method: &str,
+        uri: &str,
+        headers: &[(axum::http::HeaderName, &str)],
+        body: Option<&str>,
+    ) -> Response {
+        let mut builder = axum::http::Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(name, *value);
+        }
+        let body = match body {
+            Some(body) => {
+                builder = builder.header(CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        router(state.clone())
+            .oneshot(builder.body(body).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// A gateway over an in-memory server holding one movie at version 1,
+    /// and that movie's URI.
+    async fn gateway_with_movie() -> (AppState, String) {
+        let state = gateway_over(Arc::new(crate::watch::ChangeFeed::new(16))).await;
+        let created = send(
+            &state,
+            "POST",
+            "/movies",
+            &[],
+            Some(r#"{"title": "Heat", "genre": "Crime"}"#),
+        )
+        .await;
+        assert_eq!(created.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(created.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let uri = format!("/movies/{}", created["id"].as_str().unwrap());
+        (state, uri)
+    }
+
+    #[tokio::test]
+    async fn movie_json_carries_the_metadata_and_omits_unset_fields() {
+        let state = gateway_over(Arc::new(crate::watch::ChangeFeed::new(16))).await;
+        let body = |response: Response| async move {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let created = send(
+            &state,
+            "POST",
+            "/movies",
+            &[],
+            Some(
+                r#"{"id": "heat", "title": "Heat", "genres": ["Crime", "Thriller"],
+                    "release_year": 1995, "runtime_minutes": 170,
+                    "directors": ["Michael Mann"],
+                    "cast": [{"name": "Al Pacino", "character": "Vincent Hanna"},
+                             {"name": "Robert De Niro"}],
+                    "certification": "r", "language": "en"}"#,
+            ),
+        )
+        .await;
+        assert_eq!(created.status(), StatusCode::OK);
+        let created = body(created).await;
+        assert_eq!(created["genre"], "Crime");
+        assert_eq!(created["genres"], json!(["Crime", "Thriller"]));
+        assert_eq!(created["release_year"], 1995);
+        assert_eq!(created["runtime_minutes"], 170);
+        assert_eq!(created["directors"], json!(["Michael Mann"]));
+        assert_eq!(
+            created["cast"],
+            json!([
+                {"name": "Al Pacino", "character": "Vincent Hanna"},
+                {"name": "Robert De Niro", "character": ""},
+            ])
+        );
+        assert_eq!(created["certification"], "R");
+        assert_eq!(created["language"], "en");
+        assert!(created.get("synopsis").is_none());
+        assert_eq!(created["created_at"], created["updated_at"]);
+
+        // A client that predates the metadata sends and reads `genre` alone.
+        let created = send(
+            &state,
+            "POST",
+            "/movies",
+            &[],
+            Some(r#"{"id": "ronin", "title": "Ronin", "genre": "Action"}"#),
+        )
+        .await;
+        let created = body(created).await;
+        assert_eq!(created["genre"], "Action");
+        assert_eq!(created["genres"], json!(["Action"]));
+        assert!(created.get("release_year").is_none());
+        assert!(created.get("certification").is_none());
+    }
+
+    #[tokio::test]
+    async fn if_none_match_gives_304_for_the_current_tag() {
+        let (state, uri) = gateway_with_movie().await;
+
+        let response = send(&state, "GET", &uri, &[], None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], "\"1\"");
+
+        for matching in ["\"1\"", "W/\"1\"", "\"0\", \"1\"", "\"0\",W/\"1\"", "*"] {
+            let response = send(&state, "GET", &uri, &[(IF_NONE_MATCH, matching)], None).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", matching);
+            assert_eq!(response.headers()[ETAG], "\"1\"");
+        }
+        for stale in ["\"2\"", "W/\"0\", \"2\"", "1"] {
+            let response = send(&state, "GET", &uri, &[(IF_NONE_MATCH, stale)], None).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", stale);
+        }
+    }
+
+    #[tokio::test]
+    async fn if_match_gives_412_unless_it_names_the_current_tag() {
+        let (state, uri) = gateway_with_movie().await;
+        let update = Some(r#"{"title": "Heat (1995)", "genre": "Crime"}"#);
+
+        // Stale, weak (never a strong match) and unquoted tags all fail.
+        for stale in ["\"0\"", "W/\"1\"", "1"] {
+            let response = send(&state, "PUT", &uri, &[(IF_MATCH, stale)], update).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::PRECONDITION_FAILED,
+                "{}",
+                stale
+            );
+        }
+        let response = send(&state, "PUT", &uri, &[(IF_MATCH, "\"0\", \"1\"")], update).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = send(&state, "PUT", &uri, &[(IF_MATCH, "\"1\"")], update).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], "\"2\"");
+        let response = send(&state, "PATCH", &uri, &[(IF_MATCH, "*")], Some("{}")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], "\"2\"");
+
+        let response = send(&state, "DELETE", &uri, &[(IF_MATCH, "\"1\"")], None).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = send(&state, "DELETE", &uri, &[(IF_MATCH, "\"2\"")], None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // `*` only matches a movie that exists.
+        let response = send(&state, "DELETE", &uri, &[(IF_MATCH, "*")], None).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = send(&state, "PUT", &uri, &[(IF_MATCH, "*")], update).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = send(&state, "DELETE", &uri, &[], None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
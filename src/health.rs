@@ -0,0 +1,163 @@
+//! gRPC health status for the movie service, derived from storage readiness.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::movie::movie_service_server::SERVICE_NAME;
+use crate::storage::MovieRepository;
+
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sets the status of both `movie.MovieService` and the whole server (the
+/// empty service name).
+pub async fn set_status(reporter: &HealthReporter, status: ServingStatus) {
+    reporter.set_service_status(SERVICE_NAME, status).await;
+    reporter.set_service_status("", status).await;
+}
+
+/// Pings the repository every `interval` and reports `SERVING` or
+/// `NOT_SERVING` accordingly, logging only transitions. Runs until aborted.
+pub async fn report_storage_health(
+    reporter: HealthReporter,
+    repository: Arc<dyn MovieRepository>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut last = None;
+    loop {
+        ticker.tick().await;
+
+        let status = match repository.ping().await {
+            Ok(()) => ServingStatus::Serving,
+            Err(e) => {
+                tracing::warn!("Storage health check failed: {}", e);
+                ServingStatus::NotServing
+            }
+        };
+
+        if last != Some(status) {
+            tracing::info!("{} is now {:?}", SERVICE_NAME, status);
+            set_status(&reporter, status).await;
+            last = Some(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use async_trait::async_trait;
+    use tonic::Request;
+    use tonic_health::pb::health_check_response::ServingStatus as Reported;
+    use tonic_health::pb::health_server::Health;
+    use tonic_health::pb::HealthCheckRequest;
+    use tonic_health::server::HealthService;
+
+    use super::*;
+    use crate::movie::Movie;
+    use crate::storage::{BatchError, MovieQuery, StorageError};
+
+    /// A backend that is only ever pinged, and answers as `healthy` says.
+    #[derive(Debug)]
+    struct Pinged {
+        healthy: AtomicBool,
+    }
+
+    #[async_trait]
+    impl MovieRepository for Pinged {
+        async fn create(&self, _movie: Movie) -> Result<Movie, StorageError> {
+            unreachable!("only pinged")
+        }
+
+        async fn get(&self, _id: &str) -> Result<Option<Movie>, StorageError> {
+            unreachable!("only pinged")
+        }
+
+        async fn list(&self) -> Result<Vec<Movie>, StorageError> {
+            unreachable!("only pinged")
+        }
+
+        async fn query(&self, _query: &MovieQuery) -> Result<Vec<Movie>, StorageError> {
+            unreachable!("only pinged")
+        }
+
+        async fn update(
+            &self,
+            _movie: Movie,
+            _expected_version: Option<u64>,
+        ) -> Result<Movie, StorageError> {
+            unreachable!("only pinged")
+        }
+
+        async fn delete(
+            &self,
+            _id: &str,
+            _expected_version: Option<u64>,
+        ) -> Result<bool, StorageError> {
+            unreachable!("only pinged")
+        }
+
+        async fn create_batch(&self, _movies: Vec<Movie>) -> Result<Vec<Movie>, BatchError> {
+            unreachable!("only pinged")
+        }
+
+        async fn delete_batch(
+            &self,
+            _deletes: Vec<(String, Option<u64>)>,
+        ) -> Result<Vec<bool>, BatchError> {
+            unreachable!("only pinged")
+        }
+
+        async fn ping(&self) -> Result<(), StorageError> {
+            if self.healthy.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(StorageError::Backend("connection refused".to_string()))
+            }
+        }
+    }
+
+    async fn statuses(service: &HealthService) -> Vec<Reported> {
+        let mut statuses = Vec::new();
+        for name in [SERVICE_NAME, ""] {
+            let response = service
+                .check(Request::new(HealthCheckRequest {
+                    service: name.to_string(),
+                }))
+                .await
+                .unwrap();
+            statuses.push(response.into_inner().status());
+        }
+        statuses
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn status_follows_storage_pings() {
+        let repository = Arc::new(Pinged {
+            healthy: AtomicBool::new(true),
+        });
+        let reporter = HealthReporter::new();
+        let service = HealthService::from_health_reporter(reporter.clone());
+        let checks = tokio::spawn(report_storage_health(
+            reporter,
+            repository.clone(),
+            CHECK_INTERVAL,
+        ));
+
+        tokio::time::sleep(CHECK_INTERVAL / 2).await;
+        assert_eq!(statuses(&service).await, [Reported::Serving; 2]);
+
+        repository.healthy.store(false, Ordering::SeqCst);
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        assert_eq!(statuses(&service).await, [Reported::NotServing; 2]);
+
+        repository.healthy.store(true, Ordering::SeqCst);
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        assert_eq!(statuses(&service).await, [Reported::Serving; 2]);
+        checks.abort();
+    }
+}
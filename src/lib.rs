@@ -0,0 +1,32 @@
+// `tonic::Status` is the error type throughout and is inherently large.
+#![allow(clippy::result_large_err)]
+
+// Batch outcomes are oneofs holding a whole `Movie` next to a small error.
+#[allow(clippy::large_enum_variant)]
+pub mod movie {
+    tonic::include_proto!("movie");
+
+    /// Encoded descriptors for `movie.proto` and its imports, served by gRPC
+    /// reflection.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("movie_descriptor");
+}
+
+pub mod api_keys;
+pub mod auth;
+pub mod cache;
+pub mod coalesce;
+pub mod config;
+pub mod field_mask;
+pub mod gateway;
+pub mod health;
+pub mod pagination;
+pub mod rate_limit;
+pub mod resilience;
+pub mod search;
+pub mod service;
+pub mod shutdown;
+pub mod storage;
+pub mod tls;
+pub mod upstream;
+pub mod validation;
+pub mod watch;
@@ -0,0 +1,126 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use tonic::Status;
+
+use crate::movie::{Movie, ReadMoviesRequest};
+use crate::storage::{MovieQuery, SortField};
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Cursor state carried in `next_page_token`. The filter and ordering are
+/// included so a token cannot be replayed against a different query.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct PageToken {
+    sort_by: String,
+    descending: bool,
+    genre: Option<String>,
+    title_prefix: Option<String>,
+    key: String,
+    id: String,
+}
+
+fn parse_order_by(order_by: &str) -> Result<(SortField, bool), Status> {
+    let normalized = order_by.trim().to_ascii_lowercase();
+    let mut parts = normalized.split_whitespace();
+
+    let field = match parts.next() {
+        None | Some("id") => SortField::Id,
+        Some("title") => SortField::Title,
+        Some(other) => {
+            return Err(Status::invalid_argument(format!(
+                "Unsupported order_by field: {}",
+                other
+            )))
+        }
+    };
+    let descending = match parts.next() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => {
+            return Err(Status::invalid_argument(format!(
+                "Unsupported order_by direction: {}",
+                other
+            )))
+        }
+    };
+    if parts.next().is_some() {
+        return Err(Status::invalid_argument("Malformed order_by"));
+    }
+
+    Ok((field, descending))
+}
+
+fn sort_field_name(field: SortField) -> &'static str {
+    match field {
+        SortField::Id => "id",
+        SortField::Title => "title",
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Translates a `ReadMoviesRequest` into a storage query for one page. The
+/// returned query's limit is the page size; callers fetch one extra row to
+/// learn whether another page follows.
+pub fn query_from_request(request: &ReadMoviesRequest) -> Result<MovieQuery, Status> {
+    if request.page_size < 0 {
+        return Err(Status::invalid_argument("page_size must not be negative"));
+    }
+    let limit = match request.page_size as usize {
+        0 => DEFAULT_PAGE_SIZE,
+        size => size.min(MAX_PAGE_SIZE),
+    };
+
+    let (sort_by, descending) = parse_order_by(&request.order_by)?;
+    let filter = request.filter.clone().unwrap_or_default();
+
+    let mut query = MovieQuery {
+        genre: non_empty(&filter.genre),
+        title_prefix: non_empty(&filter.title_prefix),
+        sort_by,
+        descending,
+        after: None,
+        limit,
+    };
+
+    if !request.page_token.is_empty() {
+        let token = decode_token(&request.page_token)?;
+        if token.sort_by != sort_field_name(sort_by)
+            || token.descending != descending
+            || token.genre != query.genre
+            || token.title_prefix != query.title_prefix
+        {
+            return Err(Status::invalid_argument(
+                "page_token does not match the request filter or order_by",
+            ));
+        }
+        query.after = Some((token.key, token.id));
+    }
+
+    Ok(query)
+}
+
+/// Builds the token that resumes `query` after `last`.
+pub fn next_page_token(query: &MovieQuery, last: &Movie) -> String {
+    let token = PageToken {
+        sort_by: sort_field_name(query.sort_by).to_string(),
+        descending: query.descending,
+        genre: query.genre.clone(),
+        title_prefix: query.title_prefix.clone(),
+        key: query.sort_key(last).to_string(),
+        id: last.id.clone(),
+    };
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(&token).expect("page token serializes"))
+}
+
+fn decode_token(token: &str) -> Result<PageToken, Status> {
+    URL_SAFE_NO_PAD
+        .decode(token)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| Status::invalid_argument("Invalid page_token"))
+}
@@ -0,0 +1,333 @@
+//! Per-client token-bucket rate limiting, used by both the gateway (per
+//! route) and the gRPC server (per RPC).
+//!
+//! Every client gets its own bucket for each route or RPC. A bucket holds up
+//! to `burst` tokens, refills at `per_second` tokens a second, and each
+//! request takes one token.
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    hash::BuildHasher,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+const SHARD_COUNT: usize = 16;
+
+/// How often buckets that have refilled completely are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        (tokens + elapsed.as_secs_f64() * self.per_second as f64).min(self.burst as f64)
+    }
+}
+
+/// `default` applies to every route or RPC without an entry in `overrides`;
+/// with neither, requests are not limited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub default: Option<RateLimit>,
+    pub overrides: BTreeMap<String, RateLimit>,
+}
+
+impl RateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.default.is_some() || !self.overrides.is_empty()
+    }
+
+    fn limit_for(&self, name: &str) -> Option<RateLimit> {
+        self.overrides.get(name).copied().or(self.default)
+    }
+}
+
+/// Who a request is counted against, from most to least specific.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Client {
+    ApiKey(String),
+    Subject(String),
+    Ip(IpAddr),
+    /// The peer address is not known, e.g. in tests; all such requests
+    /// share one bucket.
+    Unknown,
+}
+
+impl Client {
+    /// Low-cardinality label for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Client::ApiKey(_) => "api_key",
+            Client::Subject(_) => "subject",
+            Client::Ip(_) => "ip",
+            Client::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+type Shard = HashMap<(String, Client), Bucket>;
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    hasher: RandomState,
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter and, if any limit is configured, a background task
+    /// that forgets idle clients. Must be called within a Tokio runtime.
+    pub fn new(config: RateLimitConfig) -> Arc<Self> {
+        let enabled = config.is_enabled();
+        let this = Arc::new(Self {
+            config,
+            hasher: RandomState::new(),
+            shards: (0..SHARD_COUNT).map(|_| Mutex::default()).collect(),
+        });
+
+        if enabled {
+            let weak = Arc::downgrade(&this);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    let Some(this) = weak.upgrade() else {
+                        return;
+                    };
+                    this.sweep();
+                }
+            });
+        }
+
+        this
+    }
+
+    /// Takes a token from `client`'s bucket for `name` (a route or RPC).
+    /// When the bucket is empty, returns how long until a token is
+    /// available.
+    pub fn check(&self, name: &str, client: &Client) -> Result<(), Duration> {
+        self.take(name, client, 1.0)
+    }
+
+    /// Like [`RateLimiter::check`], but leaves the token in the bucket.
+    pub fn peek(&self, name: &str, client: &Client) -> Result<(), Duration> {
+        self.take(name, client, 0.0)
+    }
+
+    fn take(&self, name: &str, client: &Client, cost: f64) -> Result<(), Duration> {
+        let Some(limit) = self.config.limit_for(name) else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let key = (name.to_string(), client.clone());
+        let shard = &self.shards[self.hasher.hash_one(&key) as usize % SHARD_COUNT];
+        // A poisoned shard only means a panic mid-update of some bucket's
+        // float; the map itself is intact.
+        let mut shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = shard.entry(key).or_insert(Bucket {
+            limit,
+            tokens: limit.burst as f64,
+            updated: now,
+        });
+
+        bucket.tokens = limit.refill(bucket.tokens, now - bucket.updated);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= cost;
+            return Ok(());
+        }
+
+        let missing = 1.0 - bucket.tokens;
+        Err(Duration::from_secs_f64(missing / limit.per_second as f64))
+    }
+
+    /// Drops buckets that would be full by now; they are indistinguishable
+    /// from new ones.
+    fn sweep(&self) {
+        let now = Instant::now();
+        for shard in &self.shards {
+            shard
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|_, bucket| {
+                    bucket.limit.refill(bucket.tokens, now - bucket.updated)
+                        < bucket.limit.burst as f64
+                });
+        }
+    }
+}
+
+/// Whole seconds for a `Retry-After` header, rounded up so a client that
+/// waits that long is admitted.
+pub fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "POST /movies";
+
+    fn limiter(per_second: u32, burst: u32) -> Arc<RateLimiter> {
+        RateLimiter::new(RateLimitConfig {
+            default: Some(RateLimit { per_second, burst }),
+            overrides: BTreeMap::new(),
+        })
+    }
+
+    fn client(n: u8) -> Client {
+        Client::Ip(IpAddr::from([10, 0, 0, n]))
+    }
+
+    fn buckets(limiter: &RateLimiter) -> usize {
+        limiter
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn allows_a_burst_then_refills_at_the_rate() {
+        let limiter = limiter(2, 3);
+
+        for _ in 0..3 {
+            limiter.check(ROUTE, &client(1)).unwrap();
+        }
+        assert_eq!(
+            limiter.check(ROUTE, &client(1)),
+            Err(Duration::from_millis(500))
+        );
+
+        tokio::time::advance(Duration::from_millis(250)).await;
+        // Half a token has come back; the wait shrinks accordingly.
+        assert_eq!(
+            limiter.check(ROUTE, &client(1)),
+            Err(Duration::from_millis(250))
+        );
+
+        tokio::time::advance(Duration::from_millis(250)).await;
+        limiter.check(ROUTE, &client(1)).unwrap();
+        assert!(limiter.check(ROUTE, &client(1)).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_no_further_than_the_burst() {
+        let limiter = limiter(10, 2);
+        limiter.check(ROUTE, &client(1)).unwrap();
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+
+        limiter.check(ROUTE, &client(1)).unwrap();
+        limiter.check(ROUTE, &client(1)).unwrap();
+        assert!(limiter.check(ROUTE, &client(1)).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn buckets_are_per_client_and_route() {
+        let limiter = limiter(1, 1);
+
+        limiter.check(ROUTE, &client(1)).unwrap();
+        assert!(limiter.check(ROUTE, &client(1)).is_err());
+
+        limiter.check(ROUTE, &client(2)).unwrap();
+        limiter.check("GET /movies", &client(1)).unwrap();
+        limiter
+            .check(ROUTE, &Client::ApiKey("key-1".to_string()))
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overrides_take_precedence_over_the_default() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            default: None,
+            overrides: BTreeMap::from([(
+                ROUTE.to_string(),
+                RateLimit {
+                    per_second: 1,
+                    burst: 1,
+                },
+            )]),
+        });
+
+        limiter.check(ROUTE, &client(1)).unwrap();
+        assert!(limiter.check(ROUTE, &client(1)).is_err());
+        // Without a default, other routes are not limited at all.
+        for _ in 0..100 {
+            limiter.check("GET /movies", &client(1)).unwrap();
+        }
+        assert_eq!(buckets(&limiter), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn peek_leaves_the_token() {
+        let limiter = limiter(1, 1);
+
+        limiter.peek(ROUTE, &client(1)).unwrap();
+        limiter.peek(ROUTE, &client(1)).unwrap();
+        limiter.check(ROUTE, &client(1)).unwrap();
+        assert_eq!(limiter.peek(ROUTE, &client(1)), Err(Duration::from_secs(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sweep_drops_only_buckets_that_have_refilled() {
+        let limiter = limiter(1, 10);
+        for _ in 0..10 {
+            limiter.check(ROUTE, &client(1)).unwrap();
+        }
+        tokio::time::advance(Duration::from_secs(5)).await;
+        for _ in 0..10 {
+            limiter.check(ROUTE, &client(2)).unwrap();
+        }
+
+        limiter.sweep();
+        assert_eq!(buckets(&limiter), 2);
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        limiter.sweep();
+        // The first client has refilled; the second is still short.
+        assert_eq!(buckets(&limiter), 1);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        limiter.sweep();
+        assert_eq!(buckets(&limiter), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_buckets_are_swept_in_the_background() {
+        let limiter = limiter(1, 1);
+        limiter.check(ROUTE, &client(1)).unwrap();
+        limiter.check(ROUTE, &client(2)).unwrap();
+        assert_eq!(buckets(&limiter), 2);
+
+        // The first sweep is one interval after start.
+        tokio::time::sleep(SWEEP_INTERVAL + Duration::from_secs(1)).await;
+
+        assert_eq!(buckets(&limiter), 0);
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        assert_eq!(retry_after_secs(Duration::ZERO), 0);
+        assert_eq!(retry_after_secs(Duration::from_millis(1)), 1);
+        assert_eq!(retry_after_secs(Duration::from_secs(1)), 1);
+        assert_eq!(retry_after_secs(Duration::from_millis(1500)), 2);
+    }
+}
@@ -0,0 +1,390 @@
+//! Deadlines, retries and a circuit breaker for the gateway's calls to the
+//! gRPC server.
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use tonic::Code;
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_OPEN_FOR: Duration = Duration::from_secs(10);
+
+/// RPCs that only read, so repeating one after a failed attempt cannot
+/// change anything on the server.
+pub const IDEMPOTENT_RPCS: &[&str] = &["GetMovie", "GetMovies", "BatchGetMovies"];
+
+/// Streaming RPCs stay open indefinitely, so they get no deadline.
+pub const STREAMING_RPCS: &[&str] = &["WatchMovies"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResilienceConfig {
+    /// Limit on establishing a connection to the server.
+    pub connect_timeout: Duration,
+    /// Deadline for unary RPCs without an entry in `deadlines`, covering
+    /// every attempt including backoff.
+    pub deadline: Duration,
+    pub deadlines: BTreeMap<String, Duration>,
+    pub retry: RetryPolicy,
+    pub breaker: BreakerConfig,
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            deadline: DEFAULT_DEADLINE,
+            deadlines: BTreeMap::new(),
+            retry: RetryPolicy::default(),
+            breaker: BreakerConfig::default(),
+        }
+    }
+}
+
+impl ResilienceConfig {
+    pub fn deadline_for(&self, rpc: &str) -> Option<Duration> {
+        if STREAMING_RPCS.contains(&rpc) {
+            return None;
+        }
+        Some(self.deadlines.get(rpc).copied().unwrap_or(self.deadline))
+    }
+
+    pub fn is_retryable(&self, rpc: &str) -> bool {
+        IDEMPOTENT_RPCS.contains(&rpc)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per call, including the first; 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (starting at 1): a uniformly random
+    /// duration up to an exponentially growing, capped ceiling ("full
+    /// jitter"), so clients that failed together do not retry together.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let growth = 2u32.saturating_pow(retry.saturating_sub(1));
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(growth)
+            .min(self.max_backoff);
+        ceiling.mul_f64(jitter())
+    }
+}
+
+/// A uniformly distributed value in `[0, 1]`. Every `RandomState` is keyed
+/// differently, so hashing nothing with a fresh one is a cheap source of
+/// randomness that is good enough for spreading out retries.
+fn jitter() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+/// Whether a failed call suggests the server is down or overloaded, as
+/// opposed to the server answering with an error.
+pub fn is_upstream_failure(code: Code) -> bool {
+    matches!(code, Code::Unavailable | Code::DeadlineExceeded)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects calls before letting a trial call
+    /// through.
+    pub open_for: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            open_for: DEFAULT_OPEN_FOR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    /// Letting a single trial call through to decide whether to close.
+    HalfOpen,
+    Open,
+}
+
+impl BreakerState {
+    /// Value of the circuit state gauge.
+    pub fn as_gauge(self) -> i64 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: BreakerState,
+    failures: u32,
+    opened_at: Instant,
+    trial_started: Option<Instant>,
+}
+
+/// Fails calls fast while the server appears to be down. After
+/// `failure_threshold` consecutive failures the circuit opens and rejects
+/// calls for `open_for`; then one trial call is let through, which closes
+/// the circuit on success or reopens it on failure.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    inner: Mutex<Breaker>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Breaker {
+                state: BreakerState::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+                trial_started: None,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Breaker> {
+        // The state is a few plain fields, consistent after any panic.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.lock().state
+    }
+
+    /// Admits a call, or returns how long until the circuit will next let
+    /// one through. A trial call whose outcome is never recorded (because
+    /// the caller went away) stops blocking others after `open_for`.
+    pub fn admit(&self) -> Result<(), Duration> {
+        self.admit_at(Instant::now())
+    }
+
+    fn admit_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut breaker = self.lock();
+        match breaker.state {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open => {
+                let elapsed = now - breaker.opened_at;
+                if elapsed < self.config.open_for {
+                    return Err(self.config.open_for - elapsed);
+                }
+                breaker.state = BreakerState::HalfOpen;
+                breaker.trial_started = Some(now);
+                tracing::info!("Circuit half-open, sending a trial call upstream");
+                Ok(())
+            }
+            BreakerState::HalfOpen => match breaker.trial_started {
+                Some(started) if now - started < self.config.open_for => {
+                    Err(self.config.open_for - (now - started))
+                }
+                _ => {
+                    breaker.trial_started = Some(now);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Records the outcome of an admitted call; `failed` per
+    /// [`is_upstream_failure`].
+    pub fn record(&self, failed: bool) {
+        self.record_at(failed, Instant::now())
+    }
+
+    fn record_at(&self, failed: bool, now: Instant) {
+        let mut breaker = self.lock();
+        if !failed {
+            if breaker.state != BreakerState::Closed {
+                tracing::info!("Circuit closed, upstream is reachable again");
+            }
+            breaker.state = BreakerState::Closed;
+            breaker.failures = 0;
+            breaker.trial_started = None;
+            return;
+        }
+
+        // Late failures of calls admitted before the circuit opened must not
+        // keep extending it.
+        if breaker.state == BreakerState::Open {
+            return;
+        }
+        breaker.failures = breaker.failures.saturating_add(1);
+        let reopen = breaker.state == BreakerState::HalfOpen;
+        if reopen || breaker.failures >= self.config.failure_threshold {
+            if breaker.state == BreakerState::Closed {
+                tracing::warn!(
+                    "Circuit opened after {} consecutive upstream failures",
+                    breaker.failures
+                );
+            }
+            breaker.state = BreakerState::Open;
+            breaker.opened_at = now;
+            breaker.trial_started = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPEN_FOR: Duration = Duration::from_secs(10);
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(BreakerConfig {
+            failure_threshold: 3,
+            open_for: OPEN_FOR,
+        })
+    }
+
+    /// Opens `breaker` at `at` with `failure_threshold` consecutive failures.
+    fn trip(breaker: &CircuitBreaker, at: Instant) {
+        for _ in 0..3 {
+            breaker.admit_at(at).unwrap();
+            breaker.record_at(true, at);
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker();
+        let start = Instant::now();
+
+        breaker.record_at(true, start);
+        breaker.record_at(true, start);
+        // A success in between resets the count.
+        breaker.record_at(false, start);
+        breaker.record_at(true, start);
+        breaker.record_at(true, start);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        breaker.record_at(true, start);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(breaker.admit_at(start + secs(4)), Err(secs(6)));
+    }
+
+    #[test]
+    fn lets_one_trial_through_after_open_for() {
+        let breaker = breaker();
+        let start = Instant::now();
+        trip(&breaker, start);
+
+        assert_eq!(breaker.admit_at(start + OPEN_FOR), Ok(()));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        // Others wait while the trial is outstanding.
+        assert_eq!(breaker.admit_at(start + secs(13)), Err(secs(7)));
+    }
+
+    #[test]
+    fn admits_a_new_trial_once_an_unrecorded_one_expires() {
+        let breaker = breaker();
+        let start = Instant::now();
+        trip(&breaker, start);
+        breaker.admit_at(start + OPEN_FOR).unwrap();
+
+        let expired = start + OPEN_FOR + OPEN_FOR;
+        assert_eq!(breaker.admit_at(expired), Ok(()));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert_eq!(breaker.admit_at(expired + secs(1)), Err(secs(9)));
+    }
+
+    #[test]
+    fn ignores_late_failures_while_open() {
+        let breaker = breaker();
+        let start = Instant::now();
+        trip(&breaker, start);
+
+        // Calls admitted before the circuit opened fail afterwards; they
+        // must not push back the trial.
+        breaker.record_at(true, start + secs(9));
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(breaker.admit_at(start + OPEN_FOR), Ok(()));
+    }
+
+    #[test]
+    fn reopens_when_the_trial_fails() {
+        let breaker = breaker();
+        let start = Instant::now();
+        trip(&breaker, start);
+
+        let trial = start + OPEN_FOR;
+        breaker.admit_at(trial).unwrap();
+        breaker.record_at(true, trial + secs(1));
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(breaker.admit_at(trial + secs(2)), Err(secs(9)));
+        assert_eq!(breaker.admit_at(trial + secs(11)), Ok(()));
+    }
+
+    #[test]
+    fn closes_when_the_trial_succeeds() {
+        let breaker = breaker();
+        let start = Instant::now();
+        trip(&breaker, start);
+
+        let trial = start + OPEN_FOR;
+        breaker.admit_at(trial).unwrap();
+        breaker.record_at(false, trial + secs(1));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.admit_at(trial + secs(1)), Ok(()));
+
+        // The failure count started over.
+        breaker.record_at(true, trial + secs(2));
+        breaker.record_at(true, trial + secs(2));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn backoff_grows_within_max_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_millis(300),
+        };
+        for retry in 1..=64 {
+            let ceiling = Duration::from_millis(50)
+                .saturating_mul(2u32.saturating_pow(retry - 1))
+                .min(policy.max_backoff);
+            for _ in 0..100 {
+                assert!(policy.backoff(retry) <= ceiling, "retry {retry}");
+            }
+        }
+        assert!(policy.backoff(u32::MAX) <= policy.max_backoff);
+    }
+}
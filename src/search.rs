@@ -0,0 +1,601 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use crate::movie::Movie;
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+const TITLE_WEIGHT: f64 = 2.0;
+const GENRE_WEIGHT: f64 = 1.0;
+
+const PREFIX_MATCH_FACTOR: f64 = 0.8;
+const FUZZY_MATCH_FACTOR: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Title,
+    Genre,
+}
+
+impl Field {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Genre => "genre",
+        }
+    }
+
+    fn weight(&self) -> f64 {
+        match self {
+            Field::Title => TITLE_WEIGHT,
+            Field::Genre => GENRE_WEIGHT,
+        }
+    }
+
+    /// The field's values; a movie has one title but may have several
+    /// genres. Movies stored before `genres` existed only have `genre`.
+    fn values<'a>(&self, movie: &'a Movie) -> Vec<&'a str> {
+        match self {
+            Field::Title => vec![&movie.title],
+            Field::Genre if movie.genres.is_empty() => vec![&movie.genre],
+            Field::Genre => movie.genres.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+const FIELDS: [Field; 2] = [Field::Title, Field::Genre];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    term: String,
+    start: usize,
+    end: usize,
+}
+
+/// Splits on anything that is not alphanumeric and lowercases each term,
+/// keeping byte offsets into the original text for highlighting.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                tokens.push(Token {
+                    term: text[s..i].to_lowercase(),
+                    start: s,
+                    end: i,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(Token {
+            term: text[s..].to_lowercase(),
+            start: s,
+            end: text.len(),
+        });
+    }
+
+    tokens
+}
+
+/// Edit budget for a query term: short terms must match exactly, longer ones
+/// tolerate one or two typos.
+fn max_edits(term: &str) -> usize {
+    match term.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Optimal string alignment distance, giving up once it exceeds `limit`.
+fn edit_distance(a: &str, b: &str, limit: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > limit {
+        return None;
+    }
+
+    let mut prev_prev: Vec<usize> = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        current[0] = i;
+        let mut row_min = current[0];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (prev[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(prev_prev[j - 2] + 1);
+            }
+            row_min = row_min.min(current[j]);
+        }
+        if row_min > limit {
+            return None;
+        }
+        std::mem::swap(&mut prev_prev, &mut prev);
+        std::mem::swap(&mut prev, &mut current);
+    }
+
+    let distance = prev[b.len()];
+    (distance <= limit).then_some(distance)
+}
+
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    pub field: Field,
+    /// One value of the field, HTML-escaped, with matched terms wrapped in
+    /// `<em>` tags.
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub movie: Movie,
+    pub score: f64,
+    pub highlights: Vec<Highlight>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// term -> movie id -> occurrences per field.
+    postings: HashMap<String, HashMap<String, HashMap<Field, u32>>>,
+    documents: HashMap<String, Movie>,
+}
+
+impl Inner {
+    fn insert(&mut self, movie: &Movie) {
+        for field in FIELDS {
+            for token in field.values(movie).into_iter().flat_map(tokenize) {
+                *self
+                    .postings
+                    .entry(token.term)
+                    .or_default()
+                    .entry(movie.id.clone())
+                    .or_default()
+                    .entry(field)
+                    .or_default() += 1;
+            }
+        }
+        self.documents.insert(movie.id.clone(), movie.clone());
+    }
+
+    fn remove(&mut self, id: &str) {
+        let Some(movie) = self.documents.remove(id) else {
+            return;
+        };
+        for field in FIELDS {
+            for token in field.values(&movie).into_iter().flat_map(tokenize) {
+                if let Some(docs) = self.postings.get_mut(&token.term) {
+                    docs.remove(id);
+                    if docs.is_empty() {
+                        self.postings.remove(&token.term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Index terms a query term should match, with a factor reflecting how
+    /// close the match is.
+    fn expand(&self, query_term: &str) -> Vec<(&str, f64)> {
+        let limit = max_edits(query_term);
+        self.postings
+            .keys()
+            .filter_map(|term| {
+                if term == query_term {
+                    Some((term.as_str(), 1.0))
+                } else if query_term.chars().count() >= 2 && term.starts_with(query_term) {
+                    Some((term.as_str(), PREFIX_MATCH_FACTOR))
+                } else if limit > 0 {
+                    edit_distance(query_term, term, limit)
+                        .map(|distance| (term.as_str(), FUZZY_MATCH_FACTOR / distance as f64))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Inverted index over movie titles and genres, updated incrementally as
+/// movies change.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    inner: RwLock<Inner>,
+}
+
+impl SearchIndex {
+    pub fn new<'a>(movies: impl IntoIterator<Item = &'a Movie>) -> Self {
+        let mut inner = Inner::default();
+        for movie in movies {
+            inner.insert(movie);
+        }
+        Self {
+            inner: RwLock::new(inner),
+        }
+    }
+
+    pub fn upsert(&self, movie: &Movie) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        inner.remove(&movie.id);
+        inner.insert(movie);
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.inner
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+    }
+
+    /// Ranks movies by a BM25-style score summed over query terms, weighting
+    /// title matches above genre matches and exact matches above prefix and
+    /// fuzzy ones. Movies matching more of the query terms rank higher.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let query_terms: Vec<String> = tokenize(query)
+            .into_iter()
+            .map(|token| token.term)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if query_terms.is_empty() {
+            return Vec::new();
+        }
+
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let total_docs = inner.documents.len() as f64;
+
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+        let mut matched_terms: HashMap<&str, HashSet<&str>> = HashMap::new();
+        let mut coverage: HashMap<&str, usize> = HashMap::new();
+
+        for query_term in &query_terms {
+            let mut best: HashMap<&str, f64> = HashMap::new();
+            for (term, factor) in inner.expand(query_term) {
+                let docs = &inner.postings[term];
+                let df = docs.len() as f64;
+                let idf = (1.0 + (total_docs - df + 0.5) / (df + 0.5)).ln();
+
+                for (id, fields) in docs {
+                    let term_score: f64 = fields
+                        .iter()
+                        .map(|(field, tf)| {
+                            let tf = *tf as f64;
+                            field.weight() * idf * (tf * 2.2) / (tf + 1.2)
+                        })
+                        .sum::<f64>()
+                        * factor;
+
+                    let entry = best.entry(id.as_str()).or_default();
+                    *entry = entry.max(term_score);
+                    matched_terms.entry(id.as_str()).or_default().insert(term);
+                }
+            }
+            for (id, score) in best {
+                *scores.entry(id).or_default() += score;
+                *coverage.entry(id).or_default() += 1;
+            }
+        }
+
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .map(|(id, score)| {
+                let movie = inner.documents[id].clone();
+                let fraction = coverage[id] as f64 / query_terms.len() as f64;
+                let highlights = highlight(&movie, &matched_terms[id]);
+                SearchHit {
+                    movie,
+                    score: score * fraction,
+                    highlights,
+                }
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.movie.title.cmp(&b.movie.title))
+                .then_with(|| a.movie.id.cmp(&b.movie.id))
+        });
+        hits.truncate(limit);
+        hits
+    }
+}
+
+/// One highlight per field value containing a matched term. Snippets are
+/// HTML, so the text around the `<em>` tags is escaped.
+fn highlight(movie: &Movie, terms: &HashSet<&str>) -> Vec<Highlight> {
+    FIELDS
+        .iter()
+        .flat_map(|field| {
+            field
+                .values(movie)
+                .into_iter()
+                .map(move |text| (field, text))
+        })
+        .filter_map(|(field, text)| {
+            let mut snippet = String::with_capacity(text.len() + 16);
+            let mut last = 0;
+            let mut matched = false;
+
+            for token in tokenize(text) {
+                if terms.contains(token.term.as_str()) {
+                    push_escaped(&mut snippet, &text[last..token.start]);
+                    snippet.push_str("<em>");
+                    push_escaped(&mut snippet, &text[token.start..token.end]);
+                    snippet.push_str("</em>");
+                    last = token.end;
+                    matched = true;
+                }
+            }
+            push_escaped(&mut snippet, &text[last..]);
+
+            matched.then_some(Highlight {
+                field: *field,
+                snippet,
+            })
+        })
+        .collect()
+}
+
+fn push_escaped(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie(id: &str, title: &str, genre: &str) -> Movie {
+        Movie {
+            id: id.to_string(),
+            title: title.to_string(),
+            genre: genre.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.movie.id.as_str()).collect()
+    }
+
+    fn snippet(hit: &SearchHit, field: Field) -> Option<&str> {
+        hit.highlights
+            .iter()
+            .find(|highlight| highlight.field == field)
+            .map(|highlight| highlight.snippet.as_str())
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions_as_one_edit() {
+        assert_eq!(edit_distance("matrix", "matrix", 2), Some(0));
+        assert_eq!(edit_distance("matrix", "matirx", 1), Some(1));
+        assert_eq!(edit_distance("matrix", "mtarix", 1), Some(1));
+        assert_eq!(edit_distance("matrix", "matrux", 1), Some(1));
+        assert_eq!(edit_distance("matrix", "matri", 1), Some(1));
+        assert_eq!(edit_distance("matrix", "mtarxi", 2), Some(2));
+    }
+
+    #[test]
+    fn edit_distance_gives_up_past_the_limit() {
+        assert_eq!(edit_distance("matrix", "mbtrux", 1), None);
+        assert_eq!(edit_distance("matrix", "mbtrux", 2), Some(2));
+        // Lengths alone rule it out.
+        assert_eq!(edit_distance("alien", "aliens vs", 2), None);
+        assert_eq!(edit_distance("", "abc", 2), None);
+        assert_eq!(edit_distance("", "ab", 2), Some(2));
+    }
+
+    #[test]
+    fn short_terms_must_match_exactly() {
+        let index = SearchIndex::new(&[movie("1", "Cat", "Drama")]);
+
+        assert_eq!(ids(&index.search("cat", 10)), ["1"]);
+        assert!(index.search("cap", 10).is_empty());
+        assert!(index.search("act", 10).is_empty());
+    }
+
+    #[test]
+    fn medium_terms_tolerate_one_edit() {
+        let index = SearchIndex::new(&[movie("1", "Matrix", "Action")]);
+
+        assert_eq!(ids(&index.search("matrux", 10)), ["1"]);
+        assert_eq!(ids(&index.search("matirx", 10)), ["1"]);
+        assert!(index.search("mbtrux", 10).is_empty());
+        assert!(index.search("mtairx", 10).is_empty());
+    }
+
+    #[test]
+    fn long_terms_tolerate_two_edits() {
+        let index = SearchIndex::new(&[movie("1", "Interstellar", "SciFi")]);
+
+        assert_eq!(ids(&index.search("intarstelar", 10)), ["1"]);
+        assert_eq!(ids(&index.search("itnerstellra", 10)), ["1"]);
+        assert!(index.search("intarstalar", 10).is_empty());
+    }
+
+    #[test]
+    fn terms_match_as_prefixes() {
+        let index = SearchIndex::new(&[movie("1", "Interstellar", "SciFi")]);
+
+        assert_eq!(ids(&index.search("inter", 10)), ["1"]);
+        // A single character is too unspecific to expand.
+        assert!(index.search("i", 10).is_empty());
+    }
+
+    #[test]
+    fn title_matches_rank_above_genre_matches() {
+        let index = SearchIndex::new(&[
+            movie("genre", "Heat", "Drama"),
+            movie("title", "Drama Queen", "Comedy"),
+        ]);
+
+        assert_eq!(ids(&index.search("drama", 10)), ["title", "genre"]);
+    }
+
+    #[test]
+    fn closer_matches_rank_higher() {
+        let index = SearchIndex::new(&[
+            movie("fuzzy", "Matrux", "Action"),
+            movie("prefix", "Matrixes", "Action"),
+            movie("exact", "Matrix", "Action"),
+        ]);
+
+        assert_eq!(
+            ids(&index.search("matrix", 10)),
+            ["exact", "prefix", "fuzzy"]
+        );
+    }
+
+    #[test]
+    fn movies_matching_more_terms_rank_higher() {
+        let index = SearchIndex::new(&[
+            movie("1", "Dark City", "SciFi"),
+            movie("2", "The Dark Knight", "Action"),
+            movie("3", "A Knight's Tale", "Adventure"),
+        ]);
+
+        let hits = index.search("dark knight", 10);
+        assert_eq!(hits[0].movie.id, "2");
+        assert_eq!(hits.len(), 3);
+        assert_eq!(ids(&index.search("dark knight", 1)), ["2"]);
+    }
+
+    #[test]
+    fn queries_without_terms_match_nothing() {
+        let index = SearchIndex::new(&[movie("1", "Heat", "Drama")]);
+
+        assert!(index.search("", 10).is_empty());
+        assert!(index.search(" -!? ", 10).is_empty());
+    }
+
+    #[test]
+    fn highlights_matched_terms_in_their_original_case() {
+        let index = SearchIndex::new(&[movie("1", "The Dark Knight", "Action")]);
+
+        let hits = index.search("KNIGHT dark", 10);
+        assert_eq!(
+            snippet(&hits[0], Field::Title),
+            Some("The <em>Dark</em> <em>Knight</em>")
+        );
+        // Only fields with a match are highlighted.
+        assert_eq!(snippet(&hits[0], Field::Genre), None);
+    }
+
+    #[test]
+    fn highlights_the_indexed_term_a_typo_matched() {
+        let index = SearchIndex::new(&[movie("1", "Spider-Man: Homecoming", "Action")]);
+
+        let hits = index.search("homecomnig", 10);
+        assert_eq!(
+            snippet(&hits[0], Field::Title),
+            Some("Spider-Man: <em>Homecoming</em>")
+        );
+
+        let hits = index.search("actoin", 10);
+        assert_eq!(snippet(&hits[0], Field::Genre), Some("<em>Action</em>"));
+        assert_eq!(snippet(&hits[0], Field::Title), None);
+    }
+
+    #[test]
+    fn highlights_escape_html_around_matches() {
+        let index = SearchIndex::new(&[movie("1", "Tom & Jerry's <b>\"Heist\"</b>", "Comedy")]);
+
+        let hits = index.search("heist", 10);
+        assert_eq!(
+            snippet(&hits[0], Field::Title),
+            Some("Tom &amp; Jerry&#39;s &lt;b&gt;&quot;<em>Heist</em>&quot;&lt;/b&gt;")
+        );
+    }
+
+    #[test]
+    fn every_genre_is_searchable() {
+        let mut heat = movie("1", "Heat", "Crime");
+        heat.genres = vec!["Crime".to_string(), "Thriller".to_string()];
+        let index = SearchIndex::new(&[heat.clone()]);
+
+        let hits = index.search("thriller", 10);
+        assert_eq!(ids(&hits), ["1"]);
+        assert_eq!(snippet(&hits[0], Field::Genre), Some("<em>Thriller</em>"));
+
+        heat.genres = vec!["Crime".to_string(), "Drama".to_string()];
+        index.upsert(&heat);
+
+        assert!(index.search("thriller", 10).is_empty());
+        assert_eq!(ids(&index.search("drama", 10)), ["1"]);
+        assert_eq!(ids(&index.search("crime", 10)), ["1"]);
+        assert!(!index
+            .inner
+            .read()
+            .unwrap()
+            .postings
+            .contains_key("thriller"));
+    }
+
+    #[test]
+    fn upsert_replaces_the_old_postings() {
+        let index =
+            SearchIndex::new(&[movie("1", "Alien", "Horror"), movie("2", "Heat", "Horror")]);
+
+        index.upsert(&movie("1", "Arrival", "Drama"));
+
+        assert!(index.search("alien", 10).is_empty());
+        assert_eq!(ids(&index.search("arrival", 10)), ["1"]);
+        assert_eq!(ids(&index.search("horror", 10)), ["2"]);
+        let hits = index.search("arrival", 10);
+        assert_eq!(hits[0].movie.title, "Arrival");
+
+        let inner = index.inner.read().unwrap();
+        assert!(!inner.postings.contains_key("alien"));
+        assert_eq!(inner.postings["horror"].len(), 1);
+        assert!(inner.postings["drama"].contains_key("1"));
+    }
+
+    #[test]
+    fn upsert_adds_new_movies() {
+        let index = SearchIndex::default();
+
+        index.upsert(&movie("1", "Heat", "Crime"));
+
+        assert_eq!(ids(&index.search("heat", 10)), ["1"]);
+    }
+
+    #[test]
+    fn remove_drops_the_movie_and_its_postings() {
+        let index = SearchIndex::new(&[
+            movie("1", "Heat", "Crime"),
+            movie("2", "Heat Wave", "Drama"),
+        ]);
+
+        index.remove("1");
+        // Removing an unknown movie is a no-op.
+        index.remove("missing");
+
+        assert_eq!(ids(&index.search("heat", 10)), ["2"]);
+        assert!(index.search("crime", 10).is_empty());
+
+        index.remove("2");
+        let inner = index.inner.read().unwrap();
+        assert!(inner.postings.is_empty());
+        assert!(inner.documents.is_empty());
+    }
+}
@@ -1,48 +1,27 @@
-use opentelemetry::{
-    global,
-    propagation::Extractor,
-    trace::{Span, SpanKind, Tracer},
-};
+use opentelemetry::global;
 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
 use opentelemetry_otlp::{LogExporter, MetricExporter, SpanExporter, WithExportConfig};
 use opentelemetry_sdk::logs::SdkLoggerProvider;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
 use opentelemetry_sdk::trace::SdkTracerProvider;
 use opentelemetry_sdk::Resource;
-use std::sync::{Arc, Mutex};
-use std::{collections::HashMap, error::Error, sync::OnceLock};
-use tonic::{transport::Server, Request, Response, Status};
+use std::sync::Arc;
+use std::{error::Error, future::Future, pin::Pin, sync::OnceLock};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::Server;
+use tonic_health::ServingStatus;
 use tracing_subscriber::prelude::*;
-use tracing_subscriber::EnvFilter;
-use uuid::Uuid;
 
-use movie::{
-    movie_service_server::MovieService, CreateMovieRequest, CreateMovieResponse,
-    DeleteMovieRequest, DeleteMovieResponse, Movie, ReadMovieRequest, ReadMovieResponse,
-    ReadMoviesRequest, ReadMoviesResponse, UpdateMovieRequest, UpdateMovieResponse,
-};
-
-pub mod movie {
-    tonic::include_proto!("movie");
-}
-
-struct MetadataMap<'a>(&'a tonic::metadata::MetadataMap);
-
-impl<'a> Extractor for MetadataMap<'a> {
-    fn get(&self, key: &str) -> Option<&str> {
-        self.0.get(key).and_then(|metadata| metadata.to_str().ok())
-    }
-
-    fn keys(&self) -> Vec<&str> {
-        self.0
-            .keys()
-            .map(|key| match key {
-                tonic::metadata::KeyRef::Ascii(v) => v.as_str(),
-                tonic::metadata::KeyRef::Binary(v) => v.as_str(),
-            })
-            .collect::<Vec<_>>()
-    }
-}
+use movie_tonic::auth::{AuthInterceptor, JwtVerifier};
+use movie_tonic::config::ServerConfig;
+use movie_tonic::health;
+use movie_tonic::movie::{self, movie_service_server::MovieServiceServer};
+use movie_tonic::rate_limit::RateLimiter;
+use movie_tonic::service::MovieServiceImpl;
+use movie_tonic::shutdown::Serving;
+use movie_tonic::tls;
+use movie_tonic::watch::ChangeFeed;
 
 pub struct Telemetry;
 
@@ -58,10 +37,10 @@ impl Telemetry {
             .clone()
     }
 
-    pub fn init_tracer() -> SdkTracerProvider {
+    pub fn init_tracer(endpoint: &str) -> SdkTracerProvider {
         let exporter = SpanExporter::builder()
             .with_tonic()
-            .with_endpoint("http://otel-collector:4317")
+            .with_endpoint(endpoint)
             .build()
             .expect("Failed to create span exporter");
         SdkTracerProvider::builder()
@@ -70,10 +49,10 @@ impl Telemetry {
             .build()
     }
 
-    pub fn init_meter() -> SdkMeterProvider {
+    pub fn init_meter(endpoint: &str) -> SdkMeterProvider {
         let exporter = MetricExporter::builder()
             .with_tonic()
-            .with_endpoint("http://otel-collector:4317")
+            .with_endpoint(endpoint)
             .build()
             .expect("Failed to create metric exporter");
 
@@ -83,10 +62,10 @@ impl Telemetry {
             .build()
     }
 
-    pub fn init_logger() -> SdkLoggerProvider {
+    pub fn init_logger(endpoint: &str) -> SdkLoggerProvider {
         let exporter = LogExporter::builder()
             .with_tonic()
-            .with_endpoint("http://otel-collector:4317")
+            .with_endpoint(endpoint)
             .build()
             .expect("Failed to create log exporter");
 
@@ -97,192 +76,35 @@ impl Telemetry {
     }
 }
 
-#[derive(Debug, Default, Clone)]
-struct MovieStore {
-    movies: Arc<Mutex<HashMap<String, Movie>>>,
-}
-
-#[derive(Debug, Default)]
-pub struct MovieServiceImpl {
-    store: MovieStore,
-}
-
-#[tonic::async_trait]
-impl MovieService for MovieServiceImpl {
-    async fn create_movie(
-        &self,
-        request: Request<CreateMovieRequest>,
-    ) -> Result<Response<CreateMovieResponse>, Status> {
-        let parent_cx =
-            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
-        let tracer = global::tracer("movie-server");
-        let mut span = tracer
-            .span_builder("CreateMovie")
-            .with_kind(SpanKind::Server)
-            .start_with_context(&tracer, &parent_cx);
-
-        let mut movies = self
-            .store
-            .movies
-            .lock()
-            .map_err(|_| Status::internal("Lock error"))?;
-
-        let mut movie = request
-            .into_inner()
-            .movie
-            .ok_or(Status::invalid_argument("No movie provided"))?;
-
-        if movie.id.is_empty() {
-            movie.id = Uuid::new_v4().to_string();
-            span.add_event(format!("Generated new movie ID: {}", movie.id), vec![]);
-        }
-
-        movies.insert(movie.id.clone(), movie.clone());
-
-        span.add_event("Movie created successfully", vec![]);
-
-        Ok(Response::new(CreateMovieResponse { movie: Some(movie) }))
-    }
-
-    async fn get_movie(
-        &self,
-        request: Request<ReadMovieRequest>,
-    ) -> Result<Response<ReadMovieResponse>, Status> {
-        // Extract parent context and create span
-        let parent_cx =
-            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
-        let tracer = global::tracer("movie-server");
-        let mut span = tracer
-            .span_builder("GetMovie")
-            .with_kind(SpanKind::Server)
-            .start_with_context(&tracer, &parent_cx);
-
-        let movies = self
-            .store
-            .movies
-            .lock()
-            .map_err(|_| Status::internal("Lock error"))?;
-
-        let id = request.into_inner().id;
-        span.add_event(format!("Fetching movie with ID: {}", id), vec![]);
-
-        let movie = movies
-            .get(&id)
-            .cloned()
-            .ok_or_else(|| Status::not_found("Movie not found"))?;
-
-        span.add_event("Movie retrieved successfully", vec![]);
-
-        Ok(Response::new(ReadMovieResponse { movie: Some(movie) }))
-    }
-
-    async fn get_movies(
-        &self,
-        request: Request<ReadMoviesRequest>,
-    ) -> Result<Response<ReadMoviesResponse>, Status> {
-        let parent_cx =
-            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
-        let tracer = global::tracer("movie-server");
-        let mut span = tracer
-            .span_builder("GetMovies")
-            .with_kind(SpanKind::Server)
-            .start_with_context(&tracer, &parent_cx);
-
-        let movies = self
-            .store
-            .movies
-            .lock()
-            .map_err(|_| Status::internal("Lock error"))?;
-
-        let movie_list: Vec<Movie> = movies.values().cloned().collect();
-
-        span.add_event(format!("Retrieved {} movies", movie_list.len()), vec![]);
-
-        Ok(Response::new(ReadMoviesResponse { movies: movie_list }))
-    }
-
-    async fn update_movie(
-        &self,
-        request: Request<UpdateMovieRequest>,
-    ) -> Result<Response<UpdateMovieResponse>, Status> {
-        let parent_cx =
-            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
-        let tracer = global::tracer("movie-server");
-        let mut span = tracer
-            .span_builder("UpdateMovie")
-            .with_kind(SpanKind::Server)
-            .start_with_context(&tracer, &parent_cx);
-
-        let mut movies = self
-            .store
-            .movies
-            .lock()
-            .map_err(|_| Status::internal("Lock error"))?;
-
-        let movie = request
-            .into_inner()
-            .movie
-            .ok_or(Status::invalid_argument("No movie provided"))?;
-
-        if !movies.contains_key(&movie.id) {
-            span.add_event(format!("Movie not found: {}", movie.id), vec![]);
-            return Err(Status::not_found("Movie not found"));
-        }
-
-        movies.insert(movie.id.clone(), movie.clone());
-
-        span.add_event(format!("Movie updated: {}", movie.id), vec![]);
-
-        Ok(Response::new(UpdateMovieResponse { movie: Some(movie) }))
-    }
-
-    async fn delete_movie(
-        &self,
-        request: Request<DeleteMovieRequest>,
-    ) -> Result<Response<DeleteMovieResponse>, Status> {
-        let parent_cx =
-            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
-        let tracer = global::tracer("movie-server");
-        let mut span = tracer
-            .span_builder("DeleteMovie")
-            .with_kind(SpanKind::Server)
-            .start_with_context(&tracer, &parent_cx);
-
-        let mut movies = self
-            .store
-            .movies
-            .lock()
-            .map_err(|_| Status::internal("Lock error"))?;
-
-        let id = request.into_inner().id;
-
-        let removed = movies.remove(&id).is_some();
-
-        span.add_event(
-            format!("Delete movie operation: ID = {}, Success = {}", id, removed),
-            vec![],
-        );
-
-        Ok(Response::new(DeleteMovieResponse { success: removed }))
-    }
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    let tracer_provider = Telemetry::init_tracer();
-    let meter_provider = Telemetry::init_meter();
-    let logger_provider = Telemetry::init_logger();
+    let config = match ServerConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("movie-server: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let tracer_provider = Telemetry::init_tracer(&config.telemetry.otlp_endpoint);
+    let meter_provider = Telemetry::init_meter(&config.telemetry.otlp_endpoint);
+    let logger_provider = Telemetry::init_logger(&config.telemetry.otlp_endpoint);
 
     let otel_layer = OpenTelemetryTracingBridge::new(&logger_provider);
 
-    let filter_otel = EnvFilter::new("info")
+    let filter_otel = config
+        .telemetry
+        .env_filter()
         .add_directive("hyper=off".parse().unwrap())
         .add_directive("tonic=off".parse().unwrap())
         .add_directive("h2=off".parse().unwrap())
         .add_directive("reqwest=off".parse().unwrap());
     let otel_layer = otel_layer.with_filter(filter_otel);
 
-    let filter_fmt = EnvFilter::new("info").add_directive("opentelemetry=debug".parse().unwrap());
+    let filter_fmt = config
+        .telemetry
+        .env_filter()
+        .add_directive("opentelemetry=debug".parse().unwrap());
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_thread_names(true)
         .with_filter(filter_fmt);
@@ -295,20 +117,86 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     global::set_tracer_provider(tracer_provider.clone());
     global::set_meter_provider(meter_provider.clone());
 
-    let addr = "0.0.0.0:50051".parse()?;
-    let movie_service = MovieServiceImpl::default();
+    let addr = config.listen_addr;
+    // Cancelled once the server stops accepting connections; also ends
+    // open watches so they do not hold the drain open.
+    let shutdown = CancellationToken::new();
+    let changes = Arc::new(ChangeFeed::new(config.watch_history).with_shutdown(shutdown.clone()));
+    let repository = config.storage.open().await?;
+    if config.rate_limit.is_enabled() {
+        tracing::info!("Rate limiting RPCs: {:?}", config.rate_limit);
+    }
+    let movie_service = MovieServiceImpl::new(repository.clone(), changes)
+        .await?
+        .with_rate_limiter(RateLimiter::new(config.rate_limit.clone()));
 
-    println!("Movie Service listening on {}", addr);
+    tracing::info!("Using {:?} storage backend", config.storage);
 
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let health_checks = tokio::spawn(health::report_storage_health(
+        health_reporter.clone(),
+        repository,
+        health::CHECK_INTERVAL,
+    ));
 
-    Server::builder()
-        .add_service(movie::movie_service_server::MovieServiceServer::new(
-            movie_service,
-        ))
-        .serve(addr)
-        .await?;
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(movie::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1()?;
 
+    println!("Movie Service listening on {}", addr);
 
+    let incoming = match &config.tls {
+        Some(files) => {
+            let listener = TcpListener::bind(addr).await?;
+            tracing::info!(
+                "TLS enabled (client certificates {})",
+                if files.client_ca.is_some() {
+                    "required"
+                } else {
+                    "not requested"
+                }
+            );
+            Some(tls::incoming(listener, files)?)
+        }
+        None => None,
+    };
+
+    let verifier = config.auth.as_ref().map(JwtVerifier::new).transpose()?;
+    if verifier.is_none() {
+        tracing::warn!("Authentication is disabled; every caller may modify movies");
+    }
+    let movie_service = MovieServiceServer::with_interceptor(
+        movie_service,
+        AuthInterceptor::new(verifier).with_trusted_proxies(config.trusted_proxies.clone()),
+    );
+
+    let serving = Serving::spawn(shutdown, |shutdown| {
+        let router = Server::builder()
+            .add_service(health_service)
+            .add_service(reflection_service)
+            .add_service(movie_service);
+        let shutdown = async move {
+            let _ = shutdown.await;
+        };
+        let serve: Pin<Box<dyn Future<Output = _> + Send>> = match incoming {
+            Some(incoming) => Box::pin(router.serve_with_incoming_shutdown(incoming, shutdown)),
+            None => Box::pin(router.serve_with_shutdown(addr, shutdown)),
+        };
+        serve
+    });
+    let served = serving
+        .run_until_signal(config.shutdown_delay, config.drain_timeout, async {
+            // Tell health-checking load balancers to stop routing here
+            // before in-flight requests are drained. The storage checks are
+            // stopped first so they cannot flip the status back.
+            health_checks.abort();
+            health::set_status(&health_reporter, ServingStatus::NotServing).await;
+        })
+        .await;
+
+    // Flush telemetry even when serving failed, so the spans that explain
+    // the failure are not lost.
     let mut shutdown_errors = Vec::new();
     if let Err(e) = tracer_provider.shutdown() {
         shutdown_errors.push(format!("tracer provider: {}", e));
@@ -322,6 +210,8 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         shutdown_errors.push(format!("logger provider: {}", e));
     }
 
+    served?;
+
     if !shutdown_errors.is_empty() {
         return Err(format!(
             "Failed to shutdown providers:\n{}",
@@ -0,0 +1,1381 @@
+use std::{future::Future, sync::Arc};
+
+use opentelemetry::{
+    global,
+    metrics::Counter,
+    propagation::Extractor,
+    trace::{Span, SpanKind, Tracer},
+    KeyValue,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+use uuid::Uuid;
+
+use crate::auth::{authorize, ForwardedFor, Identity, Role};
+use crate::field_mask::apply_update_mask;
+use crate::movie::{
+    delete_result, movie_result, movie_service_server::MovieService, BatchCreateMoviesRequest,
+    BatchCreateMoviesResponse, BatchDeleteMoviesRequest, BatchDeleteMoviesResponse,
+    BatchGetMoviesRequest, BatchGetMoviesResponse, CreateMovieRequest, CreateMovieResponse,
+    DeleteMovieRequest, DeleteMovieResponse, DeleteResult, Highlight, ItemError, Movie, MovieEvent,
+    MovieEventType, MovieResult, ReadMovieRequest, ReadMovieResponse, ReadMoviesRequest,
+    ReadMoviesResponse, SearchMoviesRequest, SearchMoviesResponse, SearchResult,
+    UpdateMovieRequest, UpdateMovieResponse, WatchMoviesRequest,
+};
+use crate::pagination;
+use crate::rate_limit::{self, Client, RateLimitConfig, RateLimiter};
+use crate::search::{self, SearchIndex};
+use crate::storage::{BatchError, MovieRepository, StorageError};
+use crate::validation::{normalize_movie, validate_batch_size, validate_id};
+use crate::watch::{ChangeFeed, Position};
+
+struct MetadataMap<'a>(&'a tonic::metadata::MetadataMap);
+
+impl<'a> Extractor for MetadataMap<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|metadata| metadata.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .map(|key| match key {
+                tonic::metadata::KeyRef::Ascii(v) => v.as_str(),
+                tonic::metadata::KeyRef::Binary(v) => v.as_str(),
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+/// Method names of `MovieService`, which per-RPC rate limits are keyed by.
+pub const RPCS: &[&str] = &[
+    "CreateMovie",
+    "GetMovie",
+    "GetMovies",
+    "UpdateMovie",
+    "DeleteMovie",
+    "SearchMovies",
+    "WatchMovies",
+    "BatchGetMovies",
+    "BatchCreateMovies",
+    "BatchDeleteMovies",
+];
+
+fn item_error(status: Status) -> ItemError {
+    ItemError {
+        code: status.code() as i32,
+        message: status.message().to_string(),
+    }
+}
+
+fn movie_result(result: Result<Movie, Status>) -> MovieResult {
+    MovieResult {
+        outcome: Some(match result {
+            Ok(movie) => movie_result::Outcome::Movie(movie),
+            Err(status) => movie_result::Outcome::Error(item_error(status)),
+        }),
+    }
+}
+
+fn delete_result(result: Result<bool, Status>) -> DeleteResult {
+    DeleteResult {
+        outcome: Some(match result {
+            Ok(success) => delete_result::Outcome::Success(success),
+            Err(status) => delete_result::Outcome::Error(item_error(status)),
+        }),
+    }
+}
+
+/// Results of an atomic batch that was not applied: items with an error of
+/// their own report it, and the rest `ABORTED`.
+fn rolled_back<T>(errors: Vec<Option<Status>>) -> Vec<Result<T, Status>> {
+    let failed = errors.iter().position(Option::is_some);
+    errors
+        .into_iter()
+        .map(|error| {
+            Err(error.unwrap_or_else(|| match failed {
+                Some(index) => Status::aborted(format!("Batch not applied: item {} failed", index)),
+                None => Status::aborted("Batch not applied"),
+            }))
+        })
+        .collect()
+}
+
+/// Applies an atomic batch if every item is valid. Fails outright only when
+/// the batch could not be applied for a reason other than one of its items.
+async fn apply_atomically<I, T, F, Fut>(
+    items: Vec<Result<I, Status>>,
+    apply: F,
+) -> Result<Vec<Result<T, Status>>, Status>
+where
+    F: FnOnce(Vec<I>) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, BatchError>>,
+{
+    if items.iter().any(Result::is_err) {
+        return Ok(rolled_back(items.into_iter().map(Result::err).collect()));
+    }
+
+    let count = items.len();
+    match apply(items.into_iter().flatten().collect()).await {
+        Ok(applied) => Ok(applied.into_iter().map(Ok).collect()),
+        Err(BatchError {
+            index: Some(index),
+            error,
+        }) => {
+            let mut errors: Vec<Option<Status>> = (0..count).map(|_| None).collect();
+            errors[index] = Some(error.into());
+            Ok(rolled_back(errors))
+        }
+        Err(BatchError { index: None, error }) => Err(error.into()),
+    }
+}
+
+/// gRPC `MovieService` backed by a [`MovieRepository`]. Reads go straight to
+/// the repository and never wait on `writes`, which only orders mutations.
+#[derive(Debug)]
+pub struct MovieServiceImpl {
+    repository: Arc<dyn MovieRepository>,
+    search_index: SearchIndex,
+    changes: Arc<ChangeFeed>,
+    // Serializes mutations so the search index and change feed observe them
+    // in the same order as the repository.
+    writes: tokio::sync::Mutex<()>,
+    rate_limiter: Arc<RateLimiter>,
+    throttled: Counter<u64>,
+}
+
+impl MovieServiceImpl {
+    pub async fn new(
+        repository: Arc<dyn MovieRepository>,
+        changes: Arc<ChangeFeed>,
+    ) -> Result<Self, StorageError> {
+        let movies = repository.list().await?;
+        let search_index = SearchIndex::new(&movies);
+
+        Ok(Self {
+            repository,
+            search_index,
+            changes,
+            writes: tokio::sync::Mutex::new(()),
+            rate_limiter: RateLimiter::new(RateLimitConfig::default()),
+            throttled: global::meter("movie-server")
+                .u64_counter("movie_throttled_requests")
+                .with_description("Requests rejected by the per-client rate limit")
+                .build(),
+        })
+    }
+
+    async fn find(&self, id: &str) -> Result<Movie, Status> {
+        validate_id(id)?;
+        self.repository
+            .get(id)
+            .await?
+            .ok_or_else(|| Status::not_found("Movie not found"))
+    }
+
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Counts the call against its caller's limit for `rpc`. Callers are
+    /// told apart by subject, including the API key a trusted gateway
+    /// called for, falling back to the client address a trusted gateway
+    /// forwarded and then to the peer address when authentication is
+    /// disabled or no token was sent.
+    fn throttle<T>(&self, request: &Request<T>, rpc: &'static str) -> Result<(), Status> {
+        let extensions = request.extensions();
+        let client = match extensions.get::<Identity>() {
+            Some(identity) if *identity != Identity::anonymous() => {
+                Client::Subject(identity.subject.clone())
+            }
+            _ => match extensions.get::<ForwardedFor>() {
+                Some(ForwardedFor(addr)) => Client::Ip(*addr),
+                None => request
+                    .remote_addr()
+                    .map(|addr| Client::Ip(addr.ip()))
+                    .unwrap_or(Client::Unknown),
+            },
+        };
+
+        self.rate_limiter
+            .check(rpc, &client)
+            .map_err(|retry_after| {
+                self.throttled.add(
+                    1,
+                    &[
+                        KeyValue::new("rpc", rpc),
+                        KeyValue::new("client_type", client.kind()),
+                    ],
+                );
+                Status::with_error_details(
+                    Code::ResourceExhausted,
+                    format!(
+                        "Rate limit exceeded; retry in {}s",
+                        rate_limit::retry_after_secs(retry_after)
+                    ),
+                    ErrorDetails::with_retry_info(Some(retry_after)),
+                )
+            })
+    }
+}
+
+#[tonic::async_trait]
+impl MovieService for MovieServiceImpl {
+    type WatchMoviesStream = ReceiverStream<Result<MovieEvent, Status>>;
+
+    async fn create_movie(
+        &self,
+        request: Request<CreateMovieRequest>,
+    ) -> Result<Response<CreateMovieResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("CreateMovie")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "CreateMovie") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let caller = authorize(&request, Role::Editor)?;
+        span.set_attribute(KeyValue::new("enduser.id", caller.subject.clone()));
+
+        let mut movie = request
+            .into_inner()
+            .movie
+            .ok_or(Status::invalid_argument("No movie provided"))?;
+
+        if movie.id.is_empty() {
+            movie.id = Uuid::new_v4().to_string();
+            span.add_event(format!("Generated new movie ID: {}", movie.id), vec![]);
+        }
+        normalize_movie(&mut movie)?;
+
+        let _write = self.writes.lock().await;
+        let movie = self.repository.create(movie).await?;
+        self.search_index.upsert(&movie);
+        self.changes.publish(MovieEventType::Created, movie.clone());
+
+        span.add_event("Movie created successfully", vec![]);
+
+        Ok(Response::new(CreateMovieResponse { movie: Some(movie) }))
+    }
+
+    async fn get_movie(
+        &self,
+        request: Request<ReadMovieRequest>,
+    ) -> Result<Response<ReadMovieResponse>, Status> {
+        // Extract parent context and create span
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("GetMovie")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "GetMovie") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let id = request.into_inner().id;
+        validate_id(&id)?;
+        span.add_event(format!("Fetching movie with ID: {}", id), vec![]);
+
+        let movie = self
+            .repository
+            .get(&id)
+            .await?
+            .ok_or_else(|| Status::not_found("Movie not found"))?;
+
+        span.add_event("Movie retrieved successfully", vec![]);
+
+        Ok(Response::new(ReadMovieResponse { movie: Some(movie) }))
+    }
+
+    async fn get_movies(
+        &self,
+        request: Request<ReadMoviesRequest>,
+    ) -> Result<Response<ReadMoviesResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("GetMovies")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "GetMovies") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let mut query = pagination::query_from_request(request.get_ref())?;
+        let page_size = query.limit;
+        query.limit += 1;
+
+        let mut movie_list = self.repository.query(&query).await?;
+
+        let next_page_token = if movie_list.len() > page_size {
+            movie_list.truncate(page_size);
+            movie_list
+                .last()
+                .map(|last| pagination::next_page_token(&query, last))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        span.add_event(format!("Retrieved {} movies", movie_list.len()), vec![]);
+
+        Ok(Response::new(ReadMoviesResponse {
+            movies: movie_list,
+            next_page_token,
+        }))
+    }
+
+    async fn update_movie(
+        &self,
+        request: Request<UpdateMovieRequest>,
+    ) -> Result<Response<UpdateMovieResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("UpdateMovie")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "UpdateMovie") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let caller = authorize(&request, Role::Editor)?;
+        span.set_attribute(KeyValue::new("enduser.id", caller.subject.clone()));
+
+        let request = request.into_inner();
+        let mut movie = request
+            .movie
+            .ok_or(Status::invalid_argument("No movie provided"))?;
+        validate_id(&movie.id)?;
+        let mut expected_version = request.expected_version;
+
+        let _write = self.writes.lock().await;
+        if let Some(mask) = request.update_mask.filter(|mask| !mask.paths.is_empty()) {
+            let mut current = self
+                .repository
+                .get(&movie.id)
+                .await?
+                .ok_or_else(|| Status::not_found("Movie not found"))?;
+            apply_update_mask(&mask, movie, &mut current)?;
+            span.add_event(
+                format!("Applying update mask: {}", mask.paths.join(",")),
+                vec![],
+            );
+            // Pin the version that was read so a writer in another process
+            // cannot be silently overwritten between the read and the write.
+            expected_version = expected_version.or(Some(current.version));
+            movie = current;
+        }
+        normalize_movie(&mut movie)?;
+
+        let movie = match self.repository.update(movie, expected_version).await {
+            Ok(movie) => {
+                self.search_index.upsert(&movie);
+                self.changes.publish(MovieEventType::Updated, movie.clone());
+                movie
+            }
+            Err(err) => {
+                span.add_event(format!("Movie update failed: {}", err), vec![]);
+                return Err(err.into());
+            }
+        };
+
+        span.add_event(format!("Movie updated: {}", movie.id), vec![]);
+
+        Ok(Response::new(UpdateMovieResponse { movie: Some(movie) }))
+    }
+
+    async fn delete_movie(
+        &self,
+        request: Request<DeleteMovieRequest>,
+    ) -> Result<Response<DeleteMovieResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("DeleteMovie")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "DeleteMovie") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let caller = authorize(&request, Role::Admin)?;
+        span.set_attribute(KeyValue::new("enduser.id", caller.subject.clone()));
+
+        let DeleteMovieRequest {
+            id,
+            expected_version,
+        } = request.into_inner();
+        validate_id(&id)?;
+
+        let _write = self.writes.lock().await;
+        let removed = match self.repository.delete(&id, expected_version).await {
+            Ok(removed) => removed,
+            Err(err) => {
+                span.add_event(format!("Movie delete failed: {}", err), vec![]);
+                return Err(err.into());
+            }
+        };
+        if removed {
+            self.search_index.remove(&id);
+            self.changes.publish(
+                MovieEventType::Deleted,
+                Movie {
+                    id: id.clone(),
+                    ..Default::default()
+                },
+            );
+        }
+
+        span.add_event(
+            format!("Delete movie operation: ID = {}, Success = {}", id, removed),
+            vec![],
+        );
+
+        Ok(Response::new(DeleteMovieResponse { success: removed }))
+    }
+
+    async fn search_movies(
+        &self,
+        request: Request<SearchMoviesRequest>,
+    ) -> Result<Response<SearchMoviesResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("SearchMovies")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "SearchMovies") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let request = request.into_inner();
+        if request.limit < 0 {
+            return Err(Status::invalid_argument("limit must not be negative"));
+        }
+        let limit = match request.limit as usize {
+            0 => search::DEFAULT_LIMIT,
+            limit => limit.min(search::MAX_LIMIT),
+        };
+
+        let results: Vec<SearchResult> = self
+            .search_index
+            .search(&request.query, limit)
+            .into_iter()
+            .map(|hit| SearchResult {
+                movie: Some(hit.movie),
+                score: hit.score,
+                highlights: hit
+                    .highlights
+                    .into_iter()
+                    .map(|highlight| Highlight {
+                        field: highlight.field.as_str().to_string(),
+                        snippet: highlight.snippet,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        span.add_event(
+            format!(
+                "Search for {:?} returned {} movies",
+                request.query,
+                results.len()
+            ),
+            vec![],
+        );
+
+        Ok(Response::new(SearchMoviesResponse { results }))
+    }
+
+    async fn watch_movies(
+        &self,
+        request: Request<WatchMoviesRequest>,
+    ) -> Result<Response<Self::WatchMoviesStream>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("WatchMovies")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "WatchMovies") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let request = request.into_inner();
+        let after = request.after_revision.map(|revision| Position {
+            epoch: request.epoch,
+            revision,
+        });
+
+        let stream = match self.changes.watch(after) {
+            Ok(stream) => stream,
+            Err(status) => {
+                span.add_event(format!("Watch rejected: {}", status.message()), vec![]);
+                return Err(status);
+            }
+        };
+
+        span.add_event(
+            format!(
+                "Watching movies after {} (current position {})",
+                after.map_or("now".to_string(), |after| after.to_string()),
+                Position {
+                    epoch: self.changes.epoch(),
+                    revision: self.changes.revision(),
+                }
+            ),
+            vec![],
+        );
+
+        Ok(Response::new(stream))
+    }
+
+    async fn batch_get_movies(
+        &self,
+        request: Request<BatchGetMoviesRequest>,
+    ) -> Result<Response<BatchGetMoviesResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("BatchGetMovies")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "BatchGetMovies") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let ids = request.into_inner().ids;
+        validate_batch_size("ids", ids.len())?;
+
+        let mut results = Vec::with_capacity(ids.len());
+        for id in &ids {
+            results.push(self.find(id).await);
+        }
+
+        span.add_event(
+            format!(
+                "Retrieved {} of {} movies",
+                results.iter().filter(|result| result.is_ok()).count(),
+                results.len()
+            ),
+            vec![],
+        );
+
+        Ok(Response::new(BatchGetMoviesResponse {
+            results: results.into_iter().map(movie_result).collect(),
+        }))
+    }
+
+    async fn batch_create_movies(
+        &self,
+        request: Request<BatchCreateMoviesRequest>,
+    ) -> Result<Response<BatchCreateMoviesResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("BatchCreateMovies")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "BatchCreateMovies") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let caller = authorize(&request, Role::Editor)?;
+        span.set_attribute(KeyValue::new("enduser.id", caller.subject.clone()));
+
+        let BatchCreateMoviesRequest { movies, atomic } = request.into_inner();
+        validate_batch_size("movies", movies.len())?;
+        span.set_attribute(KeyValue::new("batch.atomic", atomic));
+
+        let movies: Vec<Result<Movie, Status>> = movies
+            .into_iter()
+            .map(|mut movie| {
+                if movie.id.is_empty() {
+                    movie.id = Uuid::new_v4().to_string();
+                }
+                normalize_movie(&mut movie)?;
+                Ok(movie)
+            })
+            .collect();
+
+        let _write = self.writes.lock().await;
+        let results = if atomic {
+            apply_atomically(movies, |movies| self.repository.create_batch(movies)).await?
+        } else {
+            let mut results = Vec::with_capacity(movies.len());
+            for movie in movies {
+                results.push(match movie {
+                    Ok(movie) => self.repository.create(movie).await.map_err(Status::from),
+                    Err(status) => Err(status),
+                });
+            }
+            results
+        };
+        for movie in results.iter().flatten() {
+            self.search_index.upsert(movie);
+            self.changes.publish(MovieEventType::Created, movie.clone());
+        }
+
+        span.add_event(
+            format!(
+                "Created {} of {} movies",
+                results.iter().filter(|result| result.is_ok()).count(),
+                results.len()
+            ),
+            vec![],
+        );
+
+        Ok(Response::new(BatchCreateMoviesResponse {
+            results: results.into_iter().map(movie_result).collect(),
+        }))
+    }
+
+    async fn batch_delete_movies(
+        &self,
+        request: Request<BatchDeleteMoviesRequest>,
+    ) -> Result<Response<BatchDeleteMoviesResponse>, Status> {
+        let parent_cx =
+            global::get_text_map_propagator(|prop| prop.extract(&MetadataMap(request.metadata())));
+        let tracer = global::tracer("movie-server");
+        let mut span = tracer
+            .span_builder("BatchDeleteMovies")
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &parent_cx);
+
+        if let Err(status) = self.throttle(&request, "BatchDeleteMovies") {
+            span.add_event("Rate limit exceeded", vec![]);
+            return Err(status);
+        }
+
+        let caller = authorize(&request, Role::Admin)?;
+        span.set_attribute(KeyValue::new("enduser.id", caller.subject.clone()));
+
+        let BatchDeleteMoviesRequest { deletes, atomic } = request.into_inner();
+        validate_batch_size("deletes", deletes.len())?;
+        span.set_attribute(KeyValue::new("batch.atomic", atomic));
+
+        let ids: Vec<String> = deletes.iter().map(|delete| delete.id.clone()).collect();
+        let deletes: Vec<Result<(String, Option<u64>), Status>> = deletes
+            .into_iter()
+            .map(|delete| {
+                validate_id(&delete.id)?;
+                Ok((delete.id, delete.expected_version))
+            })
+            .collect();
+
+        let _write = self.writes.lock().await;
+        let results = if atomic {
+            apply_atomically(deletes, |deletes| self.repository.delete_batch(deletes)).await?
+        } else {
+            let mut results = Vec::with_capacity(deletes.len());
+            for delete in deletes {
+                results.push(match delete {
+                    Ok((id, expected_version)) => self
+                        .repository
+                        .delete(&id, expected_version)
+                        .await
+                        .map_err(Status::from),
+                    Err(status) => Err(status),
+                });
+            }
+            results
+        };
+        for (id, result) in ids.iter().zip(&results) {
+            if let Ok(true) = result {
+                self.search_index.remove(id);
+                self.changes.publish(
+                    MovieEventType::Deleted,
+                    Movie {
+                        id: id.clone(),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+
+        span.add_event(
+            format!(
+                "Deleted {} of {} movies",
+                results
+                    .iter()
+                    .filter(|result| matches!(result, Ok(true)))
+                    .count(),
+                results.len()
+            ),
+            vec![],
+        );
+
+        Ok(Response::new(BatchDeleteMoviesResponse {
+            results: results.into_iter().map(delete_result).collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::movie::MovieFilter;
+    use crate::storage::{JournaledMovieStore, MovieStore, SqliteMovieStore};
+
+    /// Journal directory of the backends under test, removed when dropped.
+    struct ScratchDir(PathBuf);
+
+    impl Drop for ScratchDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// One empty service per storage backend.
+    async fn services() -> (ScratchDir, Vec<(&'static str, MovieServiceImpl)>) {
+        let dir =
+            ScratchDir(std::env::temp_dir().join(format!("movie-service-{}", Uuid::new_v4())));
+        let repositories: Vec<(&'static str, Arc<dyn MovieRepository>)> = vec![
+            ("memory", Arc::new(MovieStore::default())),
+            (
+                "journal",
+                Arc::new(
+                    JournaledMovieStore::open(&dir.0, std::time::Duration::from_secs(3600))
+                        .await
+                        .unwrap(),
+                ),
+            ),
+            (
+                "sqlite",
+                Arc::new(SqliteMovieStore::open(":memory:").await.unwrap()),
+            ),
+        ];
+
+        let mut services = Vec::new();
+        for (name, repository) in repositories {
+            let service = MovieServiceImpl::new(repository, Arc::new(ChangeFeed::new(16)))
+                .await
+                .unwrap();
+            services.push((name, service));
+        }
+        (dir, services)
+    }
+
+    fn movie(id: &str, title: &str, genre: &str) -> Movie {
+        Movie {
+            id: id.to_string(),
+            title: title.to_string(),
+            genre: genre.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn as_admin<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(Identity::anonymous());
+        request
+    }
+
+    async fn create(service: &MovieServiceImpl, movie: Movie) {
+        service
+            .create_movie(as_admin(CreateMovieRequest { movie: Some(movie) }))
+            .await
+            .unwrap();
+    }
+
+    async fn page(
+        service: &MovieServiceImpl,
+        request: ReadMoviesRequest,
+    ) -> Result<(Vec<String>, String), Status> {
+        let response = service
+            .get_movies(Request::new(request))
+            .await?
+            .into_inner();
+        let ids = response.movies.into_iter().map(|movie| movie.id).collect();
+        Ok((ids, response.next_page_token))
+    }
+
+    async fn all_pages(service: &MovieServiceImpl, mut request: ReadMoviesRequest) -> Vec<String> {
+        let mut ids = Vec::new();
+        loop {
+            let (page_ids, token) = page(service, request.clone()).await.unwrap();
+            ids.extend(page_ids);
+            if token.is_empty() {
+                return ids;
+            }
+            request.page_token = token;
+        }
+    }
+
+    async fn stored_ids(service: &MovieServiceImpl) -> Vec<String> {
+        all_pages(service, ReadMoviesRequest::default()).await
+    }
+
+    fn movie_codes(response: BatchCreateMoviesResponse) -> Vec<Result<String, Code>> {
+        response
+            .results
+            .into_iter()
+            .map(|result| match result.outcome.unwrap() {
+                movie_result::Outcome::Movie(movie) => Ok(movie.id),
+                movie_result::Outcome::Error(error) => Err(Code::from_i32(error.code)),
+            })
+            .collect()
+    }
+
+    fn delete_codes(response: BatchDeleteMoviesResponse) -> Vec<Result<bool, Code>> {
+        response
+            .results
+            .into_iter()
+            .map(|result| match result.outcome.unwrap() {
+                delete_result::Outcome::Success(success) => Ok(success),
+                delete_result::Outcome::Error(error) => Err(Code::from_i32(error.code)),
+            })
+            .collect()
+    }
+
+    fn title_prefix(prefix: &str) -> Option<MovieFilter> {
+        Some(MovieFilter {
+            title_prefix: prefix.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn title_prefix_folds_case_the_same_on_every_backend() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            create(service, movie("elite", "Élite", "Drama")).await;
+            create(service, movie("elan", "élan vital", "Drama")).await;
+            create(service, movie("eagle", "Eagle Eye", "Action")).await;
+            create(service, movie("strasse", "STRASSE", "Drama")).await;
+
+            for prefix in ["É", "é", "ÉL"] {
+                let request = ReadMoviesRequest {
+                    filter: title_prefix(prefix),
+                    ..Default::default()
+                };
+                assert_eq!(
+                    all_pages(service, request).await,
+                    ["elan", "elite"],
+                    "{name}: prefix {prefix:?}"
+                );
+            }
+
+            let request = ReadMoviesRequest {
+                filter: title_prefix("e"),
+                ..Default::default()
+            };
+            assert_eq!(all_pages(service, request).await, ["eagle"], "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cursor_is_stable_under_inserts() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            for (id, title) in [
+                ("a", "Alien"),
+                ("b", "Brazil"),
+                ("c", "Cube"),
+                ("d", "Dune"),
+            ] {
+                create(service, movie(id, title, "Sci-Fi")).await;
+            }
+
+            let request = ReadMoviesRequest {
+                page_size: 2,
+                order_by: "title".to_string(),
+                ..Default::default()
+            };
+            let (first, token) = page(service, request.clone()).await.unwrap();
+            assert_eq!(first, ["a", "b"], "{name}");
+
+            // One movie sorts before the cursor and one after it.
+            create(service, movie("aa", "Abyss", "Sci-Fi")).await;
+            create(service, movie("bb", "Brazil", "Sci-Fi")).await;
+
+            let rest = all_pages(
+                service,
+                ReadMoviesRequest {
+                    page_token: token,
+                    ..request
+                },
+            )
+            .await;
+            assert_eq!(rest, ["bb", "c", "d"], "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn page_token_is_rejected_for_a_different_query() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            for id in ["a", "b", "c"] {
+                create(service, movie(id, "Heat", "Crime")).await;
+            }
+
+            let request = ReadMoviesRequest {
+                page_size: 1,
+                filter: title_prefix("he"),
+                ..Default::default()
+            };
+            let (_, token) = page(service, request.clone()).await.unwrap();
+            assert!(!token.is_empty(), "{name}");
+
+            for changed in [
+                ReadMoviesRequest {
+                    filter: title_prefix("hea"),
+                    ..request.clone()
+                },
+                ReadMoviesRequest {
+                    filter: Some(MovieFilter {
+                        genre: "Crime".to_string(),
+                        title_prefix: "he".to_string(),
+                    }),
+                    ..request.clone()
+                },
+                ReadMoviesRequest {
+                    order_by: "id desc".to_string(),
+                    ..request.clone()
+                },
+            ] {
+                let status = page(
+                    service,
+                    ReadMoviesRequest {
+                        page_token: token.clone(),
+                        ..changed
+                    },
+                )
+                .await
+                .unwrap_err();
+                assert_eq!(status.code(), Code::InvalidArgument, "{name}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn descending_sort_pages_through_ties() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            for (id, title) in [
+                ("a", "Heat"),
+                ("b", "Alien"),
+                ("c", "Heat"),
+                ("d", "Zodiac"),
+                ("e", "Heat"),
+            ] {
+                create(service, movie(id, title, "Crime")).await;
+            }
+
+            let ids = all_pages(
+                service,
+                ReadMoviesRequest {
+                    page_size: 2,
+                    order_by: "title desc".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await;
+            assert_eq!(ids, ["d", "e", "c", "a", "b"], "{name}");
+
+            let ids = all_pages(
+                service,
+                ReadMoviesRequest {
+                    page_size: 2,
+                    order_by: "id desc".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await;
+            assert_eq!(ids, ["e", "d", "c", "b", "a"], "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn atomic_create_writes_nothing_when_an_item_conflicts() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            create(service, movie("taken", "Heat", "Crime")).await;
+
+            let response = service
+                .batch_create_movies(as_admin(BatchCreateMoviesRequest {
+                    movies: vec![
+                        movie("a", "Alien", "Sci-Fi"),
+                        movie("taken", "Heat", "Crime"),
+                        movie("b", "Brazil", "Sci-Fi"),
+                    ],
+                    atomic: true,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+
+            assert_eq!(
+                movie_codes(response),
+                [
+                    Err(Code::Aborted),
+                    Err(Code::AlreadyExists),
+                    Err(Code::Aborted)
+                ],
+                "{name}"
+            );
+            assert_eq!(stored_ids(service).await, ["taken"], "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn atomic_create_writes_nothing_when_an_item_is_invalid() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            let response = service
+                .batch_create_movies(as_admin(BatchCreateMoviesRequest {
+                    movies: vec![
+                        movie("a", "Alien", "Sci-Fi"),
+                        movie("b", "", "Sci-Fi"),
+                        // Repeats an id earlier in the same batch.
+                        movie("a", "Abyss", "Sci-Fi"),
+                    ],
+                    atomic: true,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+
+            assert_eq!(
+                movie_codes(response),
+                [
+                    Err(Code::Aborted),
+                    Err(Code::InvalidArgument),
+                    Err(Code::Aborted)
+                ],
+                "{name}"
+            );
+            assert!(stored_ids(service).await.is_empty(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn atomic_create_rejects_ids_repeated_within_the_batch() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            let response = service
+                .batch_create_movies(as_admin(BatchCreateMoviesRequest {
+                    movies: vec![movie("a", "Alien", "Sci-Fi"), movie("a", "Abyss", "Sci-Fi")],
+                    atomic: true,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+
+            assert_eq!(
+                movie_codes(response),
+                [Err(Code::Aborted), Err(Code::AlreadyExists)],
+                "{name}"
+            );
+            assert!(stored_ids(service).await.is_empty(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn non_atomic_create_applies_the_items_that_succeed() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            let response = service
+                .batch_create_movies(as_admin(BatchCreateMoviesRequest {
+                    movies: vec![movie("a", "Alien", "Sci-Fi"), movie("b", "", "Sci-Fi")],
+                    atomic: false,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+
+            assert_eq!(
+                movie_codes(response),
+                [Ok("a".to_string()), Err(Code::InvalidArgument)],
+                "{name}"
+            );
+            assert_eq!(stored_ids(service).await, ["a"], "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn atomic_delete_writes_nothing_on_a_version_mismatch() {
+        let (_dir, services) = services().await;
+        for (name, service) in &services {
+            for id in ["a", "b", "c"] {
+                create(service, movie(id, "Heat", "Crime")).await;
+            }
+
+            let delete = |id: &str, expected_version| DeleteMovieRequest {
+                id: id.to_string(),
+                expected_version,
+            };
+            let response = service
+                .batch_delete_movies(as_admin(BatchDeleteMoviesRequest {
+                    deletes: vec![
+                        delete("a", Some(1)),
+                        delete("missing", None),
+                        delete("b", Some(7)),
+                        delete("c", None),
+                    ],
+                    atomic: true,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+
+            let results = response.results.clone();
+            assert_eq!(
+                delete_codes(response),
+                [
+                    Err(Code::Aborted),
+                    Err(Code::Aborted),
+                    Err(Code::Aborted),
+                    Err(Code::Aborted)
+                ],
+                "{name}"
+            );
+            // Only the item that failed carries its own reason.
+            let messages: Vec<String> = results
+                .into_iter()
+                .map(|result| match result.outcome.unwrap() {
+                    delete_result::Outcome::Error(error) => error.message,
+                    other => panic!("{name}: unexpected {other:?}"),
+                })
+                .collect();
+            assert!(messages[2].contains("version mismatch"), "{name}");
+            for index in [0, 1, 3] {
+                assert_eq!(
+                    messages[index], "Batch not applied: item 2 failed",
+                    "{name}"
+                );
+            }
+            assert_eq!(stored_ids(service).await, ["a", "b", "c"], "{name}");
+
+            let response = service
+                .batch_delete_movies(as_admin(BatchDeleteMoviesRequest {
+                    deletes: vec![delete("a", Some(1)), delete("missing", None)],
+                    atomic: true,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(delete_codes(response), [Ok(true), Ok(false)], "{name}");
+            assert_eq!(stored_ids(service).await, ["b", "c"], "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn metadata_round_trips_on_every_backend() {
+        let (_dir, services) = services().await;
+        let heat = Movie {
+            genres: vec!["Crime".to_string(), "Thriller".to_string()],
+            release_year: 1995,
+            runtime_minutes: 170,
+            directors: vec!["Michael Mann".to_string()],
+            cast: vec![
+                crate::movie::CastMember {
+                    name: "Al Pacino".to_string(),
+                    character: "Vincent Hanna".to_string(),
+                },
+                crate::movie::CastMember {
+                    name: "Robert De Niro".to_string(),
+                    character: String::new(),
+                },
+            ],
+            certification: "R".to_string(),
+            synopsis: "A detective hunts a crew of thieves.".to_string(),
+            language: "en".to_string(),
+            ..movie("heat", "Heat", "")
+        };
+
+        for (name, service) in services {
+            create(&service, heat.clone()).await;
+            let stored = service
+                .get_movie(Request::new(ReadMovieRequest {
+                    id: "heat".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .movie
+                .unwrap();
+            let created_at = stored.created_at.expect("created_at is assigned");
+            assert_eq!(stored.updated_at, Some(created_at), "{}", name);
+            assert_eq!(
+                stored,
+                Movie {
+                    genre: "Crime".to_string(),
+                    version: 1,
+                    created_at: Some(created_at),
+                    updated_at: Some(created_at),
+                    ..heat.clone()
+                },
+                "{}",
+                name
+            );
+
+            let updated = service
+                .update_movie(as_admin(UpdateMovieRequest {
+                    movie: Some(Movie {
+                        synopsis: "Retold.".to_string(),
+                        ..stored
+                    }),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .movie
+                .unwrap();
+            assert_eq!(updated.synopsis, "Retold.", "{}", name);
+            assert_eq!(updated.cast, heat.cast, "{}", name);
+            assert_eq!(updated.created_at, Some(created_at), "{}", name);
+            let updated_at = updated.updated_at.unwrap();
+            assert!(
+                (updated_at.seconds, updated_at.nanos) >= (created_at.seconds, created_at.nanos),
+                "{}",
+                name
+            );
+        }
+    }
+
+    fn as_role<T>(role: Role, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(Identity {
+            subject: "alice".to_string(),
+            role,
+        });
+        request
+    }
+
+    #[tokio::test]
+    async fn viewer_cannot_create_movies() {
+        let service = MovieServiceImpl::new(
+            Arc::new(MovieStore::default()),
+            Arc::new(ChangeFeed::new(16)),
+        )
+        .await
+        .unwrap();
+
+        let status = service
+            .create_movie(as_role(
+                Role::Viewer,
+                CreateMovieRequest {
+                    movie: Some(movie("heat", "Heat", "Crime")),
+                },
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        let status = service
+            .create_movie(Request::new(CreateMovieRequest {
+                movie: Some(movie("heat", "Heat", "Crime")),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert!(stored_ids(&service).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn editor_cannot_delete_movies() {
+        let service = MovieServiceImpl::new(
+            Arc::new(MovieStore::default()),
+            Arc::new(ChangeFeed::new(16)),
+        )
+        .await
+        .unwrap();
+        service
+            .create_movie(as_role(
+                Role::Editor,
+                CreateMovieRequest {
+                    movie: Some(movie("heat", "Heat", "Crime")),
+                },
+            ))
+            .await
+            .unwrap();
+
+        let delete = || DeleteMovieRequest {
+            id: "heat".to_string(),
+            expected_version: None,
+        };
+        let status = service
+            .delete_movie(as_role(Role::Editor, delete()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(stored_ids(&service).await, ["heat"]);
+
+        service
+            .delete_movie(as_role(Role::Admin, delete()))
+            .await
+            .unwrap();
+        assert!(stored_ids(&service).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn throttled_calls_carry_retry_info_per_forwarded_client() {
+        let service = MovieServiceImpl::new(
+            Arc::new(MovieStore::default()),
+            Arc::new(ChangeFeed::new(16)),
+        )
+        .await
+        .unwrap()
+        .with_rate_limiter(RateLimiter::new(RateLimitConfig {
+            default: Some(rate_limit::RateLimit {
+                per_second: 1,
+                burst: 1,
+            }),
+            ..Default::default()
+        }));
+        let list = |addr: &str| {
+            let mut request = Request::new(ReadMoviesRequest::default());
+            request
+                .extensions_mut()
+                .insert(ForwardedFor(addr.parse().unwrap()));
+            request
+        };
+
+        service.get_movies(list("203.0.113.7")).await.unwrap();
+        let status = service.get_movies(list("203.0.113.7")).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let retry_info = status.get_details_retry_info().unwrap();
+        assert!(retry_info.retry_delay.unwrap() <= std::time::Duration::from_secs(1));
+
+        // Another client behind the same gateway has its own bucket.
+        service.get_movies(list("203.0.113.8")).await.unwrap();
+    }
+}
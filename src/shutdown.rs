@@ -0,0 +1,267 @@
+//! Signal handling and connection draining shared by both binaries.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_SHUTDOWN_DELAY: Duration = Duration::from_secs(5);
+
+/// Resolves on the first SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut stream) => {
+                stream.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+/// A server running on its own task, stopped by cancelling its shutdown
+/// token.
+pub struct Serving<E> {
+    handle: JoinHandle<Result<(), E>>,
+    shutdown: CancellationToken,
+}
+
+impl<E: Send + 'static> Serving<E> {
+    /// Spawns the future returned by `serve`, which receives the future that
+    /// should resolve once the server is asked to stop accepting connections,
+    /// i.e. the argument to `serve_with_shutdown`/`with_graceful_shutdown`.
+    /// Long-lived streams should end when `shutdown` is cancelled too, or
+    /// they hold the drain open until it times out.
+    pub fn spawn<F, S>(shutdown: CancellationToken, serve: S) -> Self
+    where
+        S: FnOnce(WaitForCancellationFutureOwned) -> F,
+        F: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        Self {
+            handle: tokio::spawn(serve(shutdown.clone().cancelled_owned())),
+            shutdown,
+        }
+    }
+
+    /// Runs until the server exits on its own or a shutdown signal arrives;
+    /// see `run_until`.
+    pub async fn run_until_signal<H>(
+        self,
+        shutdown_delay: Duration,
+        drain_timeout: Duration,
+        on_signal: H,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+    where
+        H: Future<Output = ()>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        self.run_until(signal(), shutdown_delay, drain_timeout, on_signal)
+            .await
+    }
+
+    /// Runs until the server exits on its own or `stop` resolves. Then
+    /// `on_signal` runs and the server keeps serving for `shutdown_delay`,
+    /// so load balancers notice it is going away before connections are
+    /// refused. After that the shutdown token is cancelled and in-flight
+    /// requests get up to `drain_timeout` to finish before the server task
+    /// is aborted.
+    pub async fn run_until<S, H>(
+        mut self,
+        stop: S,
+        shutdown_delay: Duration,
+        drain_timeout: Duration,
+        on_signal: H,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+    where
+        S: Future<Output = ()>,
+        H: Future<Output = ()>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let result = tokio::select! {
+            result = &mut self.handle => result,
+            _ = stop => {
+                on_signal.await;
+                if !shutdown_delay.is_zero() {
+                    tracing::info!(
+                        "Shutting down in {}ms",
+                        shutdown_delay.as_millis()
+                    );
+                    tokio::time::sleep(shutdown_delay).await;
+                }
+                tracing::info!(
+                    "Draining connections for up to {}s",
+                    drain_timeout.as_secs()
+                );
+                self.shutdown.cancel();
+                match tokio::time::timeout(drain_timeout, &mut self.handle).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        tracing::warn!("Drain timeout elapsed, closing remaining connections");
+                        self.handle.abort();
+                        Ok(Ok(()))
+                    }
+                }
+            }
+        };
+
+        result?.map_err(Into::into)
+    }
+}
+
+/// Ends `stream` once `shutdown` is cancelled.
+pub fn until_shutdown<S>(stream: S, shutdown: CancellationToken) -> UntilShutdown<S> {
+    UntilShutdown {
+        stream,
+        shutdown: Some(Box::pin(shutdown.cancelled_owned())),
+    }
+}
+
+/// Stream returned by `until_shutdown`.
+pub struct UntilShutdown<S> {
+    stream: S,
+    // Cleared once cancelled, after which the stream stays ended.
+    shutdown: Option<Pin<Box<WaitForCancellationFutureOwned>>>,
+}
+
+impl<S: Stream + Unpin> Stream for UntilShutdown<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(shutdown) = self.shutdown.as_mut() else {
+            return Poll::Ready(None);
+        };
+        if shutdown.as_mut().poll(cx).is_ready() {
+            self.shutdown = None;
+            return Poll::Ready(None);
+        }
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_stream::StreamExt;
+    use tonic::transport::Server;
+
+    use super::*;
+    use crate::movie::movie_service_client::MovieServiceClient;
+    use crate::movie::movie_service_server::MovieServiceServer;
+    use crate::movie::{Movie, MovieEventType, WatchMoviesRequest};
+    use crate::service::MovieServiceImpl;
+    use crate::storage::MovieStore;
+    use crate::watch::ChangeFeed;
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_serving_through_the_shutdown_delay() {
+        let shutdown = CancellationToken::new();
+        let start = tokio::time::Instant::now();
+        let serving = Serving::spawn(shutdown.clone(), |stopping| async move {
+            stopping.await;
+            Ok::<_, std::io::Error>(())
+        });
+
+        serving
+            .run_until(
+                async {},
+                Duration::from_secs(5),
+                Duration::from_secs(30),
+                async {
+                    assert!(!shutdown.is_cancelled());
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(shutdown.is_cancelled());
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn open_watches_do_not_hold_the_drain_open() {
+        let shutdown = CancellationToken::new();
+        let changes = Arc::new(ChangeFeed::new(16).with_shutdown(shutdown.clone()));
+        let service = MovieServiceImpl::new(Arc::new(MovieStore::default()), changes.clone())
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let serving = Serving::spawn(shutdown, |stopping| {
+            Server::builder()
+                .add_service(MovieServiceServer::new(service))
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), stopping)
+        });
+
+        let mut client = MovieServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let mut events = client
+            .watch_movies(WatchMoviesRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        changes.publish(MovieEventType::Created, Movie::default());
+        assert_eq!(events.next().await.unwrap().unwrap().revision, 1);
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let start = Instant::now();
+        let run = tokio::spawn(serving.run_until(
+            async {
+                let _ = stopped.await;
+            },
+            Duration::ZERO,
+            Duration::from_secs(30),
+            async {},
+        ));
+        stop.send(()).unwrap();
+
+        let ended = tokio::time::timeout(Duration::from_secs(5), events.next()).await;
+        assert!(ended.unwrap().is_none());
+        run.await.unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn until_shutdown_ends_the_stream() {
+        let shutdown = CancellationToken::new();
+        let mut stream = until_shutdown(
+            tokio_stream::iter([1, 2]).chain(tokio_stream::pending()),
+            shutdown.clone(),
+        );
+
+        assert_eq!(stream.next().await, Some(1));
+        assert_eq!(stream.next().await, Some(2));
+        shutdown.cancel();
+        assert_eq!(stream.next().await, None);
+        assert_eq!(stream.next().await, None);
+    }
+}
@@ -0,0 +1,768 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use prost::Message;
+
+use super::{
+    check_version, stage_creates, stage_deletes, stamp_created, stamp_updated, BatchError,
+    MovieQuery, MovieRepository, MovieStore, StorageError,
+};
+use crate::movie::Movie;
+
+const LOG_FILE: &str = "movies.wal";
+const SNAPSHOT_FILE: &str = "movies.snapshot";
+const SNAPSHOT_TMP_FILE: &str = "movies.snapshot.tmp";
+
+/// Frames larger than this are treated as corruption rather than allocated.
+const MAX_RECORD_LEN: u32 = 16 * 1024 * 1024;
+
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+const OP_BATCH: u8 = 3;
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum Record {
+    Put(Movie),
+    Delete(String),
+    /// Records applied together: a single frame, so a torn write loses the
+    /// whole batch rather than part of it.
+    Batch(Vec<Record>),
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match self {
+            Record::Put(movie) => {
+                payload.push(OP_PUT);
+                movie
+                    .encode(&mut payload)
+                    .expect("Vec<u8> has unbounded capacity");
+            }
+            Record::Delete(id) => {
+                payload.push(OP_DELETE);
+                payload.extend_from_slice(id.as_bytes());
+            }
+            Record::Batch(records) => {
+                payload.push(OP_BATCH);
+                for record in records {
+                    payload.extend_from_slice(&record.encode());
+                }
+            }
+        }
+
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        let (op, body) = payload.split_first()?;
+        match *op {
+            OP_PUT => Movie::decode(body).ok().map(Record::Put),
+            OP_DELETE => String::from_utf8(body.to_vec()).ok().map(Record::Delete),
+            OP_BATCH => {
+                let mut records = Vec::new();
+                let mut rest = body;
+                while !rest.is_empty() {
+                    if rest.len() < 8 {
+                        return None;
+                    }
+                    let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+                    let checksum = u32::from_le_bytes(rest[4..8].try_into().unwrap());
+                    let payload = rest.get(8..8 + len)?;
+                    if crc32fast::hash(payload) != checksum {
+                        return None;
+                    }
+                    records.push(Record::decode(payload)?);
+                    rest = &rest[8 + len..];
+                }
+                Some(Record::Batch(records))
+            }
+            _ => None,
+        }
+    }
+
+    fn apply(self, movies: &mut HashMap<String, Movie>) {
+        match self {
+            Record::Put(movie) => {
+                movies.insert(movie.id.clone(), movie);
+            }
+            Record::Delete(id) => {
+                movies.remove(&id);
+            }
+            Record::Batch(records) => {
+                for record in records {
+                    record.apply(movies);
+                }
+            }
+        }
+    }
+}
+
+/// Reads framed records until EOF or the first damaged frame. Returns the
+/// decoded records and the byte offset just past the last valid one.
+///
+/// A crash mid-append can only damage the final frame: its header or payload
+/// is cut short, or the unwritten part reads back as zeros. A damaged frame
+/// with anything else after it means the file itself is corrupt, which is an
+/// error rather than something to truncate away.
+fn read_records(file: &mut File, path: &Path) -> Result<(Vec<Record>, u64), StorageError> {
+    let file_len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut valid_len = 0u64;
+
+    loop {
+        let mut header = [0u8; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let frame_end = valid_len + 8 + len as u64;
+        let record = if len > MAX_RECORD_LEN || frame_end > file_len {
+            None
+        } else {
+            let mut payload = vec![0u8; len as usize];
+            reader.read_exact(&mut payload)?;
+            (crc32fast::hash(&payload) == checksum)
+                .then(|| Record::decode(&payload))
+                .flatten()
+        };
+
+        let Some(record) = record else {
+            if frame_end < file_len && !zero_filled(&mut reader, valid_len)? {
+                return Err(StorageError::Backend(format!(
+                    "{} is corrupt at offset {}; refusing to discard the {} bytes from there",
+                    path.display(),
+                    valid_len,
+                    file_len - valid_len
+                )));
+            }
+            break;
+        };
+
+        records.push(record);
+        valid_len = frame_end;
+    }
+
+    Ok((records, valid_len))
+}
+
+/// Whether every byte from `offset` to the end of the file is zero.
+fn zero_filled(reader: &mut BufReader<&mut File>, offset: u64) -> Result<bool, StorageError> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(true),
+            n if buf[..n].iter().any(|&b| b != 0) => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Journal {
+    dir: PathBuf,
+    log: File,
+    len: u64,
+    pending: u64,
+}
+
+impl Journal {
+    /// Opens the journal in `dir`, replaying the snapshot and then the log on
+    /// top of it. A torn final frame on the log is truncated away; damage
+    /// anywhere else fails the open.
+    fn open(dir: &Path) -> Result<(Self, HashMap<String, Movie>), StorageError> {
+        fs::create_dir_all(dir)?;
+        let mut movies = HashMap::new();
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let mut snapshot = File::open(&snapshot_path)?;
+            let (records, valid_len) = read_records(&mut snapshot, &snapshot_path)?;
+            if valid_len != snapshot.metadata()?.len() {
+                return Err(StorageError::Backend(format!(
+                    "snapshot {} is corrupt",
+                    snapshot_path.display()
+                )));
+            }
+            for record in records {
+                record.apply(&mut movies);
+            }
+        }
+
+        let log_path = dir.join(LOG_FILE);
+        let mut log = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&log_path)?;
+
+        let (records, valid_len) = read_records(&mut log, &log_path)?;
+        let file_len = log.metadata()?.len();
+        if valid_len < file_len {
+            tracing::warn!(
+                "Truncating torn tail of {} at offset {} ({} bytes discarded)",
+                log_path.display(),
+                valid_len,
+                file_len - valid_len
+            );
+            log.set_len(valid_len)?;
+            log.sync_all()?;
+        }
+
+        let pending = records.len() as u64;
+        for record in records {
+            record.apply(&mut movies);
+        }
+        log.seek(SeekFrom::Start(valid_len))?;
+
+        tracing::info!(
+            "Recovered {} movies from {} ({} log records replayed)",
+            movies.len(),
+            dir.display(),
+            pending
+        );
+
+        Ok((
+            Self {
+                dir: dir.to_path_buf(),
+                log,
+                len: valid_len,
+                pending,
+            },
+            movies,
+        ))
+    }
+
+    fn append(&mut self, record: &Record) -> Result<(), StorageError> {
+        let frame = record.encode();
+        let result = self
+            .log
+            .write_all(&frame)
+            .and_then(|_| self.log.sync_data());
+
+        if let Err(e) = result {
+            // Drop any partially written frame so later appends are not
+            // hidden behind it on replay.
+            self.log.set_len(self.len)?;
+            self.log.seek(SeekFrom::Start(self.len))?;
+            return Err(e.into());
+        }
+
+        self.len += frame.len() as u64;
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Writes `movies` to a fresh snapshot and empties the log. The snapshot
+    /// is renamed into place before the log is truncated, so a crash in
+    /// between only causes already-applied records to be replayed again.
+    fn compact(&mut self, movies: &HashMap<String, Movie>) -> Result<(), StorageError> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut tmp = File::create(&tmp_path)?;
+        for movie in movies.values() {
+            tmp.write_all(&Record::Put(movie.clone()).encode())?;
+        }
+        tmp.sync_all()?;
+        drop(tmp);
+
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.log.set_len(0)?;
+        self.log.seek(SeekFrom::Start(0))?;
+        self.log.sync_all()?;
+        self.len = 0;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+/// In-memory store that journals every mutation to an append-only log and
+/// periodically compacts it into a snapshot.
+#[derive(Debug, Clone)]
+pub struct JournaledMovieStore {
+    store: MovieStore,
+    journal: Arc<Mutex<Journal>>,
+}
+
+impl JournaledMovieStore {
+    pub async fn open(
+        dir: impl AsRef<Path>,
+        compact_interval: Duration,
+    ) -> Result<Self, StorageError> {
+        let dir = dir.as_ref().to_path_buf();
+        let (journal, movies) = tokio::task::spawn_blocking(move || Journal::open(&dir))
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))??;
+
+        let store = Self {
+            store: MovieStore::from_movies(movies),
+            journal: Arc::new(Mutex::new(journal)),
+        };
+
+        tokio::spawn(store.clone().run_compaction(compact_interval));
+        Ok(store)
+    }
+
+    async fn run_compaction(self, compact_interval: Duration) {
+        let mut interval = tokio::time::interval(compact_interval);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let store = self.clone();
+            let result = tokio::task::spawn_blocking(move || store.compact())
+                .await
+                .map_err(|e| StorageError::Backend(e.to_string()))
+                .and_then(|result| result);
+            if let Err(e) = result {
+                tracing::error!("Journal compaction failed: {}", e);
+            }
+        }
+    }
+
+    fn compact(&self) -> Result<(), StorageError> {
+        let mut journal = self.lock()?;
+        if journal.pending == 0 {
+            return Ok(());
+        }
+        let movies = self.store.snapshot()?;
+        journal.compact(&movies)?;
+        tracing::info!("Compacted journal into snapshot of {} movies", movies.len());
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Journal>, StorageError> {
+        self.journal
+            .lock()
+            .map_err(|_| StorageError::Backend("Lock error".to_string()))
+    }
+
+    /// Runs a mutation with the journal locked, so the precondition check,
+    /// the log append and the in-memory apply are not interleaved with other
+    /// writers.
+    async fn write<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&MovieStore, &mut Journal) -> Result<T, StorageError> + Send + 'static,
+    {
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut journal = this.lock()?;
+            f(&this.store, &mut journal)
+        })
+        .await
+        .map_err(|e| StorageError::Backend(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl MovieRepository for JournaledMovieStore {
+    async fn create(&self, mut movie: Movie) -> Result<Movie, StorageError> {
+        self.write(move |store, journal| {
+            if store.contains(&movie.id)? {
+                return Err(StorageError::AlreadyExists(movie.id));
+            }
+            stamp_created(&mut movie);
+            journal.append(&Record::Put(movie.clone()))?;
+            store.put(movie.clone())?;
+            Ok(movie)
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StorageError> {
+        self.store.get(id).await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, StorageError> {
+        self.store.list().await
+    }
+
+    async fn query(&self, query: &MovieQuery) -> Result<Vec<Movie>, StorageError> {
+        self.store.query(query).await
+    }
+
+    async fn update(
+        &self,
+        mut movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, StorageError> {
+        self.write(move |store, journal| {
+            let Some(current) = store.find(&movie.id)? else {
+                return Err(StorageError::NotFound(movie.id));
+            };
+            check_version(&movie.id, current.version, expected_version)?;
+            stamp_updated(&mut movie, &current);
+            journal.append(&Record::Put(movie.clone()))?;
+            store.put(movie.clone())?;
+            Ok(movie)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str, expected_version: Option<u64>) -> Result<bool, StorageError> {
+        let id = id.to_string();
+        self.write(move |store, journal| {
+            let Some(current) = store.find(&id)? else {
+                return Ok(false);
+            };
+            check_version(&id, current.version, expected_version)?;
+            journal.append(&Record::Delete(id.clone()))?;
+            store.remove(&id)
+        })
+        .await
+    }
+
+    async fn create_batch(&self, movies: Vec<Movie>) -> Result<Vec<Movie>, BatchError> {
+        self.write(move |store, journal| {
+            let movies = match stage_creates(movies, |id| store.contains(id)) {
+                Ok(movies) => movies,
+                Err(error) => return Ok(Err(error)),
+            };
+            if !movies.is_empty() {
+                let records = movies.iter().cloned().map(Record::Put).collect();
+                journal.append(&Record::Batch(records))?;
+                store.apply(&movies, &[])?;
+            }
+            Ok(Ok(movies))
+        })
+        .await?
+    }
+
+    async fn delete_batch(
+        &self,
+        deletes: Vec<(String, Option<u64>)>,
+    ) -> Result<Vec<bool>, BatchError> {
+        self.write(move |store, journal| {
+            let removed = match stage_deletes(&deletes, |id| store.find(id)) {
+                Ok(removed) => removed,
+                Err(error) => return Ok(Err(error)),
+            };
+            let ids: Vec<&str> = deletes
+                .iter()
+                .zip(&removed)
+                .filter(|(_, removed)| **removed)
+                .map(|((id, _), _)| id.as_str())
+                .collect();
+            if !ids.is_empty() {
+                let records = ids
+                    .iter()
+                    .map(|id| Record::Delete(id.to_string()))
+                    .collect();
+                journal.append(&Record::Batch(records))?;
+                store.apply(&[], &ids)?;
+            }
+            Ok(Ok(removed))
+        })
+        .await?
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.write(|_, journal| {
+            fs::metadata(&journal.dir)?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory removed again when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("movie-journal-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn movie(id: &str, title: &str) -> Movie {
+        Movie {
+            id: id.to_string(),
+            title: title.to_string(),
+            version: 1,
+            ..Default::default()
+        }
+    }
+
+    fn titles(movies: &HashMap<String, Movie>) -> Vec<(String, String)> {
+        let mut titles: Vec<_> = movies
+            .values()
+            .map(|movie| (movie.id.clone(), movie.title.clone()))
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    fn log_len(dir: &TempDir) -> u64 {
+        fs::metadata(dir.0.join(LOG_FILE)).unwrap().len()
+    }
+
+    fn set_log_len(dir: &TempDir, len: u64) {
+        OpenOptions::new()
+            .write(true)
+            .open(dir.0.join(LOG_FILE))
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+    }
+
+    fn flip_last_byte(dir: &TempDir) {
+        let path = dir.0.join(LOG_FILE);
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+    }
+
+    #[test]
+    fn replays_log_on_reopen() {
+        let dir = TempDir::new();
+        let (mut journal, movies) = Journal::open(&dir.0).unwrap();
+        assert!(movies.is_empty());
+        journal.append(&Record::Put(movie("a", "Alien"))).unwrap();
+        journal.append(&Record::Put(movie("b", "Brazil"))).unwrap();
+        journal.append(&Record::Delete("a".to_string())).unwrap();
+        let len = journal.len;
+        drop(journal);
+
+        let (journal, movies) = Journal::open(&dir.0).unwrap();
+        assert_eq!(titles(&movies), [("b".into(), "Brazil".into())]);
+        assert_eq!(journal.pending, 3);
+        assert_eq!(log_len(&dir), len);
+    }
+
+    #[test]
+    fn truncates_torn_trailing_frame() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal.append(&Record::Put(movie("a", "Alien"))).unwrap();
+        let intact = journal.len;
+        journal.append(&Record::Put(movie("b", "Brazil"))).unwrap();
+        drop(journal);
+
+        // Half a header, then a header with only part of its payload.
+        for torn in [intact + 5, log_len(&dir) - 3] {
+            set_log_len(&dir, torn);
+            let (journal, movies) = Journal::open(&dir.0).unwrap();
+            assert_eq!(titles(&movies), [("a".into(), "Alien".into())]);
+            assert_eq!(journal.len, intact);
+            assert_eq!(log_len(&dir), intact);
+        }
+
+        // Appends after recovery are not hidden behind the damaged frame.
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal.append(&Record::Put(movie("c", "Cube"))).unwrap();
+        drop(journal);
+        let (_, movies) = Journal::open(&dir.0).unwrap();
+        assert_eq!(
+            titles(&movies),
+            [("a".into(), "Alien".into()), ("c".into(), "Cube".into())]
+        );
+    }
+
+    #[test]
+    fn truncates_trailing_frame_with_bad_checksum() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal.append(&Record::Put(movie("a", "Alien"))).unwrap();
+        let intact = journal.len;
+        journal.append(&Record::Delete("a".to_string())).unwrap();
+        drop(journal);
+
+        flip_last_byte(&dir);
+        let (_, movies) = Journal::open(&dir.0).unwrap();
+        assert_eq!(titles(&movies), [("a".into(), "Alien".into())]);
+        assert_eq!(log_len(&dir), intact);
+    }
+
+    #[test]
+    fn truncates_zero_filled_tail() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal.append(&Record::Put(movie("a", "Alien"))).unwrap();
+        let intact = journal.len;
+        drop(journal);
+
+        // The file grew but the frame never reached the disk.
+        set_log_len(&dir, intact + 64);
+        let (_, movies) = Journal::open(&dir.0).unwrap();
+        assert_eq!(titles(&movies), [("a".into(), "Alien".into())]);
+        assert_eq!(log_len(&dir), intact);
+    }
+
+    #[test]
+    fn rejects_damage_before_the_last_frame() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal.append(&Record::Put(movie("a", "Alien"))).unwrap();
+        let first = journal.len;
+        journal.append(&Record::Put(movie("b", "Brazil"))).unwrap();
+        journal.append(&Record::Put(movie("c", "Cube"))).unwrap();
+        let len = journal.len;
+        drop(journal);
+
+        let path = dir.0.join(LOG_FILE);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[first as usize + 10] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let error = Journal::open(&dir.0).unwrap_err();
+        assert!(
+            matches!(&error, StorageError::Backend(message) if message.contains(&format!("corrupt at offset {}", first))),
+            "{}",
+            error
+        );
+        // Nothing was truncated, so the log can still be repaired by hand.
+        assert_eq!(log_len(&dir), len);
+    }
+
+    #[test]
+    fn drops_torn_batch_as_a_whole() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal.append(&Record::Put(movie("a", "Alien"))).unwrap();
+        let intact = journal.len;
+        journal
+            .append(&Record::Batch(vec![
+                Record::Put(movie("b", "Brazil")),
+                Record::Put(movie("c", "Cube")),
+                Record::Delete("a".to_string()),
+            ]))
+            .unwrap();
+        let full = journal.len;
+        drop(journal);
+
+        // Cut inside the last inner record, leaving the first ones whole.
+        set_log_len(&dir, full - 1);
+        let (_, movies) = Journal::open(&dir.0).unwrap();
+        assert_eq!(titles(&movies), [("a".into(), "Alien".into())]);
+        assert_eq!(log_len(&dir), intact);
+    }
+
+    #[test]
+    fn drops_batch_with_corrupt_inner_record() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal
+            .append(&Record::Batch(vec![
+                Record::Put(movie("a", "Alien")),
+                Record::Put(movie("b", "Brazil")),
+            ]))
+            .unwrap();
+        drop(journal);
+
+        flip_last_byte(&dir);
+        let (_, movies) = Journal::open(&dir.0).unwrap();
+        assert!(movies.is_empty());
+        assert_eq!(log_len(&dir), 0);
+    }
+
+    #[test]
+    fn replays_log_on_top_of_snapshot() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        let mut movies = HashMap::new();
+        for record in [
+            Record::Put(movie("a", "Alien")),
+            Record::Put(movie("b", "Brazil")),
+        ] {
+            journal.append(&record).unwrap();
+            record.apply(&mut movies);
+        }
+        journal.compact(&movies).unwrap();
+        assert_eq!(log_len(&dir), 0);
+
+        journal.append(&Record::Delete("a".to_string())).unwrap();
+        journal
+            .append(&Record::Put(movie("b", "Brazil (1985)")))
+            .unwrap();
+        let len = journal.len;
+        drop(journal);
+
+        let (journal, movies) = Journal::open(&dir.0).unwrap();
+        assert_eq!(titles(&movies), [("b".into(), "Brazil (1985)".into())]);
+        assert_eq!(journal.pending, 2);
+        assert_eq!(log_len(&dir), len);
+    }
+
+    #[test]
+    fn recovers_from_crash_between_snapshot_rename_and_log_truncate() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        let mut movies = HashMap::new();
+        for record in [
+            Record::Put(movie("a", "Alien")),
+            Record::Put(movie("b", "Brazil")),
+            Record::Delete("a".to_string()),
+        ] {
+            journal.append(&record).unwrap();
+            record.apply(&mut movies);
+        }
+        let log = fs::read(dir.0.join(LOG_FILE)).unwrap();
+        journal.compact(&movies).unwrap();
+        drop(journal);
+
+        // The snapshot is in place but the log still holds every record.
+        fs::write(dir.0.join(LOG_FILE), &log).unwrap();
+        let (_, recovered) = Journal::open(&dir.0).unwrap();
+        assert_eq!(titles(&recovered), titles(&movies));
+        assert_eq!(log_len(&dir), log.len() as u64);
+    }
+
+    #[test]
+    fn ignores_unrenamed_snapshot() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        journal.append(&Record::Put(movie("a", "Alien"))).unwrap();
+        drop(journal);
+
+        // A crash while writing the snapshot leaves only the temporary file.
+        fs::write(dir.0.join(SNAPSHOT_TMP_FILE), [1, 2, 3]).unwrap();
+        let (_, movies) = Journal::open(&dir.0).unwrap();
+        assert_eq!(titles(&movies), [("a".into(), "Alien".into())]);
+    }
+
+    #[test]
+    fn rejects_corrupt_snapshot() {
+        let dir = TempDir::new();
+        let (mut journal, _) = Journal::open(&dir.0).unwrap();
+        let movies = HashMap::from([("a".to_string(), movie("a", "Alien"))]);
+        journal.compact(&movies).unwrap();
+        drop(journal);
+
+        let path = dir.0.join(SNAPSHOT_FILE);
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        assert!(Journal::open(&dir.0).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use async_trait::async_trait;
+
+use super::{
+    check_version, stage_creates, stage_deletes, stamp_created, stamp_updated, BatchError,
+    MovieQuery, MovieRepository, StorageError,
+};
+use crate::movie::Movie;
+
+const SHARD_COUNT: usize = 16;
+
+/// Shared with any snapshot taken of it; writers copy it first if one is
+/// still alive.
+type Shard = Arc<HashMap<String, Movie>>;
+
+/// In-memory store split into `RwLock`-guarded shards keyed by a hash of the
+/// movie id. Reads only share-lock the shard they touch, and a write blocks
+/// readers of its own shard for just the duration of the map operation.
+/// Batches lock every shard so they are seen whole. Lists and queries hold
+/// every shard's lock only long enough to take a copy-on-write snapshot of
+/// it, then scan the snapshots without blocking writers.
+#[derive(Debug, Clone)]
+pub struct MovieStore {
+    inner: Arc<Shards>,
+}
+
+#[derive(Debug)]
+struct Shards {
+    hasher: RandomState,
+    shards: Vec<RwLock<Shard>>,
+}
+
+impl Default for MovieStore {
+    fn default() -> Self {
+        Self::from_movies(HashMap::new())
+    }
+}
+
+impl MovieStore {
+    pub(crate) fn from_movies(movies: HashMap<String, Movie>) -> Self {
+        let hasher = RandomState::new();
+        let mut shards: Vec<HashMap<String, Movie>> =
+            (0..SHARD_COUNT).map(|_| HashMap::new()).collect();
+        for (id, movie) in movies {
+            shards[hasher.hash_one(&id) as usize % SHARD_COUNT].insert(id, movie);
+        }
+
+        Self {
+            inner: Arc::new(Shards {
+                hasher,
+                shards: shards
+                    .into_iter()
+                    .map(|shard| RwLock::new(Arc::new(shard)))
+                    .collect(),
+            }),
+        }
+    }
+
+    pub(crate) fn contains(&self, id: &str) -> Result<bool, StorageError> {
+        Ok(self.read(id)?.contains_key(id))
+    }
+
+    pub(crate) fn find(&self, id: &str) -> Result<Option<Movie>, StorageError> {
+        Ok(self.read(id)?.get(id).cloned())
+    }
+
+    pub(crate) fn put(&self, movie: Movie) -> Result<(), StorageError> {
+        Arc::make_mut(&mut *self.write(&movie.id)?).insert(movie.id.clone(), movie);
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, id: &str) -> Result<bool, StorageError> {
+        Ok(Arc::make_mut(&mut *self.write(id)?).remove(id).is_some())
+    }
+
+    /// Stores and removes movies in one step, so readers see all of the
+    /// changes or none of them.
+    pub(crate) fn apply(&self, puts: &[Movie], removes: &[&str]) -> Result<(), StorageError> {
+        let mut shards = self.write_all()?;
+        for movie in puts {
+            Arc::make_mut(&mut shards[self.index(&movie.id)])
+                .insert(movie.id.clone(), movie.clone());
+        }
+        for id in removes {
+            Arc::make_mut(&mut shards[self.index(id)]).remove(*id);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn snapshot(&self) -> Result<HashMap<String, Movie>, StorageError> {
+        let shards = self.snapshot_shards()?;
+        Ok(shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(id, movie)| (id.clone(), movie.clone()))
+            .collect())
+    }
+
+    fn index(&self, id: &str) -> usize {
+        self.inner.hasher.hash_one(id) as usize % SHARD_COUNT
+    }
+
+    fn shard(&self, id: &str) -> &RwLock<Shard> {
+        &self.inner.shards[self.index(id)]
+    }
+
+    fn read(&self, id: &str) -> Result<RwLockReadGuard<'_, Shard>, StorageError> {
+        self.shard(id)
+            .read()
+            .map_err(|_| StorageError::Backend("Lock error".to_string()))
+    }
+
+    fn write(&self, id: &str) -> Result<RwLockWriteGuard<'_, Shard>, StorageError> {
+        self.shard(id)
+            .write()
+            .map_err(|_| StorageError::Backend("Lock error".to_string()))
+    }
+
+    /// Snapshots every shard under its share lock. All the locks are taken,
+    /// always in the same order, before any is released, so the snapshots
+    /// form a consistent view; they are only held while the shard pointers
+    /// are cloned. Writers hold either a single shard or, via `write_all`,
+    /// every shard taken in that same order, which keeps this deadlock-free.
+    fn snapshot_shards(&self) -> Result<Vec<Shard>, StorageError> {
+        let guards = self
+            .inner
+            .shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .read()
+                    .map_err(|_| StorageError::Backend("Lock error".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(guards.iter().map(|shard| Arc::clone(shard)).collect())
+    }
+
+    fn write_all(&self) -> Result<Vec<RwLockWriteGuard<'_, Shard>>, StorageError> {
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .write()
+                    .map_err(|_| StorageError::Backend("Lock error".to_string()))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl MovieRepository for MovieStore {
+    async fn create(&self, mut movie: Movie) -> Result<Movie, StorageError> {
+        let mut shard = self.write(&movie.id)?;
+
+        if shard.contains_key(&movie.id) {
+            return Err(StorageError::AlreadyExists(movie.id));
+        }
+
+        stamp_created(&mut movie);
+        Arc::make_mut(&mut shard).insert(movie.id.clone(), movie.clone());
+        Ok(movie)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StorageError> {
+        self.find(id)
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, StorageError> {
+        let shards = self.snapshot_shards()?;
+        Ok(shards
+            .iter()
+            .flat_map(|shard| shard.values())
+            .cloned()
+            .collect())
+    }
+
+    async fn query(&self, query: &MovieQuery) -> Result<Vec<Movie>, StorageError> {
+        let shards = self.snapshot_shards()?;
+        Ok(query.select(shards.iter().flat_map(|shard| shard.values())))
+    }
+
+    async fn update(
+        &self,
+        mut movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, StorageError> {
+        let mut shard = self.write(&movie.id)?;
+
+        match Arc::make_mut(&mut shard).get_mut(&movie.id) {
+            Some(existing) => {
+                check_version(&movie.id, existing.version, expected_version)?;
+                stamp_updated(&mut movie, existing);
+                *existing = movie.clone();
+                Ok(movie)
+            }
+            None => Err(StorageError::NotFound(movie.id)),
+        }
+    }
+
+    async fn delete(&self, id: &str, expected_version: Option<u64>) -> Result<bool, StorageError> {
+        let mut shard = self.write(id)?;
+
+        match shard.get(id) {
+            Some(existing) => {
+                check_version(id, existing.version, expected_version)?;
+                Arc::make_mut(&mut shard).remove(id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn create_batch(&self, movies: Vec<Movie>) -> Result<Vec<Movie>, BatchError> {
+        let mut shards = self.write_all()?;
+        let movies = stage_creates(movies, |id| Ok(shards[self.index(id)].contains_key(id)))?;
+        for movie in &movies {
+            Arc::make_mut(&mut shards[self.index(&movie.id)])
+                .insert(movie.id.clone(), movie.clone());
+        }
+        Ok(movies)
+    }
+
+    async fn delete_batch(
+        &self,
+        deletes: Vec<(String, Option<u64>)>,
+    ) -> Result<Vec<bool>, BatchError> {
+        let mut shards = self.write_all()?;
+        let removed = stage_deletes(&deletes, |id| Ok(shards[self.index(id)].get(id).cloned()))?;
+        for ((id, _), removed) in deletes.iter().zip(&removed) {
+            if *removed {
+                Arc::make_mut(&mut shards[self.index(id)]).remove(id);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie(id: &str, title: &str) -> Movie {
+        Movie {
+            id: id.to_string(),
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn titles(shards: &[Shard]) -> Vec<&str> {
+        let mut titles: Vec<&str> = shards
+            .iter()
+            .flat_map(|shard| shard.values())
+            .map(|movie| movie.title.as_str())
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    #[tokio::test]
+    async fn snapshots_do_not_block_or_see_later_writes() {
+        let store = MovieStore::default();
+        store.create(movie("a", "Alien")).await.unwrap();
+        store.create(movie("b", "Brazil")).await.unwrap();
+
+        let snapshot = store.snapshot_shards().unwrap();
+        // Every shard lock is free again while the snapshot is held.
+        assert!(store
+            .inner
+            .shards
+            .iter()
+            .all(|shard| shard.try_write().is_ok()));
+
+        store.create(movie("c", "Cube")).await.unwrap();
+        store.delete("a", None).await.unwrap();
+        store.apply(&[movie("b", "Brazil (1985)")], &[]).unwrap();
+
+        assert_eq!(titles(&snapshot), ["Alien", "Brazil"]);
+        assert_eq!(
+            titles(&store.snapshot_shards().unwrap()),
+            ["Brazil (1985)", "Cube"]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::HashSet,
+    fmt, io,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use prost_types::Timestamp;
+use tonic::Status;
+
+use crate::movie::Movie;
+
+mod journal;
+mod memory;
+mod sqlite;
+
+pub use journal::JournaledMovieStore;
+pub use memory::MovieStore;
+pub use sqlite::SqliteMovieStore;
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound(String),
+    AlreadyExists(String),
+    VersionMismatch {
+        id: String,
+        expected: u64,
+        actual: u64,
+    },
+    Backend(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::NotFound(id) => write!(f, "movie not found: {}", id),
+            StorageError::AlreadyExists(id) => write!(f, "movie already exists: {}", id),
+            StorageError::VersionMismatch {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "movie {} is at version {}, expected {}",
+                id, actual, expected
+            ),
+            StorageError::Backend(message) => write!(f, "storage backend error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError::Backend(err.to_string())
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Backend(err.to_string())
+    }
+}
+
+impl From<StorageError> for Status {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::NotFound(_) => Status::not_found("Movie not found"),
+            StorageError::AlreadyExists(_) => Status::already_exists("Movie already exists"),
+            StorageError::VersionMismatch {
+                expected, actual, ..
+            } => Status::aborted(format!(
+                "Movie version mismatch: expected {}, current {}",
+                expected, actual
+            )),
+            StorageError::Backend(message) => Status::internal(message),
+        }
+    }
+}
+
+/// Failure of a batch applied all-or-nothing; none of it was applied.
+/// `index` is the position of the item that failed, or `None` when the
+/// failure was not down to any one item.
+#[derive(Debug)]
+pub struct BatchError {
+    pub index: Option<usize>,
+    pub error: StorageError,
+}
+
+impl BatchError {
+    pub fn at(index: usize, error: StorageError) -> Self {
+        Self {
+            index: Some(index),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "batch item {}: {}", index, self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+impl From<StorageError> for BatchError {
+    fn from(error: StorageError) -> Self {
+        Self { index: None, error }
+    }
+}
+
+/// Version assigned to a newly created movie.
+pub const INITIAL_VERSION: u64 = 1;
+
+/// Sets the server-owned fields of a movie about to be created.
+pub(crate) fn stamp_created(movie: &mut Movie) {
+    let now = Some(Timestamp::from(SystemTime::now()));
+    movie.version = INITIAL_VERSION;
+    movie.created_at = now;
+    movie.updated_at = now;
+}
+
+/// Sets the server-owned fields of a movie about to replace `current`.
+pub(crate) fn stamp_updated(movie: &mut Movie, current: &Movie) {
+    movie.version = current.version + 1;
+    movie.created_at = current.created_at;
+    movie.updated_at = Some(Timestamp::from(SystemTime::now()));
+}
+
+/// Fails with `VersionMismatch` when an expected version is given and the
+/// stored movie is at a different one.
+pub(crate) fn check_version(
+    id: &str,
+    actual: u64,
+    expected: Option<u64>,
+) -> Result<(), StorageError> {
+    match expected {
+        Some(expected) if expected != actual => Err(StorageError::VersionMismatch {
+            id: id.to_string(),
+            expected,
+            actual,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks and stamps a batch of creates in order. Fails at the first movie
+/// that `exists` or whose id was already used earlier in the batch.
+pub(crate) fn stage_creates(
+    mut movies: Vec<Movie>,
+    mut exists: impl FnMut(&str) -> Result<bool, StorageError>,
+) -> Result<Vec<Movie>, BatchError> {
+    let mut ids = HashSet::new();
+    for (index, movie) in movies.iter_mut().enumerate() {
+        if !ids.insert(movie.id.clone())
+            || exists(&movie.id).map_err(|e| BatchError::at(index, e))?
+        {
+            return Err(BatchError::at(
+                index,
+                StorageError::AlreadyExists(movie.id.clone()),
+            ));
+        }
+        stamp_created(movie);
+    }
+    Ok(movies)
+}
+
+/// Checks a batch of deletes in order, returning whether each movie
+/// existed. A movie deleted earlier in the batch no longer exists.
+pub(crate) fn stage_deletes(
+    deletes: &[(String, Option<u64>)],
+    mut find: impl FnMut(&str) -> Result<Option<Movie>, StorageError>,
+) -> Result<Vec<bool>, BatchError> {
+    let mut deleted = HashSet::new();
+    let mut removed = Vec::with_capacity(deletes.len());
+    for (index, (id, expected_version)) in deletes.iter().enumerate() {
+        let current = if deleted.contains(id) {
+            None
+        } else {
+            find(id).map_err(|e| BatchError::at(index, e))?
+        };
+        let Some(current) = current else {
+            removed.push(false);
+            continue;
+        };
+        check_version(id, current.version, *expected_version)
+            .map_err(|e| BatchError::at(index, e))?;
+        deleted.insert(id);
+        removed.push(true);
+    }
+    Ok(removed)
+}
+
+/// Persistence boundary for `MovieServiceImpl`. Backends own the movie
+/// version and timestamps: `create` and `update` overwrite whatever the
+/// caller passed in via `stamp_created` and `stamp_updated`.
+#[async_trait]
+pub trait MovieRepository: Send + Sync + fmt::Debug {
+    async fn create(&self, movie: Movie) -> Result<Movie, StorageError>;
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StorageError>;
+
+    async fn list(&self) -> Result<Vec<Movie>, StorageError>;
+
+    /// Returns up to `query.limit` movies matching the filter, in the
+    /// requested order, starting strictly after `query.after`.
+    async fn query(&self, query: &MovieQuery) -> Result<Vec<Movie>, StorageError>;
+
+    /// Replaces a stored movie, failing with `VersionMismatch` if
+    /// `expected_version` is set and differs from the stored version.
+    async fn update(
+        &self,
+        movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, StorageError>;
+
+    /// Removes a movie, returning whether it existed. A version check is
+    /// only applied when the movie exists.
+    async fn delete(&self, id: &str, expected_version: Option<u64>) -> Result<bool, StorageError>;
+
+    /// Creates every movie or, failing that, none of them. Readers see
+    /// either all of the batch or none of it.
+    async fn create_batch(&self, movies: Vec<Movie>) -> Result<Vec<Movie>, BatchError>;
+
+    /// Applies every `(id, expected_version)` delete or, failing that, none
+    /// of them, returning whether each movie existed. Readers see either
+    /// all of the batch or none of it.
+    async fn delete_batch(
+        &self,
+        deletes: Vec<(String, Option<u64>)>,
+    ) -> Result<Vec<bool>, BatchError>;
+
+    /// Checks that the backend can currently serve requests; drives the
+    /// gRPC health status of the movie service.
+    async fn ping(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// Case folding applied to both sides of a `title_prefix` match. Every
+/// backend folds with this rather than its own collation, so a filter
+/// selects the same movies whichever backend serves it.
+pub(crate) fn fold_title(title: &str) -> String {
+    title.to_lowercase()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortField {
+    #[default]
+    Id,
+    Title,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MovieQuery {
+    pub genre: Option<String>,
+    pub title_prefix: Option<String>,
+    pub sort_by: SortField,
+    pub descending: bool,
+    /// Keyset position as a `(sort key, id)` pair; ties on the sort key are
+    /// broken by id so the order is total and stable across pages.
+    pub after: Option<(String, String)>,
+    pub limit: usize,
+}
+
+impl MovieQuery {
+    pub fn sort_key<'a>(&self, movie: &'a Movie) -> &'a str {
+        match self.sort_by {
+            SortField::Id => &movie.id,
+            SortField::Title => &movie.title,
+        }
+    }
+
+    fn matches(&self, movie: &Movie) -> bool {
+        if let Some(genre) = &self.genre {
+            let mut genres = std::iter::once(&movie.genre).chain(&movie.genres);
+            if !genres.any(|candidate| candidate.eq_ignore_ascii_case(genre)) {
+                return false;
+            }
+        }
+        if let Some(prefix) = &self.title_prefix {
+            if !fold_title(&movie.title).starts_with(&fold_title(prefix)) {
+                return false;
+            }
+        }
+        if let Some((key, id)) = &self.after {
+            let position = (self.sort_key(movie), movie.id.as_str());
+            let cursor = (key.as_str(), id.as_str());
+            if self.descending {
+                return position < cursor;
+            }
+            return position > cursor;
+        }
+        true
+    }
+
+    /// Evaluates the query over an unordered set of movies.
+    pub(crate) fn select<'a>(&self, movies: impl Iterator<Item = &'a Movie>) -> Vec<Movie> {
+        let mut selected: Vec<&Movie> = movies.filter(|movie| self.matches(movie)).collect();
+        selected.sort_by(|a, b| {
+            let ordering =
+                (self.sort_key(a), a.id.as_str()).cmp(&(self.sort_key(b), b.id.as_str()));
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        selected.into_iter().take(self.limit).cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageConfig {
+    Memory { journal: Option<JournalConfig> },
+    Sqlite { path: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalConfig {
+    pub dir: String,
+    pub compact_interval: Duration,
+}
+
+impl StorageConfig {
+    pub async fn open(&self) -> Result<Arc<dyn MovieRepository>, StorageError> {
+        match self {
+            StorageConfig::Memory { journal: None } => Ok(Arc::new(MovieStore::default())),
+            StorageConfig::Memory {
+                journal: Some(journal),
+            } => Ok(Arc::new(
+                JournaledMovieStore::open(&journal.dir, journal.compact_interval).await?,
+            )),
+            StorageConfig::Sqlite { path } => Ok(Arc::new(SqliteMovieStore::open(path).await?)),
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use prost_types::Timestamp;
+use rusqlite::{
+    params, params_from_iter,
+    types::{Type, Value},
+    Connection, OptionalExtension, Row,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    check_version, fold_title, stamp_created, stamp_updated, BatchError, MovieQuery,
+    MovieRepository, SortField, StorageError,
+};
+use crate::movie::{CastMember, Movie};
+
+/// Schema migrations, applied in order. The index of the last applied
+/// migration is tracked in `PRAGMA user_version`, so entries must only ever
+/// be appended.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE movies (
+        id    TEXT PRIMARY KEY NOT NULL,
+        title TEXT NOT NULL,
+        genre TEXT NOT NULL
+    );",
+    "CREATE INDEX movies_title_id ON movies (title, id);
+     CREATE INDEX movies_genre ON movies (genre COLLATE NOCASE);",
+    "ALTER TABLE movies ADD COLUMN version INTEGER NOT NULL DEFAULT 1;",
+    "ALTER TABLE movies ADD COLUMN genres TEXT NOT NULL DEFAULT '[]';
+     ALTER TABLE movies ADD COLUMN release_year INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE movies ADD COLUMN runtime_minutes INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE movies ADD COLUMN directors TEXT NOT NULL DEFAULT '[]';
+     ALTER TABLE movies ADD COLUMN cast_members TEXT NOT NULL DEFAULT '[]';
+     ALTER TABLE movies ADD COLUMN certification TEXT NOT NULL DEFAULT '';
+     ALTER TABLE movies ADD COLUMN synopsis TEXT NOT NULL DEFAULT '';
+     ALTER TABLE movies ADD COLUMN language TEXT NOT NULL DEFAULT '';
+     ALTER TABLE movies ADD COLUMN created_at TEXT;
+     ALTER TABLE movies ADD COLUMN updated_at TEXT;
+     UPDATE movies SET genres = json_array(genre) WHERE genre <> '';",
+    // Filled in by `fold_titles`, since SQLite's own `lower` only folds ASCII.
+    "ALTER TABLE movies ADD COLUMN title_folded TEXT;",
+];
+
+/// Every column of `movies` that `movie_from_row` reads, in order. List
+/// columns hold JSON arrays and timestamps hold RFC 3339 text.
+const COLUMNS: &str = "id, title, genre, version, genres, release_year, runtime_minutes, \
+                       directors, cast_members, certification, synopsis, language, \
+                       created_at, updated_at";
+/// `COLUMNS` followed by the derived `title_folded`, in the order
+/// `movie_values` writes them.
+const WRITE_COLUMNS: &str = "id, title, genre, version, genres, release_year, runtime_minutes, \
+                             directors, cast_members, certification, synopsis, language, \
+                             created_at, updated_at, title_folded";
+const PLACEHOLDERS: &str = "?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15";
+
+/// JSON shape of a `cast_members` entry.
+#[derive(Serialize, Deserialize)]
+struct CastRow {
+    name: String,
+    #[serde(default)]
+    character: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SqliteMovieStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteMovieStore {
+    pub async fn open(path: &str) -> Result<Self, StorageError> {
+        let path = path.to_string();
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection, StorageError> {
+            let mut conn = Connection::open(path)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            migrate(&mut conn)?;
+            fold_titles(&mut conn)?;
+            Ok(conn)
+        })
+        .await
+        .map_err(|e| StorageError::Backend(e.to_string()))??;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| StorageError::Backend("Lock error".to_string()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| StorageError::Backend(e.to_string()))?
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let current: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (version, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", (version + 1) as u32)?;
+        tx.commit()?;
+        tracing::info!("Applied sqlite migration {}", version + 1);
+    }
+
+    Ok(())
+}
+
+/// Fills `title_folded` for rows written before the column existed.
+fn fold_titles(conn: &mut Connection) -> Result<(), StorageError> {
+    let tx = conn.transaction()?;
+    let titles = tx
+        .prepare("SELECT id, title FROM movies WHERE title_folded IS NULL")?
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (id, title) in &titles {
+        tx.execute(
+            "UPDATE movies SET title_folded = ?1 WHERE id = ?2",
+            params![fold_title(title), id],
+        )?;
+    }
+    tx.commit()?;
+    if !titles.is_empty() {
+        tracing::info!("Folded {} sqlite movie titles", titles.len());
+    }
+    Ok(())
+}
+
+fn json_column<T: DeserializeOwned>(row: &Row<'_>, index: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn timestamp_column(row: &Row<'_>, index: usize) -> rusqlite::Result<Option<Timestamp>> {
+    let text: Option<String> = row.get(index)?;
+    text.map(|text| text.parse::<Timestamp>())
+        .transpose()
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn movie_from_row(row: &Row<'_>) -> rusqlite::Result<Movie> {
+    let cast: Vec<CastRow> = json_column(row, 8)?;
+    Ok(Movie {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        genre: row.get(2)?,
+        version: row.get::<_, i64>(3)? as u64,
+        genres: json_column(row, 4)?,
+        release_year: row.get(5)?,
+        runtime_minutes: row.get(6)?,
+        directors: json_column(row, 7)?,
+        cast: cast
+            .into_iter()
+            .map(|member| CastMember {
+                name: member.name,
+                character: member.character,
+            })
+            .collect(),
+        certification: row.get(9)?,
+        synopsis: row.get(10)?,
+        language: row.get(11)?,
+        created_at: timestamp_column(row, 12)?,
+        updated_at: timestamp_column(row, 13)?,
+    })
+}
+
+fn json_value<T: Serialize>(value: &T) -> Value {
+    Value::Text(serde_json::to_string(value).expect("column value serializes to JSON"))
+}
+
+fn timestamp_value(timestamp: Option<Timestamp>) -> Value {
+    match timestamp {
+        Some(timestamp) => Value::Text(timestamp.to_string()),
+        None => Value::Null,
+    }
+}
+
+/// Binds a movie to `PLACEHOLDERS` in `WRITE_COLUMNS` order.
+fn movie_values(movie: &Movie) -> Vec<Value> {
+    let cast: Vec<CastRow> = movie
+        .cast
+        .iter()
+        .map(|member| CastRow {
+            name: member.name.clone(),
+            character: member.character.clone(),
+        })
+        .collect();
+
+    vec![
+        Value::Text(movie.id.clone()),
+        Value::Text(movie.title.clone()),
+        Value::Text(movie.genre.clone()),
+        Value::Integer(movie.version as i64),
+        json_value(&movie.genres),
+        Value::Integer(movie.release_year.into()),
+        Value::Integer(movie.runtime_minutes.into()),
+        json_value(&movie.directors),
+        json_value(&cast),
+        Value::Text(movie.certification.clone()),
+        Value::Text(movie.synopsis.clone()),
+        Value::Text(movie.language.clone()),
+        timestamp_value(movie.created_at),
+        timestamp_value(movie.updated_at),
+        Value::Text(fold_title(&movie.title)),
+    ]
+}
+
+fn find(conn: &Connection, id: &str) -> Result<Option<Movie>, StorageError> {
+    Ok(conn
+        .query_row(
+            &format!("SELECT {} FROM movies WHERE id = ?1", COLUMNS),
+            params![id],
+            movie_from_row,
+        )
+        .optional()?)
+}
+
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[async_trait]
+impl MovieRepository for SqliteMovieStore {
+    async fn create(&self, mut movie: Movie) -> Result<Movie, StorageError> {
+        stamp_created(&mut movie);
+        self.with_conn(move |conn| {
+            let inserted = conn.execute(
+                &format!(
+                    "INSERT INTO movies ({}) VALUES ({}) ON CONFLICT(id) DO NOTHING",
+                    WRITE_COLUMNS, PLACEHOLDERS
+                ),
+                params_from_iter(movie_values(&movie)),
+            )?;
+
+            if inserted == 0 {
+                return Err(StorageError::AlreadyExists(movie.id));
+            }
+            Ok(movie)
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StorageError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| find(conn, &id)).await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, StorageError> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(&format!("SELECT {} FROM movies", COLUMNS))?;
+            let movies = stmt
+                .query_map([], movie_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(movies)
+        })
+        .await
+    }
+
+    async fn query(&self, query: &MovieQuery) -> Result<Vec<Movie>, StorageError> {
+        let mut sql = format!("SELECT {} FROM movies WHERE 1 = 1", COLUMNS);
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(genre) = &query.genre {
+            sql.push_str(
+                " AND (genre = ? COLLATE NOCASE OR EXISTS \
+                 (SELECT 1 FROM json_each(movies.genres) WHERE value = ? COLLATE NOCASE))",
+            );
+            values.push(Value::Text(genre.clone()));
+            values.push(Value::Text(genre.clone()));
+        }
+        if let Some(prefix) = &query.title_prefix {
+            sql.push_str(" AND title_folded LIKE ? ESCAPE '\\'");
+            values.push(Value::Text(format!(
+                "{}%",
+                escape_like(&fold_title(prefix))
+            )));
+        }
+
+        let column = match query.sort_by {
+            SortField::Id => "id",
+            SortField::Title => "title",
+        };
+        let (comparison, direction) = if query.descending {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
+
+        if let Some((key, id)) = &query.after {
+            sql.push_str(&format!(" AND ({}, id) {} (?, ?)", column, comparison));
+            values.push(Value::Text(key.clone()));
+            values.push(Value::Text(id.clone()));
+        }
+
+        sql.push_str(&format!(
+            " ORDER BY {column} {direction}, id {direction} LIMIT ?"
+        ));
+        values.push(Value::Integer(query.limit as i64));
+
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let movies = stmt
+                .query_map(params_from_iter(values), movie_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(movies)
+        })
+        .await
+    }
+
+    async fn update(
+        &self,
+        mut movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, StorageError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(current) = find(&tx, &movie.id)? else {
+                return Err(StorageError::NotFound(movie.id));
+            };
+            check_version(&movie.id, current.version, expected_version)?;
+
+            stamp_updated(&mut movie, &current);
+            tx.execute(
+                &format!(
+                    "REPLACE INTO movies ({}) VALUES ({})",
+                    WRITE_COLUMNS, PLACEHOLDERS
+                ),
+                params_from_iter(movie_values(&movie)),
+            )?;
+            tx.commit()?;
+            Ok(movie)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str, expected_version: Option<u64>) -> Result<bool, StorageError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(current) = find(&tx, &id)? else {
+                return Ok(false);
+            };
+            check_version(&id, current.version, expected_version)?;
+
+            tx.execute("DELETE FROM movies WHERE id = ?1", params![id])?;
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn create_batch(&self, mut movies: Vec<Movie>) -> Result<Vec<Movie>, BatchError> {
+        for movie in &mut movies {
+            stamp_created(movie);
+        }
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            for (index, movie) in movies.iter().enumerate() {
+                let inserted = tx.execute(
+                    &format!(
+                        "INSERT INTO movies ({}) VALUES ({}) ON CONFLICT(id) DO NOTHING",
+                        WRITE_COLUMNS, PLACEHOLDERS
+                    ),
+                    params_from_iter(movie_values(movie)),
+                )?;
+                if inserted == 0 {
+                    return Ok(Err(BatchError::at(
+                        index,
+                        StorageError::AlreadyExists(movie.id.clone()),
+                    )));
+                }
+            }
+            tx.commit()?;
+            Ok(Ok(movies))
+        })
+        .await?
+    }
+
+    async fn delete_batch(
+        &self,
+        deletes: Vec<(String, Option<u64>)>,
+    ) -> Result<Vec<bool>, BatchError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let mut removed = Vec::with_capacity(deletes.len());
+            for (index, (id, expected_version)) in deletes.iter().enumerate() {
+                let Some(current) = find(&tx, id)? else {
+                    removed.push(false);
+                    continue;
+                };
+                if let Err(error) = check_version(id, current.version, *expected_version) {
+                    return Ok(Err(BatchError::at(index, error)));
+                }
+                tx.execute("DELETE FROM movies WHERE id = ?1", params![id])?;
+                removed.push(true);
+            }
+            tx.commit()?;
+            Ok(Ok(removed))
+        })
+        .await?
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.with_conn(|conn| {
+            conn.query_row("SELECT 1", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await
+    }
+}
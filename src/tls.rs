@@ -0,0 +1,519 @@
+//! TLS for the gRPC link between the gateway and the server.
+//!
+//! tonic's built-in TLS settings are fixed once a server or channel is
+//! built, so both sides drive rustls directly instead: every new connection
+//! uses the most recently loaded `rustls` config, and a background task
+//! reloads it when the certificate, key or CA files change on disk.
+
+use std::{
+    fmt, fs,
+    future::Future,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, PoisonError, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use hyper_util::rt::TokioIo;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{
+    self,
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    RootCertStore,
+};
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+
+/// How often certificate files are checked for changes.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const ALPN_H2: &[u8] = b"h2";
+
+#[derive(Debug)]
+pub enum TlsError {
+    File { path: PathBuf, message: String },
+    Config(String),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::File { path, message } => write!(f, "{}: {}", path.display(), message),
+            TlsError::Config(message) => write!(f, "invalid TLS configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<rustls::Error> for TlsError {
+    fn from(err: rustls::Error) -> Self {
+        TlsError::Config(err.to_string())
+    }
+}
+
+/// Server certificate and key, plus the CA that client certificates must
+/// chain to when mutual TLS is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerTlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+/// CA used to verify the server, the name to verify it as, and an optional
+/// client certificate and key for mutual TLS. Without `domain`, each server
+/// is verified as the host of the URL it is configured under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientTlsFiles {
+    pub ca: PathBuf,
+    pub domain: Option<String>,
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl ServerTlsFiles {
+    fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.cert.clone(), self.key.clone()];
+        paths.extend(self.client_ca.clone());
+        paths
+    }
+
+    fn load(&self) -> Result<rustls::ServerConfig, TlsError> {
+        let provider = provider();
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots(ca)?), provider)
+                        .build()
+                        .map_err(|e| TlsError::Config(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_single_cert(certs(&self.cert)?, key(&self.key)?)?;
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Ok(config)
+    }
+}
+
+impl ClientTlsFiles {
+    fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.ca.clone()];
+        if let Some((cert, key)) = &self.identity {
+            paths.extend([cert.clone(), key.clone()]);
+        }
+        paths
+    }
+
+    fn load(&self) -> Result<rustls::ClientConfig, TlsError> {
+        let builder = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots(&self.ca)?);
+
+        let mut config = match &self.identity {
+            Some((cert, key_path)) => {
+                builder.with_client_auth_cert(certs(cert)?, key(key_path)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Ok(config)
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn open(path: &Path) -> Result<BufReader<fs::File>, TlsError> {
+    fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| file_error(path, e))
+}
+
+fn file_error(path: &Path, err: impl fmt::Display) -> TlsError {
+    TlsError::File {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    }
+}
+
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| file_error(path, e))?;
+    if certs.is_empty() {
+        return Err(file_error(path, "no PEM certificates found"));
+    }
+    Ok(certs)
+}
+
+fn key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|e| file_error(path, e))?
+        .ok_or_else(|| file_error(path, "no PEM private key found"))
+}
+
+fn roots(path: &Path) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(path)? {
+        roots.add(cert).map_err(|e| file_error(path, e))?;
+    }
+    Ok(roots)
+}
+
+/// The current config of type `T`, swapped in place when its files change.
+#[derive(Debug)]
+struct Reloadable<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T: Send + Sync + 'static> Reloadable<T> {
+    fn current(&self) -> Arc<T> {
+        // A poisoned lock still holds a fully built config.
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Loads the config once, failing startup if that fails, then keeps it
+    /// up to date in the background. A reload that fails (for example
+    /// because only the certificate has been replaced so far) keeps serving
+    /// the previous config and is retried on the next change check.
+    fn watch<F>(paths: Vec<PathBuf>, load: F) -> Result<Arc<Self>, TlsError>
+    where
+        F: Fn() -> Result<T, TlsError> + Send + 'static,
+    {
+        let mut loaded = modified(&paths);
+        let this = Arc::new(Self {
+            current: RwLock::new(Arc::new(load()?)),
+        });
+
+        let weak = Arc::downgrade(&this);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(this) = weak.upgrade() else {
+                    return;
+                };
+
+                let seen = modified(&paths);
+                if seen == loaded {
+                    continue;
+                }
+                match load() {
+                    Ok(config) => {
+                        *this.current.write().unwrap_or_else(PoisonError::into_inner) =
+                            Arc::new(config);
+                        loaded = seen;
+                        tracing::info!("Reloaded TLS certificates");
+                    }
+                    Err(e) => tracing::warn!("Keeping previous TLS certificates: {}", e),
+                }
+            }
+        });
+
+        Ok(this)
+    }
+}
+
+fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// Accepts TCP connections on `listener` and yields those that complete a
+/// TLS handshake, for `serve_with_incoming`. Handshakes run concurrently so a
+/// slow client cannot hold up others; failed handshakes are logged and
+/// dropped.
+pub fn incoming(
+    listener: TcpListener,
+    files: &ServerTlsFiles,
+) -> Result<ReceiverStream<io::Result<server::TlsStream<TcpStream>>>, TlsError> {
+    let files = files.clone();
+    let config = Reloadable::watch(files.paths(), move || files.load())?;
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        loop {
+            let (tcp, peer) = tokio::select! {
+                _ = tx.closed() => return,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+            };
+
+            let acceptor = TlsAcceptor::from(config.current());
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", peer, e),
+                    Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+    });
+
+    Ok(ReceiverStream::new(rx))
+}
+
+/// Client TLS settings shared by every channel to the server replicas, so
+/// their certificates are loaded and watched once.
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    config: Arc<Reloadable<rustls::ClientConfig>>,
+    domain: Option<String>,
+}
+
+impl ClientTls {
+    pub fn load(files: &ClientTlsFiles) -> Result<Self, TlsError> {
+        let loader = files.clone();
+        Ok(Self {
+            config: Reloadable::watch(files.paths(), move || loader.load())?,
+            domain: files.domain.clone(),
+        })
+    }
+
+    /// Creates a channel to `addr`, verifying the server as the configured
+    /// domain or else as `host`, and presenting the client certificate if
+    /// configured. Like `Endpoint::connect_lazy`, nothing is dialled until
+    /// the first call. Establishing a connection, including the handshake,
+    /// is limited to `connect_timeout`.
+    pub fn channel(
+        &self,
+        addr: &Uri,
+        host: &str,
+        connect_timeout: Duration,
+    ) -> Result<Channel, Box<dyn std::error::Error + Send + Sync>> {
+        let domain = self.domain.as_deref().unwrap_or(host);
+        let domain = ServerName::try_from(domain.to_string())
+            .map_err(|e| TlsError::Config(format!("{}: {}", domain, e)))?;
+
+        // TLS is done by the connector, so tonic itself must see a plaintext
+        // URL.
+        let authority = addr
+            .authority()
+            .ok_or_else(|| TlsError::Config(format!("{}: missing host", addr)))?;
+        let port = addr.port_u16().unwrap_or(443);
+        let endpoint = Endpoint::from_shared(format!("http://{}:{}", authority.host(), port))?;
+
+        Ok(endpoint.connect_with_connector_lazy(Connector {
+            config: self.config.clone(),
+            domain,
+            connect_timeout,
+        }))
+    }
+}
+
+#[derive(Clone)]
+struct Connector {
+    config: Arc<Reloadable<rustls::ClientConfig>>,
+    domain: ServerName<'static>,
+    connect_timeout: Duration,
+}
+
+impl tower::Service<Uri> for Connector {
+    type Response = TokioIo<client::TlsStream<TcpStream>>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = TlsConnector::from(self.config.current());
+        let domain = self.domain.clone();
+        let connect_timeout = self.connect_timeout;
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing host"))?;
+            // IPv6 hosts keep their brackets in URLs but not in addresses.
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let connect = async {
+                let tcp = TcpStream::connect((host, uri.port_u16().unwrap_or(443))).await?;
+                tcp.set_nodelay(true)?;
+                connector.connect(domain, tcp).await
+            };
+            let stream = tokio::time::timeout(connect_timeout, connect)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+
+    use super::*;
+
+    /// A CA writing the certificates it issues, and itself, to a scratch
+    /// directory removed again when dropped.
+    struct Pki {
+        dir: PathBuf,
+        cert: Certificate,
+        key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("movie-tls-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let cert = params.self_signed(&key).unwrap();
+            fs::write(dir.join("ca.pem"), cert.pem()).unwrap();
+            Self { dir, cert, key }
+        }
+
+        fn ca(&self) -> PathBuf {
+            self.dir.join("ca.pem")
+        }
+
+        /// Issues a certificate for `localhost` and writes it and its key to
+        /// `<stem>.pem` and `<stem>.key`, bumping their modification time so
+        /// a rewrite is noticed however coarse the file system's clock is.
+        fn issue(&self, stem: &str, usage: ExtendedKeyUsagePurpose) -> CertificateDer<'static> {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.extended_key_usages = vec![usage];
+            let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+            self.write(&format!("{}.pem", stem), &cert.pem());
+            self.write(&format!("{}.key", stem), &key.serialize_pem());
+            cert.der().clone()
+        }
+
+        fn write(&self, name: &str, contents: &str) {
+            let path = self.dir.join(name);
+            fs::write(&path, contents).unwrap();
+            let modified = fs::metadata(&path).unwrap().modified().unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified + Duration::from_secs(60))
+                .unwrap();
+        }
+
+        fn server_files(&self, client_ca: bool) -> ServerTlsFiles {
+            ServerTlsFiles {
+                cert: self.dir.join("server.pem"),
+                key: self.dir.join("server.key"),
+                client_ca: client_ca.then(|| self.ca()),
+            }
+        }
+
+        fn client_config(&self, identity: bool) -> Arc<rustls::ClientConfig> {
+            let files = ClientTlsFiles {
+                ca: self.ca(),
+                domain: None,
+                identity: identity
+                    .then(|| (self.dir.join("client.pem"), self.dir.join("client.key"))),
+            };
+            Arc::new(files.load().unwrap())
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Runs a handshake over an in-memory pipe, returning the certificate
+    /// the server presented or the server's error.
+    async fn handshake(
+        server: Arc<rustls::ServerConfig>,
+        client: Arc<rustls::ClientConfig>,
+    ) -> io::Result<CertificateDer<'static>> {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let domain = ServerName::try_from("localhost").unwrap();
+        let (accepted, connected) = tokio::join!(
+            TlsAcceptor::from(server).accept(server_io),
+            TlsConnector::from(client).connect(domain, client_io),
+        );
+        accepted?;
+        let connected = connected?;
+        Ok(connected.get_ref().1.peer_certificates().unwrap()[0].clone())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reloads_changed_certificates_and_keeps_them_through_bad_ones() {
+        let pki = Pki::new();
+        let first = pki.issue("server", ExtendedKeyUsagePurpose::ServerAuth);
+        let files = pki.server_files(false);
+        let loader = files.clone();
+        let config = Reloadable::watch(files.paths(), move || loader.load()).unwrap();
+        let client = pki.client_config(false);
+
+        let presented = handshake(config.current(), client.clone()).await.unwrap();
+        assert_eq!(presented, first);
+
+        let second = pki.issue("server", ExtendedKeyUsagePurpose::ServerAuth);
+        tokio::time::sleep(RELOAD_INTERVAL * 2).await;
+        let presented = handshake(config.current(), client.clone()).await.unwrap();
+        assert_eq!(presented, second);
+
+        // A half-written rotation fails to load and is not swapped in.
+        pki.write("server.key", "not a key");
+        tokio::time::sleep(RELOAD_INTERVAL * 2).await;
+        let presented = handshake(config.current(), client).await.unwrap();
+        assert_eq!(presented, second);
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_rejects_clients_without_a_certificate() {
+        let pki = Pki::new();
+        pki.issue("server", ExtendedKeyUsagePurpose::ServerAuth);
+        pki.issue("client", ExtendedKeyUsagePurpose::ClientAuth);
+        let server = Arc::new(pki.server_files(true).load().unwrap());
+
+        assert!(handshake(server.clone(), pki.client_config(false))
+            .await
+            .is_err());
+        assert!(handshake(server, pki.client_config(true)).await.is_ok());
+    }
+
+    #[test]
+    fn loading_fails_on_missing_or_invalid_files() {
+        let pki = Pki::new();
+        assert!(matches!(
+            pki.server_files(false).load(),
+            Err(TlsError::File { .. })
+        ));
+
+        pki.issue("server", ExtendedKeyUsagePurpose::ServerAuth);
+        pki.write("server.key", "not a key");
+        assert!(pki.server_files(false).load().is_err());
+    }
+}
@@ -0,0 +1,549 @@
+//! Client-side load balancing across `movie-server` replicas.
+//!
+//! Every replica gets its own lazily connected channel, and each call goes
+//! to the less loaded of two randomly picked replicas. A background task
+//! checks every replica with the gRPC health RPC and takes those that fail
+//! out of rotation until they pass again. With DNS discovery it also
+//! re-resolves the configured hosts, so replicas can come and go without
+//! restarting the gateway. While no replica is available, calls fail with
+//! `UNAVAILABLE` after the connect timeout rather than waiting for one.
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use prometheus_client::metrics::counter::Counter;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, Interval, MissedTickBehavior, Sleep};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::body::Body;
+use tonic::codegen::http;
+use tonic::transport::{Channel, Uri};
+use tonic::Status;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::{health_client::HealthClient, HealthCheckRequest};
+use tower::balance::p2c::Balance;
+use tower::buffer::{future::ResponseFuture, Buffer};
+use tower::discover::Change;
+use tower::load::{CompleteOnResponse, PendingRequests};
+use tower::util::BoxService;
+use tower::{Service, ServiceExt};
+
+use crate::config::GatewayConfig;
+use crate::gateway::Metrics;
+use crate::movie::movie_service_server::SERVICE_NAME;
+use crate::tls::ClientTls;
+
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_DNS_REFRESH: Duration = Duration::from_secs(30);
+
+/// Calls that may queue for the balancer before callers have to wait, the
+/// same as tonic's own channels allow.
+const BUFFER_CAPACITY: usize = 1024;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type BoxFuture = Pin<Box<dyn Future<Output = Result<http::Response<Body>, BoxError>> + Send>>;
+type Replica = PendingRequests<Backend>;
+type Changes = mpsc::UnboundedSender<Result<Change<String, Replica>, Infallible>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discovery {
+    /// Every upstream URL is one replica, dialled by name.
+    Static,
+    /// Every address an upstream host resolves to is one replica; hosts are
+    /// looked up again every `refresh`.
+    Dns { refresh: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceConfig {
+    pub discovery: Discovery,
+    /// How often each replica is health checked; a check that takes longer
+    /// counts as failed.
+    pub health_check_interval: Duration,
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        Self {
+            discovery: Discovery::Static,
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+        }
+    }
+}
+
+/// The gRPC server as seen by the gateway: a cheaply cloneable service that
+/// can be used wherever a tonic `Channel` can.
+#[derive(Clone)]
+pub struct Upstream {
+    inner: Buffer<http::Request<Body>, BoxFuture>,
+}
+
+impl fmt::Debug for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upstream").finish_non_exhaustive()
+    }
+}
+
+impl Upstream {
+    /// Balances calls across the replicas behind `config.upstreams` and
+    /// starts discovering and health checking them in the background.
+    /// Nothing is dialled until the first call or health check, so this
+    /// succeeds while the servers are down, or with DNS discovery before
+    /// their names resolve.
+    pub async fn start(config: &GatewayConfig, metrics: Metrics) -> Result<Self, BoxError> {
+        let (changes, discovered) = mpsc::unbounded_channel();
+        let upstream = Self::new(ReadyWithin::new(
+            Balance::new(UnboundedReceiverStream::new(discovered)),
+            config.resilience.connect_timeout,
+        ));
+
+        let mut pool = Pool {
+            targets: config.upstreams.clone(),
+            tls: config.tls.as_ref().map(ClientTls::load).transpose()?,
+            connect_timeout: config.resilience.connect_timeout,
+            config: config.balance,
+            metrics,
+            changes,
+            members: BTreeMap::new(),
+        };
+        // The balancer holds the receiving end for as long as `upstream`
+        // lives, so this cannot fail yet.
+        let _ = pool.refresh().await;
+        if pool.members.is_empty() {
+            tracing::warn!("No upstream replica found yet, calls fail until one is");
+        }
+        tokio::spawn(pool.run());
+
+        Ok(upstream)
+    }
+
+    fn new<S>(service: S) -> Self
+    where
+        S: Service<http::Request<Body>, Response = http::Response<Body>> + Send + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        let service = BoxService::new(service.map_err(Into::<BoxError>::into));
+        Self {
+            inner: Buffer::new(service, BUFFER_CAPACITY),
+        }
+    }
+}
+
+/// A single server, without balancing or health checks.
+impl From<Channel> for Upstream {
+    fn from(channel: Channel) -> Self {
+        Self::new(channel)
+    }
+}
+
+impl Service<http::Request<Body>> for Upstream {
+    type Response = http::Response<Body>;
+    type Error = BoxError;
+    type Future = ResponseFuture<BoxFuture>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        self.inner.call(request)
+    }
+}
+
+/// Fails calls with `UNAVAILABLE` once `inner` has not been ready for
+/// `wait`, and keeps doing so until it is ready again. The balancer is not
+/// ready while it has no replicas, and calls without a deadline, such as
+/// `WatchMovies`, would otherwise wait for one forever.
+struct ReadyWithin<S> {
+    inner: S,
+    wait: Duration,
+    waiting: Option<Pin<Box<Sleep>>>,
+    fail_next: bool,
+}
+
+impl<S> ReadyWithin<S> {
+    fn new(inner: S, wait: Duration) -> Self {
+        Self {
+            inner,
+            wait,
+            waiting: None,
+            fail_next: false,
+        }
+    }
+}
+
+impl<S> Service<http::Request<Body>> for ReadyWithin<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Poll::Ready(result) = self.inner.poll_ready(cx) {
+            self.waiting = None;
+            self.fail_next = false;
+            return Poll::Ready(result.map_err(Into::into));
+        }
+
+        let wait = self.wait;
+        let waiting = self
+            .waiting
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(wait)));
+        if waiting.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        self.fail_next = true;
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        if std::mem::take(&mut self.fail_next) {
+            let status = Status::unavailable("No upstream replica is available");
+            return Box::pin(std::future::ready(Err(status.into())));
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move { response.await.map_err(Into::into) })
+    }
+}
+
+/// One replica's channel, counting the calls the balancer sends to it.
+#[derive(Clone)]
+struct Backend {
+    channel: Channel,
+    requests: Counter,
+}
+
+impl Service<http::Request<Body>> for Backend {
+    type Response = http::Response<Body>;
+    type Error = <Channel as Service<http::Request<Body>>>::Error;
+    type Future = <Channel as Service<http::Request<Body>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.channel.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        self.requests.inc();
+        self.channel.call(request)
+    }
+}
+
+/// Where a replica came from and, with DNS discovery, the address it
+/// resolved to.
+#[derive(Debug, Clone)]
+struct Address {
+    target: Uri,
+    resolved: Option<SocketAddr>,
+}
+
+#[derive(Debug)]
+struct Member {
+    address: Address,
+    channel: Channel,
+    in_rotation: bool,
+}
+
+/// The replicas the balancer knows about, keyed by their metrics label.
+struct Pool {
+    targets: Vec<Uri>,
+    tls: Option<ClientTls>,
+    connect_timeout: Duration,
+    config: BalanceConfig,
+    metrics: Metrics,
+    changes: Changes,
+    members: BTreeMap<String, Member>,
+}
+
+/// Every `Upstream` handle, and with them the balancer, has been dropped.
+struct Closed;
+
+impl Pool {
+    async fn run(mut self) {
+        let mut checks = tokio::time::interval(self.config.health_check_interval);
+        checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut lookups = match self.config.discovery {
+            Discovery::Static => None,
+            Discovery::Dns { refresh } => {
+                Some(tokio::time::interval_at(Instant::now() + refresh, refresh))
+            }
+        };
+
+        loop {
+            let result = tokio::select! {
+                _ = checks.tick() => self.check().await,
+                _ = tick(&mut lookups) => match self.refresh().await {
+                    // Check new replicas straight away rather than waiting
+                    // for the next round.
+                    Ok(()) => self.check().await,
+                    Err(closed) => Err(closed),
+                },
+            };
+            if result.is_err() || self.changes.is_closed() {
+                return;
+            }
+        }
+    }
+
+    /// Works out the current replicas and brings the balancer in line.
+    /// Replicas of a host that cannot be resolved are kept until it can.
+    async fn refresh(&mut self) -> Result<(), Closed> {
+        let mut found = BTreeMap::new();
+        for target in &self.targets {
+            if self.config.discovery == Discovery::Static {
+                let key = target
+                    .authority()
+                    .map_or_else(|| target.to_string(), ToString::to_string);
+                let address = Address {
+                    target: target.clone(),
+                    resolved: None,
+                };
+                found.insert(key, address);
+                continue;
+            }
+
+            match lookup(target).await {
+                Ok(addrs) => {
+                    for addr in addrs {
+                        let address = Address {
+                            target: target.clone(),
+                            resolved: Some(addr),
+                        };
+                        found.insert(addr.to_string(), address);
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to resolve upstream {}: {}", target, e);
+                    found.extend(
+                        self.members
+                            .iter()
+                            .filter(|(_, member)| member.address.target == *target)
+                            .map(|(key, member)| (key.clone(), member.address.clone())),
+                    );
+                }
+            }
+        }
+
+        let gone: Vec<String> = self
+            .members
+            .keys()
+            .filter(|key| !found.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in gone {
+            if self
+                .members
+                .remove(&key)
+                .is_some_and(|member| member.in_rotation)
+            {
+                send(&self.changes, Change::Remove(key.clone()))?;
+            }
+            self.metrics.remove_backend(&key);
+            tracing::info!("Removed upstream replica {}", key);
+        }
+
+        for (key, address) in found {
+            if self.members.contains_key(&key) {
+                continue;
+            }
+            let channel = match self.connect(&address) {
+                Ok(channel) => channel,
+                Err(e) => {
+                    tracing::warn!("Cannot create a channel to {}: {}", key, e);
+                    continue;
+                }
+            };
+            let replica = replica(&key, channel.clone(), &self.metrics);
+            send(&self.changes, Change::Insert(key.clone(), replica))?;
+            tracing::info!("Added upstream replica {}", key);
+            self.members.insert(
+                key,
+                Member {
+                    address,
+                    channel,
+                    in_rotation: true,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn connect(&self, address: &Address) -> Result<Channel, BoxError> {
+        let target = &address.target;
+        let dial = match address.resolved {
+            Some(addr) => {
+                format!("{}://{}", target.scheme_str().unwrap_or("http"), addr).parse()?
+            }
+            None => target.clone(),
+        };
+        match &self.tls {
+            Some(tls) => tls.channel(&dial, host(target), self.connect_timeout),
+            None => Ok(Channel::builder(dial)
+                .connect_timeout(self.connect_timeout)
+                .connect_lazy()),
+        }
+    }
+
+    /// Health checks every replica at once, ejecting those that fail and
+    /// restoring those that pass again.
+    async fn check(&mut self) -> Result<(), Closed> {
+        let timeout = self.config.health_check_interval;
+        let mut checks = JoinSet::new();
+        for (key, member) in &self.members {
+            let key = key.clone();
+            let mut client = HealthClient::new(member.channel.clone());
+            checks.spawn(async move {
+                let request = HealthCheckRequest {
+                    service: SERVICE_NAME.to_string(),
+                };
+                let serving = match tokio::time::timeout(timeout, client.check(request)).await {
+                    Ok(Ok(response)) => response.into_inner().status() == ServingStatus::Serving,
+                    _ => false,
+                };
+                (key, serving)
+            });
+        }
+
+        let mut serving = BTreeMap::new();
+        while let Some(result) = checks.join_next().await {
+            if let Ok((key, ok)) = result {
+                serving.insert(key, ok);
+            }
+        }
+
+        // When every replica fails, keep them all in rotation so calls
+        // still reach a server and fail with its error rather than waiting
+        // for one to come back.
+        let any_serving = serving.values().any(|ok| *ok);
+        for (key, member) in &mut self.members {
+            let ok = serving.get(key).copied().unwrap_or(false);
+            self.metrics.set_backend_healthy(key, ok);
+
+            let wanted = ok || !any_serving;
+            if wanted == member.in_rotation {
+                continue;
+            }
+            let change = if wanted {
+                tracing::info!(
+                    "Upstream replica {} is healthy, returning it to rotation",
+                    key
+                );
+                Change::Insert(
+                    key.clone(),
+                    replica(key, member.channel.clone(), &self.metrics),
+                )
+            } else {
+                tracing::warn!(
+                    "Upstream replica {} failed its health check, ejecting it",
+                    key
+                );
+                Change::Remove(key.clone())
+            };
+            send(&self.changes, change)?;
+            member.in_rotation = wanted;
+        }
+
+        Ok(())
+    }
+}
+
+fn replica(key: &str, channel: Channel, metrics: &Metrics) -> Replica {
+    let backend = Backend {
+        channel,
+        requests: metrics.backend_requests(key),
+    };
+    PendingRequests::new(backend, CompleteOnResponse::default())
+}
+
+fn send(changes: &Changes, change: Change<String, Replica>) -> Result<(), Closed> {
+    changes.send(Ok(change)).map_err(|_| Closed)
+}
+
+/// Ticks `interval`, or never when there is none.
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The host of `uri` as a name or bare IP address; IPv6 hosts keep their
+/// brackets in URLs.
+fn host(uri: &Uri) -> &str {
+    uri.host()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+}
+
+async fn lookup(target: &Uri) -> std::io::Result<Vec<SocketAddr>> {
+    let default_port = match target.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    };
+    let port = target.port_u16().unwrap_or(default_port);
+    Ok(tokio::net::lookup_host((host(target), port))
+        .await?
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+    use crate::movie::movie_service_client::MovieServiceClient;
+    use crate::movie::{ReadMovieRequest, WatchMoviesRequest};
+
+    /// A balancer that has not discovered any replica, as with DNS discovery
+    /// whose first lookup failed.
+    fn without_replicas(wait: Duration) -> (Changes, MovieServiceClient<Upstream>) {
+        let (changes, discovered) = mpsc::unbounded_channel();
+        let upstream = Upstream::new(ReadyWithin::new(
+            Balance::new(UnboundedReceiverStream::new(discovered)),
+            wait,
+        ));
+        (changes, MovieServiceClient::new(upstream))
+    }
+
+    #[tokio::test]
+    async fn calls_fail_fast_without_replicas() {
+        let (_changes, mut client) = without_replicas(Duration::from_millis(20));
+
+        // Streaming calls have no deadline of their own.
+        let watch = client.watch_movies(WatchMoviesRequest::default());
+        let status = tokio::time::timeout(Duration::from_secs(5), watch)
+            .await
+            .expect("call waited for a replica")
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+
+        // Later calls keep failing rather than queueing.
+        for _ in 0..3 {
+            let get = client.get_movie(ReadMovieRequest {
+                id: "a".to_string(),
+            });
+            let status = tokio::time::timeout(Duration::from_secs(5), get)
+                .await
+                .expect("call waited for a replica")
+                .unwrap_err();
+            assert_eq!(status.code(), Code::Unavailable);
+        }
+    }
+}
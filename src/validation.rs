@@ -0,0 +1,412 @@
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
+
+use crate::movie::Movie;
+
+pub const MAX_ID_LEN: usize = 64;
+pub const MAX_TITLE_LEN: usize = 200;
+pub const MAX_NAME_LEN: usize = 200;
+pub const MAX_SYNOPSIS_LEN: usize = 4000;
+pub const MAX_GENRES: usize = 10;
+pub const MAX_DIRECTORS: usize = 20;
+pub const MAX_CAST: usize = 200;
+pub const MAX_BATCH_SIZE: usize = 100;
+
+pub const EARLIEST_RELEASE_YEAR: i32 = 1888;
+pub const LATEST_RELEASE_YEAR: i32 = 2100;
+pub const MAX_RUNTIME_MINUTES: i32 = 24 * 60;
+
+pub const CERTIFICATIONS: [&str; 6] = ["G", "PG", "PG-13", "R", "NC-17", "NR"];
+
+pub const GENRES: [&str; 21] = [
+    "Action",
+    "Adventure",
+    "Animation",
+    "Biography",
+    "Comedy",
+    "Crime",
+    "Documentary",
+    "Drama",
+    "Family",
+    "Fantasy",
+    "History",
+    "Horror",
+    "Music",
+    "Musical",
+    "Mystery",
+    "Romance",
+    "Sci-Fi",
+    "Sport",
+    "Thriller",
+    "War",
+    "Western",
+];
+
+/// Field violations collected while checking a message, reported together so
+/// a client can fix every problem in one round trip.
+#[derive(Debug, Default)]
+struct Violations(Vec<FieldViolation>);
+
+impl Violations {
+    fn add(&mut self, field: impl Into<String>, description: impl Into<String>) {
+        self.0.push(FieldViolation::new(field, description));
+    }
+
+    fn check_len(&mut self, field: impl Into<String>, value: &str, max: usize) {
+        if value.chars().count() > max {
+            self.add(field, format!("must be at most {} characters", max));
+        }
+    }
+
+    fn check_id(&mut self, field: &str, id: &str) {
+        if id.is_empty() {
+            self.add(field, "must not be empty");
+        } else if id.len() > MAX_ID_LEN {
+            self.add(field, format!("must be at most {} characters", MAX_ID_LEN));
+        } else if !id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            self.add(field, "may only contain ASCII letters, digits, '-' and '_'");
+        }
+    }
+
+    /// `INVALID_ARGUMENT` carrying a `google.rpc.BadRequest` with every
+    /// violation, or `Ok` when there are none.
+    fn into_result(self) -> Result<(), Status> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        let message = self
+            .0
+            .iter()
+            .map(|violation| format!("{} {}", violation.field, violation.description))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(Status::with_error_details(
+            Code::InvalidArgument,
+            format!("Invalid argument: {}", message),
+            ErrorDetails::with_bad_request(self.0),
+        ))
+    }
+}
+
+/// Checks a movie id as sent by a client.
+pub fn validate_id(id: &str) -> Result<(), Status> {
+    let mut violations = Violations::default();
+    violations.check_id("id", id);
+    violations.into_result()
+}
+
+/// Checks the number of items in a batch request's repeated `field`.
+pub fn validate_batch_size(field: &str, len: usize) -> Result<(), Status> {
+    let mut violations = Violations::default();
+    if len > MAX_BATCH_SIZE {
+        violations.add(
+            field,
+            format!("must have at most {} entries", MAX_BATCH_SIZE),
+        );
+    }
+    violations.into_result()
+}
+
+/// Canonicalizes `movie` in place and checks every client-settable field.
+/// Titles and names are trimmed, genres, certifications and languages are
+/// normalized to their canonical spelling, and `genre` is kept equal to
+/// `genres[0]` whichever of the two the client sent. Field paths in the
+/// returned violations are relative to the `Movie`.
+pub fn normalize_movie(movie: &mut Movie) -> Result<(), Status> {
+    let mut violations = Violations::default();
+
+    violations.check_id("id", &movie.id);
+
+    movie.title = movie.title.trim().to_string();
+    if movie.title.is_empty() {
+        violations.add("title", "must not be empty");
+    }
+    violations.check_len("title", &movie.title, MAX_TITLE_LEN);
+
+    if movie.genres.is_empty() && !movie.genre.trim().is_empty() {
+        movie.genres.push(movie.genre.clone());
+    }
+    if movie.genres.len() > MAX_GENRES {
+        violations.add(
+            "genres",
+            format!("must have at most {} entries", MAX_GENRES),
+        );
+    }
+    for (i, genre) in movie.genres.iter_mut().enumerate() {
+        match GENRES
+            .iter()
+            .find(|allowed| allowed.eq_ignore_ascii_case(genre.trim()))
+        {
+            Some(allowed) => *genre = allowed.to_string(),
+            None => violations.add(
+                format!("genres[{}]", i),
+                format!("must be one of {}", GENRES.join(", ")),
+            ),
+        }
+    }
+    movie.genre = movie.genres.first().cloned().unwrap_or_default();
+
+    if movie.release_year != 0
+        && !(EARLIEST_RELEASE_YEAR..=LATEST_RELEASE_YEAR).contains(&movie.release_year)
+    {
+        violations.add(
+            "release_year",
+            format!(
+                "must be between {} and {}",
+                EARLIEST_RELEASE_YEAR, LATEST_RELEASE_YEAR
+            ),
+        );
+    }
+    if !(0..=MAX_RUNTIME_MINUTES).contains(&movie.runtime_minutes) {
+        violations.add(
+            "runtime_minutes",
+            format!("must be between 0 and {}", MAX_RUNTIME_MINUTES),
+        );
+    }
+
+    if movie.directors.len() > MAX_DIRECTORS {
+        violations.add(
+            "directors",
+            format!("must have at most {} entries", MAX_DIRECTORS),
+        );
+    }
+    for (i, director) in movie.directors.iter_mut().enumerate() {
+        *director = director.trim().to_string();
+        if director.is_empty() {
+            violations.add(format!("directors[{}]", i), "must not be empty");
+        }
+        violations.check_len(format!("directors[{}]", i), director, MAX_NAME_LEN);
+    }
+
+    if movie.cast.len() > MAX_CAST {
+        violations.add("cast", format!("must have at most {} entries", MAX_CAST));
+    }
+    for (i, member) in movie.cast.iter_mut().enumerate() {
+        member.name = member.name.trim().to_string();
+        member.character = member.character.trim().to_string();
+        if member.name.is_empty() {
+            violations.add(format!("cast[{}].name", i), "must not be empty");
+        }
+        violations.check_len(format!("cast[{}].name", i), &member.name, MAX_NAME_LEN);
+        violations.check_len(
+            format!("cast[{}].character", i),
+            &member.character,
+            MAX_NAME_LEN,
+        );
+    }
+
+    movie.certification = movie.certification.trim().to_ascii_uppercase();
+    if !movie.certification.is_empty() && !CERTIFICATIONS.contains(&movie.certification.as_str()) {
+        violations.add(
+            "certification",
+            format!("must be one of {}", CERTIFICATIONS.join(", ")),
+        );
+    }
+
+    violations.check_len("synopsis", &movie.synopsis, MAX_SYNOPSIS_LEN);
+
+    movie.language = movie.language.trim().to_ascii_lowercase();
+    let iso_639_1 =
+        movie.language.len() == 2 && movie.language.bytes().all(|b| b.is_ascii_lowercase());
+    if !movie.language.is_empty() && !iso_639_1 {
+        violations.add("language", "must be an ISO 639-1 code");
+    }
+
+    violations.into_result()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movie::CastMember;
+
+    fn valid() -> Movie {
+        Movie {
+            id: "heat-1995".to_string(),
+            title: "Heat".to_string(),
+            genres: vec!["Crime".to_string()],
+            release_year: 1995,
+            runtime_minutes: 170,
+            directors: vec!["Michael Mann".to_string()],
+            cast: vec![CastMember {
+                name: "Al Pacino".to_string(),
+                character: "Vincent Hanna".to_string(),
+            }],
+            certification: "R".to_string(),
+            language: "en".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The fields named in the status's `BadRequest`, in order.
+    fn violated_fields(status: &Status) -> Vec<String> {
+        assert_eq!(status.code(), Code::InvalidArgument);
+        status
+            .get_details_bad_request()
+            .expect("status carries no BadRequest")
+            .field_violations
+            .into_iter()
+            .map(|violation| violation.field)
+            .collect()
+    }
+
+    #[test]
+    fn accepts_a_valid_movie() {
+        let mut movie = valid();
+
+        normalize_movie(&mut movie).unwrap();
+
+        assert_eq!(movie.genre, "Crime");
+    }
+
+    #[test]
+    fn canonicalizes_fields() {
+        let mut movie = Movie {
+            title: "  Heat \n".to_string(),
+            genre: String::new(),
+            genres: vec![" sci-fi".to_string(), "DRAMA".to_string()],
+            directors: vec![" Michael Mann ".to_string()],
+            certification: " pg-13 ".to_string(),
+            language: " EN".to_string(),
+            ..valid()
+        };
+
+        normalize_movie(&mut movie).unwrap();
+
+        assert_eq!(movie.title, "Heat");
+        assert_eq!(movie.genres, ["Sci-Fi", "Drama"]);
+        assert_eq!(movie.genre, "Sci-Fi");
+        assert_eq!(movie.directors, ["Michael Mann"]);
+        assert_eq!(movie.certification, "PG-13");
+        assert_eq!(movie.language, "en");
+    }
+
+    #[test]
+    fn genre_alone_becomes_the_genres() {
+        let mut movie = Movie {
+            genre: "thriller".to_string(),
+            genres: Vec::new(),
+            ..valid()
+        };
+
+        normalize_movie(&mut movie).unwrap();
+
+        assert_eq!(movie.genres, ["Thriller"]);
+        assert_eq!(movie.genre, "Thriller");
+    }
+
+    #[test]
+    fn reports_every_violation_at_once() {
+        let mut movie = Movie {
+            id: "heat 1995".to_string(),
+            title: "   ".to_string(),
+            genres: vec!["Crime".to_string(), "Noir".to_string()],
+            release_year: 1700,
+            runtime_minutes: -1,
+            directors: vec![" ".to_string()],
+            cast: vec![CastMember {
+                name: String::new(),
+                character: "Vincent Hanna".to_string(),
+            }],
+            certification: "X".to_string(),
+            language: "eng".to_string(),
+            ..valid()
+        };
+
+        let status = normalize_movie(&mut movie).unwrap_err();
+
+        assert_eq!(
+            violated_fields(&status),
+            [
+                "id",
+                "title",
+                "genres[1]",
+                "release_year",
+                "runtime_minutes",
+                "directors[0]",
+                "cast[0].name",
+                "certification",
+                "language",
+            ]
+        );
+        // The message lists them too, for clients that ignore details.
+        assert!(status.message().contains("genres[1] must be one of"));
+        assert!(status.message().contains("title must not be empty"));
+    }
+
+    #[test]
+    fn limits_lengths_in_characters() {
+        let mut movie = Movie {
+            title: "é".repeat(MAX_TITLE_LEN),
+            ..valid()
+        };
+        normalize_movie(&mut movie).unwrap();
+
+        let mut movie = Movie {
+            title: "é".repeat(MAX_TITLE_LEN + 1),
+            synopsis: "a".repeat(MAX_SYNOPSIS_LEN + 1),
+            cast: vec![CastMember {
+                name: "Al Pacino".to_string(),
+                character: "a".repeat(MAX_NAME_LEN + 1),
+            }],
+            ..valid()
+        };
+        let status = normalize_movie(&mut movie).unwrap_err();
+
+        assert_eq!(
+            violated_fields(&status),
+            ["title", "cast[0].character", "synopsis"]
+        );
+    }
+
+    #[test]
+    fn limits_repeated_fields() {
+        let mut movie = Movie {
+            genres: vec!["Drama".to_string(); MAX_GENRES + 1],
+            directors: vec!["Michael Mann".to_string(); MAX_DIRECTORS + 1],
+            ..valid()
+        };
+
+        let status = normalize_movie(&mut movie).unwrap_err();
+
+        assert_eq!(violated_fields(&status), ["genres", "directors"]);
+    }
+
+    #[test]
+    fn unset_optional_fields_are_valid() {
+        let mut movie = Movie {
+            id: "heat".to_string(),
+            title: "Heat".to_string(),
+            ..Default::default()
+        };
+
+        normalize_movie(&mut movie).unwrap();
+    }
+
+    #[test]
+    fn checks_ids() {
+        validate_id("heat_1995-a").unwrap();
+
+        for id in [
+            String::new(),
+            "a".repeat(MAX_ID_LEN + 1),
+            "héat".to_string(),
+            "../heat".to_string(),
+        ] {
+            let status = validate_id(&id).unwrap_err();
+            assert_eq!(violated_fields(&status), ["id"], "id {:?}", id);
+        }
+    }
+
+    #[test]
+    fn checks_batch_sizes() {
+        validate_batch_size("movies", MAX_BATCH_SIZE).unwrap();
+
+        let status = validate_batch_size("movies", MAX_BATCH_SIZE + 1).unwrap_err();
+        assert_eq!(violated_fields(&status), ["movies"]);
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::Status;
+
+use crate::movie::{Movie, MovieEvent, MovieEventType};
+
+pub const DEFAULT_HISTORY: usize = 1024;
+
+/// Where a watcher left off. Revisions restart with every process, so they
+/// are only comparable within the epoch that assigned them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub epoch: u64,
+    pub revision: u64,
+}
+
+impl Position {
+    pub fn of(event: &MovieEvent) -> Self {
+        Self {
+            epoch: event.epoch,
+            revision: event.revision,
+        }
+    }
+}
+
+/// Formats as `<epoch>-<revision>`, e.g. for an SSE event id.
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.epoch, self.revision)
+    }
+}
+
+impl FromStr for Position {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, revision) = s.split_once('-').ok_or(())?;
+        Ok(Self {
+            epoch: epoch.parse().map_err(|_| ())?,
+            revision: revision.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// Ordered feed of movie changes with a bounded replay history.
+#[derive(Debug)]
+pub struct ChangeFeed {
+    /// Random per process, so positions from before a restart are refused
+    /// rather than silently matched against the restarted revisions.
+    epoch: u64,
+    state: Mutex<FeedState>,
+    sender: broadcast::Sender<MovieEvent>,
+    shutdown: CancellationToken,
+}
+
+#[derive(Debug)]
+struct FeedState {
+    revision: u64,
+    history: VecDeque<MovieEvent>,
+    capacity: usize,
+}
+
+/// Events to replay followed by a receiver for live events. The receiver
+/// was subscribed atomically with the backlog snapshot, so there are no gaps
+/// or duplicates between the two.
+pub struct Subscription {
+    /// Revision the subscriber has seen up to before the backlog.
+    pub revision: u64,
+    pub backlog: Vec<MovieEvent>,
+    pub receiver: broadcast::Receiver<MovieEvent>,
+}
+
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        // The live channel is kept well below the history, so a watcher that
+        // lags behind it can still catch up from the history.
+        let (sender, _) = broadcast::channel((capacity / 4).max(1));
+        Self {
+            epoch: uuid::Uuid::new_v4().as_u64_pair().0.max(1),
+            state: Mutex::new(FeedState {
+                revision: 0,
+                history: VecDeque::with_capacity(capacity),
+                capacity,
+            }),
+            sender,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Ends every watch stream once `shutdown` is cancelled, so open watches
+    /// do not hold a draining server open.
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.lock().revision
+    }
+
+    /// Assigns the next revision to a change and fans it out to watchers.
+    pub fn publish(&self, event_type: MovieEventType, movie: Movie) -> MovieEvent {
+        let mut state = self.lock();
+        state.revision += 1;
+
+        let event = MovieEvent {
+            revision: state.revision,
+            r#type: event_type as i32,
+            movie: Some(movie),
+            epoch: self.epoch,
+        };
+
+        if state.history.len() == state.capacity {
+            state.history.pop_front();
+        }
+        state.history.push_back(event.clone());
+
+        // Sending while the lock is held keeps delivery order identical to
+        // revision order. An error only means nobody is watching.
+        let _ = self.sender.send(event.clone());
+        event
+    }
+
+    /// Subscribes to events after `after_revision`, or to new events only
+    /// when it is `None`.
+    pub fn subscribe(&self, after_revision: Option<u64>) -> Result<Subscription, Status> {
+        let state = self.lock();
+        let receiver = self.sender.subscribe();
+
+        let Some(after) = after_revision else {
+            return Ok(Subscription {
+                revision: state.revision,
+                backlog: Vec::new(),
+                receiver,
+            });
+        };
+
+        if after > state.revision {
+            return Err(Status::out_of_range(format!(
+                "Revision {} is ahead of the current revision {}",
+                after, state.revision
+            )));
+        }
+
+        let oldest = state
+            .history
+            .front()
+            .map(|event| event.revision)
+            .unwrap_or(state.revision + 1);
+        if after + 1 < oldest {
+            return Err(Status::out_of_range(format!(
+                "Revision {} has been compacted; the oldest retained revision is {}",
+                after, oldest
+            )));
+        }
+
+        let backlog = state
+            .history
+            .iter()
+            .filter(|event| event.revision > after)
+            .cloned()
+            .collect();
+
+        Ok(Subscription {
+            revision: after,
+            backlog,
+            receiver,
+        })
+    }
+
+    /// Streams events after `after`, or new events only when it is `None`,
+    /// until the receiving side is dropped or the feed shuts down. A
+    /// position from another epoch is refused like a compacted one. A
+    /// watcher that falls behind the live channel is transparently
+    /// resubscribed from its last revision while that is still retained.
+    pub fn watch(
+        self: &Arc<Self>,
+        after: Option<Position>,
+    ) -> Result<ReceiverStream<Result<MovieEvent, Status>>, Status> {
+        if let Some(after) = after {
+            if after.epoch != self.epoch {
+                return Err(Status::out_of_range(format!(
+                    "Position {} is from another server run (epoch {}); resync from the current state",
+                    after, self.epoch
+                )));
+            }
+        }
+        let subscription = self.subscribe(after.map(|after| after.revision))?;
+        let (tx, rx) = mpsc::channel(128);
+        let shutdown = self.shutdown.clone();
+        let forward = self.clone().forward(subscription, tx);
+        tokio::spawn(async move { shutdown.run_until_cancelled(forward).await });
+        Ok(ReceiverStream::new(rx))
+    }
+
+    async fn forward(
+        self: Arc<Self>,
+        mut subscription: Subscription,
+        tx: mpsc::Sender<Result<MovieEvent, Status>>,
+    ) {
+        let mut revision = subscription.revision;
+
+        loop {
+            for event in std::mem::take(&mut subscription.backlog) {
+                revision = event.revision;
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                let received = tokio::select! {
+                    received = subscription.receiver.recv() => received,
+                    _ = tx.closed() => return,
+                };
+
+                match received {
+                    Ok(event) if event.revision <= revision => continue,
+                    Ok(event) => {
+                        revision = event.revision;
+                        if tx.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        match self.subscribe(Some(revision)) {
+                            Ok(resubscribed) => {
+                                subscription = resubscribed;
+                                break;
+                            }
+                            Err(status) => {
+                                let _ = tx.send(Err(status)).await;
+                                return;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FeedState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+    use tonic::Code;
+
+    use super::*;
+
+    fn publish(feed: &ChangeFeed, count: usize) {
+        for i in 0..count {
+            feed.publish(
+                MovieEventType::Created,
+                Movie {
+                    id: i.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    fn at(feed: &ChangeFeed, revision: u64) -> Option<Position> {
+        Some(Position {
+            epoch: feed.epoch(),
+            revision,
+        })
+    }
+
+    async fn revisions(
+        stream: &mut ReceiverStream<Result<MovieEvent, Status>>,
+        count: usize,
+    ) -> Vec<u64> {
+        let mut revisions = Vec::new();
+        for _ in 0..count {
+            revisions.push(stream.next().await.unwrap().unwrap().revision);
+        }
+        revisions
+    }
+
+    #[tokio::test]
+    async fn resumes_after_a_retained_revision() {
+        let feed = Arc::new(ChangeFeed::new(16));
+        publish(&feed, 3);
+
+        let mut stream = feed.watch(at(&feed, 1)).unwrap();
+        assert_eq!(revisions(&mut stream, 2).await, [2, 3]);
+
+        publish(&feed, 1);
+        assert_eq!(revisions(&mut stream, 1).await, [4]);
+    }
+
+    #[tokio::test]
+    async fn new_watchers_only_see_new_events() {
+        let feed = Arc::new(ChangeFeed::new(16));
+        publish(&feed, 3);
+
+        let mut stream = feed.watch(None).unwrap();
+        publish(&feed, 1);
+        assert_eq!(revisions(&mut stream, 1).await, [4]);
+    }
+
+    #[tokio::test]
+    async fn refuses_compacted_future_and_foreign_positions() {
+        let feed = Arc::new(ChangeFeed::new(4));
+        publish(&feed, 10);
+
+        let compacted = feed.watch(at(&feed, 2)).unwrap_err();
+        assert_eq!(compacted.code(), Code::OutOfRange);
+        // The oldest retained event can still be resumed from just before.
+        assert!(feed.watch(at(&feed, 6)).is_ok());
+
+        let ahead = feed.watch(at(&feed, 11)).unwrap_err();
+        assert_eq!(ahead.code(), Code::OutOfRange);
+
+        let restarted = ChangeFeed::new(4);
+        let foreign = feed
+            .watch(Some(Position {
+                epoch: restarted.epoch(),
+                revision: 8,
+            }))
+            .unwrap_err();
+        assert_eq!(foreign.code(), Code::OutOfRange);
+        assert!(foreign.message().contains("resync"));
+    }
+
+    #[tokio::test]
+    async fn lagged_watchers_catch_up_from_history() {
+        // The live channel holds 4 events, the history 16.
+        let feed = Arc::new(ChangeFeed::new(16));
+        let mut stream = feed.watch(None).unwrap();
+
+        // Published before the forwarding task gets to run, so its receiver
+        // lags and it resubscribes from revision 0.
+        publish(&feed, 10);
+        assert_eq!(
+            revisions(&mut stream, 10).await,
+            (1..=10).collect::<Vec<_>>()
+        );
+
+        publish(&feed, 1);
+        assert_eq!(revisions(&mut stream, 1).await, [11]);
+    }
+
+    #[tokio::test]
+    async fn watchers_lagging_past_the_history_get_an_error() {
+        let feed = Arc::new(ChangeFeed::new(4));
+        let mut stream = feed.watch(None).unwrap();
+
+        publish(&feed, 10);
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::OutOfRange);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[test]
+    fn positions_round_trip_through_strings() {
+        let position = Position {
+            epoch: 42,
+            revision: 7,
+        };
+        assert_eq!(position.to_string(), "42-7");
+        assert_eq!("42-7".parse(), Ok(position));
+        assert!("7".parse::<Position>().is_err());
+        assert!("42-".parse::<Position>().is_err());
+    }
+}